@@ -2,7 +2,7 @@ use crate::media::ManagerMessage;
 
 /// All valid messages which are sent between threads. Implimentations aren't
 /// provided in this module and must be made in the respective threads.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum ThreadMessage {
     /// Stop the current thread
     Stop,
@@ -135,6 +135,7 @@ impl ThreadController {
     ///             ThreadMessage::Echo(msg) => {
     ///                 println!("message recieved: {}", msg)
     ///             }
+    ///             _ => (),
     ///         }
     ///     }
     /// });
@@ -268,14 +269,14 @@ impl ThreadController {
     pub fn send_all(&self, message: ThreadMessage) {
         for thread in &self.threads {
             if !thread.is_finished() {
-                thread.send_message(message);
+                thread.send_message(message.clone());
             }
         }
     }
 
     /// Start the controller's message manager / managing threads
     pub fn begin(self) {
-        println!("Started Thread Controller");
+        eprintln!("Started Thread Controller");
         loop {
             let msg = self.rx.recv().unwrap();
 
@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    path::Path,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::ThreadMessage,
+    media::{EventRecord, ManagerMessage, MediaError, MediaEvent, MediaState, PlaybackStatus},
+};
+
+/// What a hook runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookTrigger {
+    /// Another track is playing
+    TrackChanged,
+    /// The playback status changed in any way
+    StatusChanged,
+    /// The session started playing
+    PlaybackStarted,
+    /// The session was playing and is now paused or stopped
+    PlaybackStopped,
+    /// The position jumped
+    Seeked,
+    /// Another session is the current one now
+    SessionChanged,
+}
+
+/// A command to run on media events
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hook {
+    /// Events the command runs on
+    pub on: Vec<HookTrigger>,
+    /// Program and its arguments. Not run through a shell, use
+    /// `["sh", "-c", "..."]` for that.
+    pub command: Vec<String>,
+    /// How long the command may run before it is killed, overriding
+    /// [`HooksConfig::timeout_ms`]
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Commands to run on media events.
+///
+/// Commands get the state of the session as `WINDOW_*` environment variables,
+/// see [`state_env`], and the [`EventRecord`] of the event, with the state, as
+/// JSON on stdin. Hooks run one after another, never overlapping, and are
+/// killed once they run for longer than their timeout.
+///
+/// ```json
+/// {
+///   "timeout_ms": 5000,
+///   "hooks": [
+///     { "on": ["track_changed"], "command": ["notify-send", "Now playing"] },
+///     { "on": ["playback_started", "playback_stopped"],
+///       "command": ["sh", "-c", "echo $WINDOW_STATUS >> ~/playback.log"],
+///       "timeout_ms": 1000 }
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HooksConfig {
+    /// How long commands may run before they are killed, 10s by default
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// The commands
+    pub hooks: Vec<Hook>,
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        HooksConfig {
+            timeout_ms: default_timeout_ms(),
+            hooks: vec![],
+        }
+    }
+}
+
+impl HooksConfig {
+    /// Reads the configuration from a JSON file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, MediaError> {
+        let file = std::fs::File::open(path).map_err(|error| {
+            MediaError::Backend(format!("Could not open the hooks config: {}", error))
+        })?;
+        let config: Self =
+            serde_json::from_reader(std::io::BufReader::new(file)).map_err(|error| {
+                MediaError::Backend(format!("The hooks config is not valid: {}", error))
+            })?;
+
+        if config.hooks.iter().any(|hook| hook.command.is_empty()) {
+            return Err(MediaError::Backend(
+                "The hooks config is not valid: every hook needs a command".to_string(),
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// Runs the hooks for the messages a [`Manager`][crate::media::Manager]
+    /// publishes, until told to stop. Meant to be run as a thread of the
+    /// thread controller.
+    pub fn run(self, rx: crossbeam_channel::Receiver<ThreadMessage>) {
+        let (jobs_tx, jobs_rx) = crossbeam_channel::unbounded::<Job>();
+        let worker = std::thread::spawn(move || {
+            for job in jobs_rx {
+                job.run();
+            }
+        });
+
+        let mut triggers = Triggers::default();
+        loop {
+            match rx.recv() {
+                Ok(ThreadMessage::Stop) | Err(_) => break,
+                Ok(ThreadMessage::Media(msg)) => {
+                    for (trigger, record) in triggers.update(&msg) {
+                        for job in self.jobs(trigger, &record) {
+                            jobs_tx.send(job).ok();
+                        }
+                    }
+                }
+                Ok(_) => {}
+            }
+        }
+
+        // Finish the hooks which are queued already
+        drop(jobs_tx);
+        worker.join().ok();
+    }
+
+    fn jobs(&self, trigger: HookTrigger, record: &EventRecord) -> Vec<Job> {
+        let stdin = serde_json::to_string(record).unwrap_or_default();
+        let mut env = vec![("WINDOW_EVENT".to_string(), trigger_name(trigger))];
+        match &record.state {
+            Some(state) => env.extend(state_env(state)),
+            None => env.push((
+                "WINDOW_SESSION".to_string(),
+                record.session_id.clone().unwrap_or_default(),
+            )),
+        }
+
+        self.hooks
+            .iter()
+            .filter(|hook| hook.on.contains(&trigger))
+            .map(|hook| Job {
+                command: hook.command.clone(),
+                env: env.clone(),
+                stdin: stdin.clone(),
+                timeout: Duration::from_millis(hook.timeout_ms.unwrap_or(self.timeout_ms)),
+            })
+            .collect()
+    }
+}
+
+/// The state of a session as `WINDOW_*` environment variables: `WINDOW_SESSION`,
+/// `WINDOW_TITLE`, `WINDOW_ARTIST`, `WINDOW_ALBUM`, `WINDOW_ALBUM_ARTIST`,
+/// `WINDOW_TRACK_NUMBER`, `WINDOW_STATUS`, `WINDOW_POSITION_MS`,
+/// `WINDOW_DURATION_MS` (empty for live streams), `WINDOW_SHUFFLE`,
+/// `WINDOW_REPEAT` and `WINDOW_ART_HASH` (empty without art).
+pub fn state_env(state: &MediaState) -> Vec<(String, String)> {
+    let vars = [
+        ("SESSION", state.session_id.clone()),
+        ("TITLE", state.media.title.clone()),
+        ("ARTIST", state.media.artist.clone()),
+        ("ALBUM", state.media.album_title.clone()),
+        ("ALBUM_ARTIST", state.media.album_artist.clone()),
+        ("TRACK_NUMBER", state.media.track_number.to_string()),
+        ("STATUS", state.playback.playback_status.to_string()),
+        ("POSITION_MS", state.timeline.pos.as_millis().to_string()),
+        (
+            "DURATION_MS",
+            state
+                .duration_ms
+                .map(|duration| duration.to_string())
+                .unwrap_or_default(),
+        ),
+        ("SHUFFLE", state.playback.shuffle_active.to_string()),
+        ("REPEAT", state.playback.auto_repeat_mode.to_string()),
+        ("ART_HASH", state.art_hash.clone().unwrap_or_default()),
+    ];
+
+    vars.into_iter()
+        .map(|(name, value)| (format!("WINDOW_{}", name), value))
+        .collect()
+}
+
+fn trigger_name(trigger: HookTrigger) -> String {
+    serde_json::to_value(trigger)
+        .ok()
+        .and_then(|name| name.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Turns the messages of the manager into the triggers they fire, with the
+/// record handed to the hooks
+#[derive(Debug, Default)]
+struct Triggers {
+    states: HashMap<String, MediaState>,
+    /// Session which became the current one, waiting for its state
+    pending_session: Option<String>,
+}
+
+impl Triggers {
+    fn update(&mut self, msg: &ManagerMessage) -> Vec<(HookTrigger, EventRecord)> {
+        let now = SystemTime::now();
+        let record = |msg: &ManagerMessage, state: Option<&MediaState>| {
+            EventRecord::from_message(msg, now).map(|record| EventRecord {
+                state: state.cloned(),
+                ..record
+            })
+        };
+
+        match msg {
+            ManagerMessage::SessionChanged(Some(id)) => {
+                // The state of the session is published right after it
+                self.pending_session = Some(id.clone());
+                vec![]
+            }
+            ManagerMessage::SessionChanged(None) => {
+                self.pending_session = None;
+                record(msg, None)
+                    .map(|record| vec![(HookTrigger::SessionChanged, record)])
+                    .unwrap_or_default()
+            }
+            ManagerMessage::StateChanged(state) => {
+                self.states
+                    .insert(state.session_id.clone(), (**state).clone());
+                if self.pending_session.as_ref() != Some(&state.session_id) {
+                    return vec![];
+                }
+
+                self.pending_session = None;
+                let session_changed =
+                    ManagerMessage::SessionChanged(Some(state.session_id.clone()));
+                record(&session_changed, Some(state))
+                    .map(|record| vec![(HookTrigger::SessionChanged, record)])
+                    .unwrap_or_default()
+            }
+            ManagerMessage::Event(event) => {
+                let state = self.states.get(event.session_id());
+                let record = match record(msg, state) {
+                    Some(record) => record,
+                    None => return vec![],
+                };
+
+                event_triggers(event)
+                    .into_iter()
+                    .map(|trigger| (trigger, record.clone()))
+                    .collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+fn event_triggers(event: &MediaEvent) -> Vec<HookTrigger> {
+    match event {
+        MediaEvent::TrackChanged { .. } => vec![HookTrigger::TrackChanged],
+        MediaEvent::Seeked { .. } => vec![HookTrigger::Seeked],
+        MediaEvent::StatusChanged { from, to, .. } => {
+            let mut triggers = vec![HookTrigger::StatusChanged];
+            if *to == PlaybackStatus::Playing {
+                triggers.push(HookTrigger::PlaybackStarted);
+            } else if *from == Some(PlaybackStatus::Playing) {
+                triggers.push(HookTrigger::PlaybackStopped);
+            }
+            triggers
+        }
+        MediaEvent::MetadataChanged { .. } | MediaEvent::PlaybackChanged { .. } => vec![],
+    }
+}
+
+/// One run of a hook
+#[derive(Debug)]
+struct Job {
+    command: Vec<String>,
+    env: Vec<(String, String)>,
+    stdin: String,
+    timeout: Duration,
+}
+
+impl Job {
+    fn run(self) {
+        let name = self.command.join(" ");
+        let child = Command::new(&self.command[0])
+            .args(&self.command[1..])
+            .envs(self.env)
+            .stdin(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(error) => return eprintln!("[Hooks] Could not run `{}`: {}", name, error),
+        };
+
+        // Written on its own thread so a hook which never reads its stdin
+        // can't block past its timeout. Hooks closing it early are fine.
+        if let Some(mut stdin) = child.stdin.take() {
+            let input = self.stdin;
+            std::thread::spawn(move || stdin.write_all(input.as_bytes()).ok());
+        }
+
+        match wait_timeout(&mut child, self.timeout) {
+            Ok(Some(status)) if !status.success() => {
+                eprintln!("[Hooks] `{}` failed with {}", name, status);
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                eprintln!(
+                    "[Hooks] `{}` took longer than {}ms and was killed",
+                    name,
+                    self.timeout.as_millis()
+                );
+                child.kill().ok();
+                child.wait().ok();
+            }
+            Err(error) => {
+                eprintln!(
+                    "[Hooks] Could not wait for `{}`, killing it: {}",
+                    name, error
+                );
+                child.kill().ok();
+                child.wait().ok();
+            }
+        }
+    }
+}
+
+/// Waits for the child to exit, `None` if it is still running after `timeout`
+fn wait_timeout(
+    child: &mut Child,
+    timeout: Duration,
+) -> std::io::Result<Option<std::process::ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait()? {
+            Some(status) => return Ok(Some(status)),
+            None if Instant::now() < deadline => {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            None => return Ok(None),
+        }
+    }
+}
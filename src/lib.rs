@@ -8,5 +8,9 @@
 
 /// Module that controls threads
 pub mod controller;
-/// Module that allows control of Windows media
+/// Module that runs user commands on media events
+pub mod hooks;
+/// Module that allows control of system media
 pub mod media;
+/// Module that serves media control over HTTP
+pub mod server;
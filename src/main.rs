@@ -1,22 +1,75 @@
-use clap::{Parser, Subcommand};
+use std::{
+    collections::HashMap,
+    io::Write,
+    net::SocketAddr,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::{ArgEnum, Parser, Subcommand};
+use crossbeam_channel::RecvTimeoutError;
 use window::{
     controller::{Thread, ThreadController, ThreadMessage},
+    hooks::HooksConfig,
     media::{
-        currently_playing, currently_playing_raw, get_current_session, next_track, pause, play,
-        previous_track, Manager,
+        album_art, channel_down, channel_up, currently_playing, currently_playing_formatted,
+        currently_playing_raw, currently_playing_raw_with_art, default_backend, fast_forward,
+        list_sessions, media_state_schema, next_track, parse_duration, pause, play, previous_track,
+        record, restart_track, rewind, seek, select_session, set_playback_rate, set_repeat,
+        set_shuffle, stop, toggle_play_pause, toggle_shuffle, wait_for_session, BarStyle,
+        EventKind, EventRecord, FakeBackend, Manager, ManagerMessage, MediaBackend, MediaError,
+        MediaEvent, MediaState, RepeatMode, SeekTarget, SessionSelector, StatusBar, Template,
+        DEFAULT_COALESCE_WINDOW,
+    },
+    server::{
+        discover, host_name, qr_code, Advertisement, CertificateStatus, Certificates, Devices,
+        Pairing, Server, PIN_LIFETIME,
     },
 };
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
+#[clap(after_help = "EXIT CODES:
+    3    There is no media session, or none matching --session
+    4    The session doesn't support this control right now
+    5    The session doesn't report a property the command needs
+    6    An argument was out of range
+    7    The platform's media service failed")]
 struct Cli {
+    /// Use an in-memory media session driven by a JSON script instead of the
+    /// system's media sessions
+    #[clap(long, global = true, value_name = "SCRIPT")]
+    fake_session: Option<PathBuf>,
+
+    /// Session to control, by app id or by index as listed by `sessions`.
+    /// Defaults to the current session.
+    #[clap(long, global = true, value_name = "APP-ID|INDEX")]
+    session: Option<SessionSelector>,
+
+    /// Print errors as a JSON object instead of a message. `watch`,
+    /// `discover` and `devices list` print one JSON object per line for
+    /// everything they find, and `cert` one for the certificates, too.
+    #[clap(long, global = true)]
+    json: bool,
+
+    /// Directory the paired devices and the certificates of `serve` are kept
+    /// in. Defaults to `window` in the config directory of the user.
+    #[clap(long, global = true, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+
     /// Options
     #[clap(subcommand)]
     command: Commands,
 }
 
-// #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ArgEnum)]
+enum Switch {
+    On,
+    Off,
+    Toggle,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Play current track
@@ -27,42 +80,807 @@ enum Commands {
     Next,
     /// Play previous track
     Previous,
+    /// Pause when playing, play otherwise
+    Toggle,
+    /// Stop playback
+    Stop,
+    /// Fast-forward current track
+    #[clap(name = "ff")]
+    FastForward,
+    /// Rewind current track
+    Rewind,
+    /// Switch to the next channel
+    ChannelUp,
+    /// Switch to the previous channel
+    ChannelDown,
+    /// Start recording
+    Record,
+    /// Seek to a position (`1:23`) or skip by an offset (`+30s`, `-15s`)
+    Seek {
+        /// Position or offset to seek to
+        #[clap(allow_hyphen_values = true)]
+        position: SeekTarget,
+    },
+    /// Start the current track from the beginning
+    Restart,
+    /// Turn shuffle on or off
+    Shuffle {
+        #[clap(arg_enum)]
+        state: Switch,
+    },
+    /// Change the repeat mode (`none`, `track` or `list`)
+    Repeat { mode: RepeatMode },
+    /// Change the playback rate, `1.0` being normal speed and `100` the most
+    Rate { rate: f64 },
     /// See what's currently playing
-    Current,
+    Current {
+        /// Print the state through a template instead, e.g.
+        /// `{{artist}} - {{title}} [{{position}}/{{duration}}]`. Fields are
+        /// named as in `current-json`. Filters: `{{title | trunc 20}}` with
+        /// clock, secs, ms, upper, lower, trunc N, shell, json, markup and
+        /// default "text". Conditionals: `{{#if album}}...{{else}}...{{/if}}`.
+        #[clap(long, value_name = "TEMPLATE")]
+        format: Option<Template>,
+    },
     /// Get the currently playing data in JSON format
-    CurrentJSON,
+    CurrentJSON {
+        /// Embed the album art as a `data:` URL
+        #[clap(long)]
+        art: bool,
+        /// Scale the embedded album art down to at most this many pixels.
+        /// Only available on Windows.
+        #[clap(long, value_name = "PIXELS", requires = "art")]
+        art_size: Option<u32>,
+    },
+    /// Save the album art of the current track
+    Art {
+        /// File to write the image to. Written to stdout when omitted.
+        #[clap(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+        /// Scale the image down to at most this many pixels on either side.
+        /// Only available on Windows.
+        #[clap(long, value_name = "PIXELS")]
+        size: Option<u32>,
+    },
+    /// List every media session
+    Sessions,
+    /// Print the JSON Schema of the state printed by `current-json`
+    Schema,
+    /// Wait until there is a media session and print its app id
+    WaitSession {
+        /// Give up after this long, e.g. `30s`. Waits forever when omitted.
+        #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+        timeout: Option<Duration>,
+    },
     /// Watch for media changes using media manager
-    Watch,
+    Watch {
+        /// Watch every session instead of only the current one
+        #[clap(long)]
+        all: bool,
+
+        /// How long to collect the events of a session before reading its
+        /// state, e.g. `0.25s`
+        #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+        coalesce: Option<Duration>,
+
+        /// Only print these kinds of events: session, state, track, status,
+        /// seek (also called timeline) or playback. Prints all of them by
+        /// default.
+        #[clap(long, value_name = "KINDS", use_value_delimiter = true)]
+        events: Vec<EventKind>,
+    },
+    /// Run commands on media events, as configured in a JSON file
+    Hooks {
+        /// The hooks config, like `{"hooks": [{"on": ["track_changed"],
+        /// "command": ["notify-send", "Next track"]}]}`. Hooks run on
+        /// track_changed, status_changed, playback_started, playback_stopped,
+        /// seeked or session_changed, with the state in `WINDOW_*` variables
+        /// and as JSON on stdin.
+        #[clap(value_name = "CONFIG")]
+        config: PathBuf,
+    },
+    /// Follow the current session and write it in the format of a status bar,
+    /// every time it changes
+    Bar {
+        /// waybar, i3blocks or polybar
+        #[clap(long, value_name = "BAR")]
+        style: BarStyle,
+
+        /// Template of the text, as for `current --format`
+        #[clap(long, value_name = "TEMPLATE")]
+        format: Option<Template>,
+
+        /// Template of the waybar tooltip
+        #[clap(long, value_name = "TEMPLATE")]
+        tooltip: Option<Template>,
+
+        /// Scroll text longer than this many characters, 0 never scrolls
+        #[clap(long, value_name = "CHARS", default_value_t = 40)]
+        width: usize,
+    },
+    /// Serve the controls over HTTPS: `POST /play`, `/pause`, `/next` and
+    /// `/previous`, the state as JSON at `GET /current`, and a WebSocket
+    /// pushing every change at `/events`. The certificate is generated on the
+    /// first run, devices pin its fingerprint when pairing.
+    Serve {
+        /// Address to listen on, `0.0.0.0:3000` to be reachable from other
+        /// devices
+        #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:3000")]
+        bind: String,
+
+        /// Name to advertise the server as on the local network. Defaults to
+        /// the host name.
+        #[clap(long, value_name = "NAME")]
+        name: Option<String>,
+
+        /// Don't advertise the server on the local network. It is never
+        /// advertised when it only listens on a loopback address.
+        #[clap(long)]
+        no_advertise: bool,
+
+        /// Show a PIN to pair another device with. Shown anyway while no
+        /// device is paired.
+        #[clap(long)]
+        pair: bool,
+
+        /// Serve plain HTTP instead of HTTPS, for when something in front of
+        /// the server speaks TLS
+        #[clap(long)]
+        no_tls: bool,
+    },
+    /// Manage the devices paired with `serve`
+    Devices {
+        #[clap(subcommand)]
+        command: DevicesCommand,
+    },
+    /// Manage the certificate `serve` speaks TLS with
+    Cert {
+        #[clap(subcommand)]
+        command: CertCommand,
+    },
+    /// Find servers advertised on the local network
+    Discover {
+        /// How long to look for, e.g. `5s`
+        #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration), default_value = "3s")]
+        timeout: Duration,
+    },
+}
+
+#[derive(Subcommand)]
+enum DevicesCommand {
+    /// List the paired devices
+    List,
+    /// Rename a device
+    Rename {
+        /// Id of the device, as listed
+        id: String,
+        /// New name of the device
+        name: String,
+    },
+    /// Unpair a device, it has to pair again to get back in
+    Revoke {
+        /// Id of the device, as listed
+        id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum CertCommand {
+    /// Show the fingerprint of the certificate, and of the next one while
+    /// rotating
+    Show,
+    /// Generate a new certificate. The current one is kept for the grace
+    /// period, for paired devices to learn the new fingerprint.
+    Rotate {
+        /// How long to keep the current certificate, e.g. `24h` or `0s`
+        #[clap(long, value_name = "DURATION", parse(try_from_str = parse_duration), default_value = "168h")]
+        grace: Duration,
+    },
+}
+
+/// Where the media backend selected on the command line comes from. Opened on
+/// whichever thread needs it, as platform backends stay on the thread that
+/// created them. Fake sessions are loaded and start replaying their script
+/// once, and every thread shares them.
+#[derive(Debug, Clone)]
+enum BackendSource {
+    System,
+    Fake(FakeBackend),
+}
+
+impl BackendSource {
+    fn new(fake_session: &Option<PathBuf>) -> Result<Self, MediaError> {
+        match fake_session {
+            Some(script) => {
+                let backend = FakeBackend::from_file(script)?;
+                backend.start();
+                Ok(BackendSource::Fake(backend))
+            }
+            None => Ok(BackendSource::System),
+        }
+    }
+
+    fn open(&self) -> Result<Box<dyn MediaBackend>, MediaError> {
+        match self {
+            BackendSource::System => default_backend(),
+            BackendSource::Fake(backend) => Ok(Box::new(backend.clone())),
+        }
+    }
+}
+
+/// Gets the backend selected on the command line
+fn backend(fake_session: &Option<PathBuf>) -> Result<Box<dyn MediaBackend>, MediaError> {
+    BackendSource::new(fake_session)?.open()
+}
+
+/// File called `name` in the config directory
+fn config_path(config_dir: &Option<PathBuf>, name: &str) -> Result<PathBuf, MediaError> {
+    let dir = match config_dir {
+        Some(dir) => dir.clone(),
+        None => dirs::config_dir()
+            .ok_or_else(|| {
+                MediaError::Backend("There is no config directory, pass --config-dir".to_string())
+            })?
+            .join("window"),
+    };
+
+    Ok(dir.join(name))
+}
+
+/// Exit code for each kind of error. `2` is taken by argument errors and
+/// `101` by panics.
+fn exit_code(error: &MediaError) -> i32 {
+    match error {
+        MediaError::NoSession => 3,
+        MediaError::Unsupported(_) => 4,
+        MediaError::PropertyUnavailable(_) => 5,
+        MediaError::InvalidArgument(_) => 6,
+        MediaError::Backend(_) => 7,
+    }
 }
 
 #[doc(hidden)]
 fn main() {
     let cli = Cli::parse();
 
-    let current_session = get_current_session().unwrap();
+    if let Err(error) = run(&cli) {
+        if cli.json {
+            let mut json = serde_json::json!({
+                "error": error.kind(),
+                "message": error.to_string(),
+            });
+            if let MediaError::Unsupported(control) = &error {
+                json["control"] = control.to_string().into();
+            }
+            eprintln!("{}", json);
+        } else {
+            eprintln!("Error: {}", error);
+        }
+
+        std::process::exit(exit_code(&error));
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), MediaError> {
+    let selector = cli.session.clone().unwrap_or_default();
+    let current_session = || select_session(&*backend(&cli.fake_session)?, &selector);
 
     match &cli.command {
-        Commands::Play => play(current_session),
-        Commands::Pause => pause(current_session),
-        Commands::Next => next_track(current_session),
-        Commands::Previous => previous_track(current_session),
-        Commands::Current => currently_playing(current_session),
-        Commands::CurrentJSON => println!("{}", currently_playing_raw(current_session)),
-        Commands::Watch => {
-            let (tx, rx) = crossbeam_channel::unbounded();
-
-            let txc = tx.clone();
-            ctrlc::set_handler(move || {
-                txc.send(ThreadMessage::Stop).unwrap();
-            })
-            .expect("Error setting ctrlc handler");
-
-            let txc = tx.clone();
-            ThreadController::new(rx)
-                .add_thread(Thread::new(move |rx| {
-                    Manager::new(txc, rx).start_sync();
+        Commands::Play => play(&*current_session()?)?,
+        Commands::Pause => pause(&*current_session()?)?,
+        Commands::Next => next_track(&*current_session()?)?,
+        Commands::Previous => previous_track(&*current_session()?)?,
+        Commands::Toggle => toggle_play_pause(&*current_session()?)?,
+        Commands::Stop => stop(&*current_session()?)?,
+        Commands::FastForward => fast_forward(&*current_session()?)?,
+        Commands::Rewind => rewind(&*current_session()?)?,
+        Commands::ChannelUp => channel_up(&*current_session()?)?,
+        Commands::ChannelDown => channel_down(&*current_session()?)?,
+        Commands::Record => record(&*current_session()?)?,
+        Commands::Seek { position } => {
+            seek(&*current_session()?, *position)?;
+        }
+        Commands::Restart => {
+            restart_track(&*current_session()?)?;
+        }
+        Commands::Shuffle { state } => {
+            let session = current_session()?;
+            match state {
+                Switch::On => set_shuffle(&*session, true)?,
+                Switch::Off => set_shuffle(&*session, false)?,
+                Switch::Toggle => {
+                    toggle_shuffle(&*session)?;
+                }
+            }
+        }
+        Commands::Repeat { mode } => set_repeat(&*current_session()?, *mode)?,
+        Commands::Rate { rate } => set_playback_rate(&*current_session()?, *rate)?,
+        Commands::Current { format: None } => currently_playing(&*current_session()?)?,
+        Commands::Current {
+            format: Some(template),
+        } => println!(
+            "{}",
+            currently_playing_formatted(&*current_session()?, template)?
+        ),
+        Commands::CurrentJSON { art, art_size } => {
+            let session = current_session()?;
+            if *art {
+                println!("{}", currently_playing_raw_with_art(&*session, *art_size)?);
+            } else {
+                println!("{}", currently_playing_raw(&*session)?);
+            }
+        }
+        Commands::Art { out, size } => {
+            let art = album_art(&*current_session()?, *size)?;
+            let written = match out {
+                Some(path) => std::fs::write(path, &art.data),
+                None => std::io::stdout().write_all(&art.data),
+            };
+            written.map_err(|error| {
+                MediaError::Backend(format!("Could not write the album art: {}", error))
+            })?;
+        }
+        Commands::Sessions => {
+            for session in list_sessions(&*backend(&cli.fake_session)?)? {
+                println!(
+                    "{}: {} [{}] {}",
+                    session.index, session.source_app_id, session.status, session.title
+                );
+            }
+        }
+        Commands::Schema => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&media_state_schema()).unwrap()
+            );
+        }
+        Commands::WaitSession { timeout } => {
+            let session = wait_for_session(&*backend(&cli.fake_session)?, &selector, *timeout)?;
+            println!("{}", session.source_app_id());
+        }
+        Commands::Watch {
+            all,
+            coalesce,
+            events,
+        } => {
+            let all = *all;
+            let coalesce = coalesce.unwrap_or(DEFAULT_COALESCE_WINDOW);
+            let events = events.clone();
+            let json = cli.json;
+            let print = move |msg: &ManagerMessage| {
+                match msg.kind() {
+                    Some(kind) if events.is_empty() || events.contains(&kind) => {}
+                    _ => return,
+                }
+                if json {
+                    print_record(msg);
+                } else {
+                    print_event(msg);
+                }
+            };
+
+            run_manager(
+                BackendSource::new(&cli.fake_session)?,
+                move |manager| {
+                    let manager = manager.coalesce_window(coalesce).on_event(print);
+                    if all {
+                        manager.watch_all_sessions()
+                    } else {
+                        Ok(manager)
+                    }
+                },
+                |controller| controller,
+            )?;
+        }
+        Commands::Hooks { config } => {
+            let config = HooksConfig::from_file(config)?;
+            run_manager(BackendSource::new(&cli.fake_session)?, Ok, |controller| {
+                controller.add_thread(Thread::new(move |rx| config.run(rx)))
+            })?;
+        }
+        Commands::Bar {
+            style,
+            format,
+            tooltip,
+            width,
+        } => {
+            let mut bar = StatusBar::new(*style)
+                .width(*width)
+                .click(toggle_command(cli));
+            if let Some(format) = format {
+                bar = bar.format(format.clone());
+            }
+            if let Some(tooltip) = tooltip {
+                bar = bar.tooltip(tooltip.clone());
+            }
+
+            run_manager(BackendSource::new(&cli.fake_session)?, Ok, |controller| {
+                controller.add_thread(Thread::new(move |rx| run_bar(bar, rx)))
+            })?;
+        }
+        Commands::Serve {
+            bind,
+            name,
+            no_advertise,
+            pair,
+            no_tls,
+        } => {
+            let devices = Devices::open(config_path(&cli.config_dir, "devices.json")?)?;
+            let pairing = if *pair || devices.list().is_empty() {
+                Some(Pairing::open()?)
+            } else {
+                None
+            };
+            let mut server = Server::bind(bind)?
+                .session(selector.clone())
+                .devices(devices);
+            let certificates = if *no_tls {
+                None
+            } else {
+                let certificates =
+                    Certificates::open(config_path(&cli.config_dir, "certificates.json")?)?;
+                let status = certificates.status().clone();
+                server = server.tls(certificates);
+                Some(status)
+            };
+            let addr = server.local_addr();
+            if let Some(addr) = addr {
+                let scheme = if certificates.is_some() {
+                    "https"
+                } else {
+                    "http"
+                };
+                eprintln!("[Server] Listening on {}://{}", scheme, addr);
+            }
+            if let Some(certificates) = &certificates {
+                eprintln!(
+                    "[Server] Certificate fingerprint {}",
+                    certificates.fingerprint
+                );
+            }
+
+            let name = name.clone().unwrap_or_else(host_name);
+            let fingerprint = certificates
+                .as_ref()
+                .map(|certificates| certificates.fingerprint.as_str());
+            if let (Some(pairing), Some(addr)) = (pairing, addr) {
+                show_pairing(&pairing, addr, &name, fingerprint)?;
+                server = server.pairing(pairing);
+            }
+
+            match addr {
+                Some(addr) if !no_advertise && !addr.ip().is_loopback() => {
+                    let advertisement =
+                        Advertisement::start(&name, addr.port(), certificates.as_ref())?;
+                    eprintln!("[Server] Advertised as {} on the local network", name);
+                    server = server.advertise(advertisement);
+                }
+                _ => {}
+            }
+
+            let source = BackendSource::new(&cli.fake_session)?;
+            let server_source = source.clone();
+            run_manager(source, Ok, |controller| {
+                controller.add_thread(Thread::new(move |rx| match server_source.open() {
+                    Ok(backend) => server.run(&*backend, rx),
+                    Err(error) => eprintln!("[Server] Could not open the backend: {}", error),
                 }))
-                .begin();
+            })?;
+        }
+        Commands::Devices { command } => {
+            let mut devices = Devices::open(config_path(&cli.config_dir, "devices.json")?)?;
+            match command {
+                DevicesCommand::List => {
+                    for device in devices.list() {
+                        if cli.json {
+                            println!("{}", serde_json::to_string(device).unwrap());
+                        } else {
+                            let json = serde_json::to_value(device).unwrap();
+                            println!(
+                                "{}: {} (paired {})",
+                                device.id,
+                                device.name,
+                                json["paired_at"].as_str().unwrap_or_default()
+                            );
+                        }
+                    }
+                }
+                DevicesCommand::Rename { id, name } => devices.rename(id, name)?,
+                DevicesCommand::Revoke { id } => {
+                    let device = devices.revoke(id)?;
+                    println!("Revoked {}", device.name);
+                }
+            }
+        }
+        Commands::Cert { command } => {
+            let mut certificates =
+                Certificates::open(config_path(&cli.config_dir, "certificates.json")?)?;
+            let status = match command {
+                CertCommand::Show => certificates.status(),
+                CertCommand::Rotate { grace } => certificates.rotate(*grace)?,
+            };
+            print_certificates(status, cli.json);
+        }
+        Commands::Discover { timeout } => {
+            for instance in discover(*timeout)? {
+                if cli.json {
+                    println!("{}", serde_json::to_string(&instance).unwrap());
+                    continue;
+                }
+
+                let addresses: Vec<String> = instance
+                    .addresses
+                    .iter()
+                    .map(|addr| SocketAddr::new(*addr, instance.port).to_string())
+                    .collect();
+                let certificate = instance
+                    .fingerprint
+                    .map(|fingerprint| format!(" certificate {}", fingerprint))
+                    .unwrap_or_default();
+                println!(
+                    "{}: {} on {} [v{} {}]{}",
+                    instance.name,
+                    addresses.join(" "),
+                    instance.host,
+                    instance
+                        .version
+                        .map(|version| version.to_string())
+                        .unwrap_or_else(|| "?".to_string()),
+                    instance.capabilities.join(","),
+                    certificate
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows the PIN, and a QR code with it, where the server is and the
+/// `fingerprint` of its certificate, for a device to pair with
+fn show_pairing(
+    pairing: &Pairing,
+    addr: SocketAddr,
+    name: &str,
+    fingerprint: Option<&str>,
+) -> Result<(), MediaError> {
+    let host = if addr.ip().is_unspecified() {
+        format!("{}.local", host_name())
+    } else {
+        addr.ip().to_string()
+    };
+    let uri = pairing.uri(&host, addr.port(), name, fingerprint);
+
+    eprintln!(
+        "[Server] Pair a device with PIN {} within {} minutes, or scan:\n{}\n{}",
+        pairing.pin(),
+        PIN_LIFETIME.as_secs() / 60,
+        qr_code(&uri)?,
+        uri
+    );
+
+    Ok(())
+}
+
+/// Prints where the certificates stand, as a JSON object with `json`
+fn print_certificates(status: &CertificateStatus, json: bool) {
+    let record = serde_json::to_value(status).unwrap();
+    if json {
+        return println!("{}", record);
+    }
+
+    println!(
+        "Fingerprint: {} (created {})",
+        status.fingerprint,
+        record["created_at"].as_str().unwrap_or_default()
+    );
+    if let Some(next) = &status.next {
+        println!(
+            "Next: {} (from {})",
+            next.fingerprint,
+            record["next"]["active_from"].as_str().unwrap_or_default()
+        );
+    }
+}
+
+/// The command toggling playback of the session the bar follows, with the
+/// options `window` was started with
+fn toggle_command(cli: &Cli) -> String {
+    let exe = std::env::current_exe()
+        .map(|exe| exe.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| "window".to_string());
+    let mut args = vec![exe];
+    if let Some(script) = &cli.fake_session {
+        args.push("--fake-session".to_string());
+        // The bar runs the command from a directory of its own
+        let script = std::fs::canonicalize(script).unwrap_or_else(|_| script.clone());
+        args.push(script.to_string_lossy().into_owned());
+    }
+    match &cli.session {
+        Some(SessionSelector::Index(index)) => {
+            args.extend(["--session".to_string(), index.to_string()])
+        }
+        Some(SessionSelector::AppId(id)) => args.extend(["--session".to_string(), id.clone()]),
+        Some(SessionSelector::Current) | None => {}
+    }
+    args.push("toggle".to_string());
+
+    args.iter()
+        .map(|arg| format!("'{}'", arg.replace('\'', r"'\''")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Runs a media manager on its own thread under a thread controller until
+/// ctrl-c is pressed. `configure` sets the manager up on its thread, `threads`
+/// adds the threads listening to it once it started.
+fn run_manager<F, T>(source: BackendSource, configure: F, threads: T) -> Result<(), MediaError>
+where
+    F: FnOnce(Manager) -> Result<Manager, MediaError> + Send + 'static,
+    T: FnOnce(ThreadController) -> ThreadController,
+{
+    let (tx, rx) = crossbeam_channel::unbounded();
+
+    let txc = tx.clone();
+    ctrlc::set_handler(move || {
+        txc.send(ThreadMessage::Stop).unwrap();
+    })
+    .expect("Error setting ctrlc handler");
+
+    let (ready_tx, ready_rx) = crossbeam_channel::bounded(1);
+    let controller = ThreadController::new(rx).add_thread(Thread::new(move |rx| {
+        let manager = source
+            .open()
+            .and_then(|backend| Manager::with_backend(backend, tx, rx))
+            .and_then(configure);
+
+        match manager {
+            Ok(mut manager) => {
+                ready_tx.send(Ok(())).unwrap();
+                manager.start_sync();
+            }
+            Err(error) => ready_tx.send(Err(error)).unwrap(),
+        }
+    }));
+
+    // The manager is built on its own thread, wait for it to either start or
+    // fail before handing over to the controller
+    ready_rx.recv().unwrap()?;
+    threads(controller).begin();
+
+    Ok(())
+}
+
+/// How often the bar scrolls and updates the position
+const BAR_TICK: Duration = Duration::from_millis(500);
+
+/// Writes the bar for the current session whenever it changes, until told to
+/// stop
+fn run_bar(mut bar: StatusBar, rx: crossbeam_channel::Receiver<ThreadMessage>) {
+    let mut current = None;
+    let mut states: HashMap<String, MediaState> = HashMap::new();
+    let mut last = None;
+    let mut next_tick = Instant::now() + BAR_TICK;
+
+    loop {
+        match rx.recv_deadline(next_tick) {
+            Ok(ThreadMessage::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+            Ok(ThreadMessage::Media(ManagerMessage::SessionChanged(id))) => current = id,
+            Ok(ThreadMessage::Media(ManagerMessage::StateChanged(state))) => {
+                states.insert(state.session_id.clone(), *state);
+            }
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => {
+                bar.tick();
+                next_tick += BAR_TICK;
+            }
         }
+
+        // The state of a new session follows right after it
+        let state = match &current {
+            Some(id) => match states.get(id) {
+                Some(state) => Some(state),
+                None => continue,
+            },
+            None => None,
+        };
+        let output = bar.render(state, SystemTime::now());
+        if last.as_ref() != Some(&output) {
+            let mut stdout = std::io::stdout().lock();
+            writeln!(stdout, "{}", output).ok();
+            stdout.flush().ok();
+            last = Some(output);
+        }
+    }
+}
+
+/// Prints what the media manager publishes as one line of JSON, flushed right
+/// away so whoever reads it sees it as it happens
+fn print_record(msg: &ManagerMessage) {
+    if let Some(record) = EventRecord::from_message(msg, SystemTime::now()) {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", serde_json::to_string(&record).unwrap()).ok();
+        stdout.flush().ok();
+    }
+}
+
+/// Prints what the media manager publishes while watching
+fn print_event(msg: &ManagerMessage) {
+    match msg {
+        ManagerMessage::SessionChanged(Some(id)) => {
+            println!("[Media Manager] Current session is {}", id);
+        }
+        ManagerMessage::StateChanged(state) => print_state(state),
+        ManagerMessage::Event(event) => print_media_event(event),
+        _ => {}
     }
 }
+
+fn print_media_event(event: &MediaEvent) {
+    let details = match event {
+        MediaEvent::TrackChanged { from, to, .. } => match from {
+            Some(from) => format!("{} -> {}", from.title, to.title),
+            None => to.title.clone(),
+        },
+        MediaEvent::MetadataChanged { media, .. } => media.title.clone(),
+        MediaEvent::StatusChanged {
+            from: Some(from),
+            to,
+            ..
+        } => format!("{} -> {}", from, to),
+        MediaEvent::StatusChanged { to, .. } => to.to_string(),
+        MediaEvent::Seeked { from, to, .. } => {
+            format!("{}ms -> {}ms", from.as_millis(), to.as_millis())
+        }
+        MediaEvent::PlaybackChanged { .. } => String::new(),
+    };
+
+    println!(
+        "[Media Manager] {} {}: {}",
+        event.session_id(),
+        event.name(),
+        details
+    );
+}
+
+fn print_state(state: &MediaState) {
+    let duration = match state.duration_ms {
+        Some(duration) => format!("{}ms", duration),
+        None => "live".to_string(),
+    };
+
+    println!(
+        "\
+        -- START STATE CHANGE --\n\
+        \tsession: {}\n\
+        \talbum artist: {}\n\
+        \talbum title: {}\n\
+        \talbum track count: {}\n\
+        \tartist: {}\n\
+        \tsubtitle: {}\n\
+        \ttitle: {}\n\
+        \ttrack #: {}\n\
+        \tart: {}\n\
+        \tpb status: {}\n\
+        \tpb type: {:?}\n\
+        \tshuffle active?: {}\n\
+        \tpos: {}ms\n\
+        \tduration: {}\n\
+        -- END STATE CHANGE --\
+        \n",
+        state.session_id,
+        state.media.album_artist,
+        state.media.album_title,
+        state.media.album_track_count,
+        state.media.artist,
+        state.media.subtitle,
+        state.media.title,
+        state.media.track_number,
+        state.art_hash.as_deref().unwrap_or("none"),
+        state.playback.playback_status,
+        state.playback.playback_type,
+        state.playback.shuffle_active,
+        state.timeline.pos.as_millis(),
+        duration,
+    );
+}
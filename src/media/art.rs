@@ -0,0 +1,158 @@
+use sha1::{Digest, Sha1};
+
+use crate::media::MediaSession;
+
+/// Album art or other thumbnail image of a session's track
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Thumbnail {
+    /// MIME type of the image, e.g. `image/png`
+    pub content_type: String,
+    /// Encoded image bytes
+    pub data: Vec<u8>,
+}
+
+impl Thumbnail {
+    /// Wraps encoded image bytes, guessing the content type from the data
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            content_type: sniff_content_type(&data).to_string(),
+            data,
+        }
+    }
+
+    /// Hex encoded SHA-1 of the image bytes. It only changes when the image
+    /// does, so clients can use it to tell whether to fetch the art again.
+    pub fn hash(&self) -> String {
+        Sha1::digest(&self.data)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Standard base64 encoding of the image bytes
+    pub fn base64(&self) -> String {
+        base64(&self.data)
+    }
+
+    /// The image as a `data:` URL, ready to use as an `<img>` source
+    ///
+    /// # Example
+    /// ```
+    /// use window::media::Thumbnail;
+    ///
+    /// let art = Thumbnail::new(b"\x89PNG\r\n\x1a\n".to_vec());
+    /// assert_eq!(art.data_url(), "data:image/png;base64,iVBORw0KGgo=");
+    /// ```
+    pub fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.content_type, self.base64())
+    }
+}
+
+/// Guesses the MIME type of an image from its magic bytes
+fn sniff_content_type(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF8") {
+        "image/gif"
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        "image/webp"
+    } else if data.starts_with(b"BM") {
+        "image/bmp"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (triple >> (18 - 6 * i)) & 0x3f;
+                encoded.push(ALPHABET[index as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Identifies the track a thumbnail was read for
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TrackKey {
+    source_app_id: String,
+    title: String,
+    artist: String,
+    album_title: String,
+    /// See [`MediaSession::thumbnail_id`]
+    thumbnail_id: Option<String>,
+    max_size: Option<u32>,
+}
+
+impl TrackKey {
+    fn of(session: &dyn MediaSession, max_size: Option<u32>) -> Self {
+        let media = session.media_properties().unwrap_or_default();
+
+        Self {
+            source_app_id: session.source_app_id(),
+            title: media.title,
+            artist: media.artist,
+            album_title: media.album_title,
+            thumbnail_id: session.thumbnail_id(),
+            max_size,
+        }
+    }
+}
+
+/// Remembers the thumbnail of the last track it was asked about, so art is
+/// only read again once the track or, where the backend tells, its art
+/// changes.
+///
+/// # Example
+/// ```
+/// use window::media::{ArtCache, FakeBackend, FakeScript, MediaBackend};
+///
+/// let session = FakeBackend::new(FakeScript::default()).current_session().unwrap();
+/// let mut cache = ArtCache::default();
+///
+/// // The fake session has no art by default
+/// assert!(cache.get(&*session, None).is_none());
+/// ```
+#[derive(Debug, Default)]
+pub struct ArtCache {
+    key: Option<TrackKey>,
+    thumbnail: Option<Thumbnail>,
+}
+
+impl ArtCache {
+    /// Gets the thumbnail of the session's current track, reading it only if
+    /// the track, its art or the requested size changed since the last call
+    pub fn get(&mut self, session: &dyn MediaSession, max_size: Option<u32>) -> Option<&Thumbnail> {
+        let key = TrackKey::of(session, max_size);
+        if self.key.as_ref() != Some(&key) {
+            self.thumbnail = session.thumbnail(max_size);
+            self.key = Some(key);
+        }
+
+        self.thumbnail.as_ref()
+    }
+
+    /// Forgets the cached thumbnail
+    pub fn clear(&mut self) {
+        self.key = None;
+        self.thumbnail = None;
+    }
+}
@@ -0,0 +1,219 @@
+use std::time::SystemTime;
+
+use serde_json::json;
+
+use crate::media::{
+    estimate_position, template::escape_markup, MediaState, PlaybackStatus, Template,
+};
+
+/// Status bar whose output format a [`StatusBar`] writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarStyle {
+    /// A JSON object per line with `text`, `tooltip`, `class`, `alt` and
+    /// `percentage`, for a waybar custom module with `"return-type": "json"`
+    Waybar,
+    /// A JSON object per line with `full_text`, `short_text` and `color`, for
+    /// a persistent i3blocks block with `interval=persist` and `format=json`
+    I3blocks,
+    /// A line with polybar formatting tags, for a `tail = true` script module.
+    /// Clicking it runs the [`StatusBar::click`] command.
+    Polybar,
+}
+
+impl std::str::FromStr for BarStyle {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "waybar" => Ok(BarStyle::Waybar),
+            "i3blocks" => Ok(BarStyle::I3blocks),
+            "polybar" => Ok(BarStyle::Polybar),
+            _ => Err("Bars are `waybar`, `i3blocks` or `polybar`"),
+        }
+    }
+}
+
+/// Template the bar shows by default
+pub const DEFAULT_BAR_FORMAT: &str = "{{#if artist}}{{artist}} - {{/if}}{{title}}";
+
+/// Template of the tooltip by default
+pub const DEFAULT_BAR_TOOLTIP: &str =
+    "{{title}}{{#if artist}}\nby {{artist}}{{/if}}{{#if album}}\non {{album}}{{/if}}\n\
+    {{position}}{{#if duration}} / {{duration}}{{/if}}";
+
+/// Renders media states in the format of a status bar. Text longer than the
+/// width scrolls by one character on every [`StatusBar::tick`].
+///
+/// # Example
+/// ```
+/// use window::media::{
+///     BarStyle, FakeBackend, FakeScript, FakeTrack, MediaBackend, MediaState, StatusBar,
+///     SystemClock,
+/// };
+///
+/// let backend = FakeBackend::new(FakeScript {
+///     tracks: vec![FakeTrack {
+///         title: "A Rather Long Title".to_string(),
+///         ..Default::default()
+///     }],
+///     playing: true,
+///     ..Default::default()
+/// });
+/// let session = backend.current_session().unwrap();
+/// let state = MediaState::read(&*session, &SystemClock).unwrap();
+/// let now = state.timeline.last_updated_time;
+///
+/// let mut bar = StatusBar::new(BarStyle::Polybar).width(8);
+/// assert_eq!(bar.render(Some(&state), now), "%{A1:window toggle:}A Rather%{A}");
+/// bar.tick();
+/// assert_eq!(bar.render(Some(&state), now), "%{A1:window toggle:} Rather %{A}");
+/// ```
+#[derive(Debug, Clone)]
+pub struct StatusBar {
+    style: BarStyle,
+    format: Template,
+    tooltip: Template,
+    width: usize,
+    click: String,
+    /// Session and title of the track the scroll offset belongs to
+    track: Option<(String, String)>,
+    offset: usize,
+}
+
+impl StatusBar {
+    /// Create a bar showing the default templates, scrolling text longer than
+    /// 40 characters
+    pub fn new(style: BarStyle) -> Self {
+        Self {
+            style,
+            format: DEFAULT_BAR_FORMAT.parse().unwrap(),
+            tooltip: DEFAULT_BAR_TOOLTIP.parse().unwrap(),
+            width: 40,
+            click: "window toggle".to_string(),
+            track: None,
+            offset: 0,
+        }
+    }
+
+    /// Show `format` instead of the default template
+    pub fn format(mut self, format: Template) -> Self {
+        self.format = format;
+
+        self
+    }
+
+    /// Show `tooltip` in the tooltip of waybar instead of the default template
+    pub fn tooltip(mut self, tooltip: Template) -> Self {
+        self.tooltip = tooltip;
+
+        self
+    }
+
+    /// Scroll text longer than `width` characters. Zero never scrolls.
+    pub fn width(mut self, width: usize) -> Self {
+        self.width = width;
+
+        self
+    }
+
+    /// Run `command` when the polybar module is clicked, instead of
+    /// `window toggle`
+    pub fn click(mut self, command: impl Into<String>) -> Self {
+        self.click = command.into();
+
+        self
+    }
+
+    /// Scroll the text on by one character
+    pub fn tick(&mut self) {
+        self.offset += 1;
+    }
+
+    /// Renders the output for the bar, with the position estimated for `now`.
+    /// `None` renders an empty bar, for when there is no session. Scrolling
+    /// starts over once another track plays.
+    pub fn render(&mut self, state: Option<&MediaState>, now: SystemTime) -> String {
+        let state = state.map(|state| {
+            let mut state = state.clone();
+            state.timeline.pos = estimate_position(&state.timeline, &state.playback, now);
+            state.timeline.last_updated_time = now;
+            state
+        });
+
+        let track = state
+            .as_ref()
+            .map(|state| (state.session_id.clone(), state.media.title.clone()));
+        if track != self.track {
+            self.track = track;
+            self.offset = 0;
+        }
+        let text = state
+            .as_ref()
+            .map(|state| self.scrolled(&self.format.render(state)))
+            .unwrap_or_default();
+        let status = state.as_ref().map(|state| state.playback.playback_status);
+
+        match self.style {
+            BarStyle::Waybar => {
+                let class = match status {
+                    Some(status) => status.to_string().to_lowercase(),
+                    None => "none".to_string(),
+                };
+                json!({
+                    "text": escape_markup(&text),
+                    "tooltip": state
+                        .as_ref()
+                        .map(|state| escape_markup(&self.tooltip.render(state)))
+                        .unwrap_or_default(),
+                    "class": class,
+                    "alt": class,
+                    "percentage": state
+                        .as_ref()
+                        .and_then(|state| state.timeline.finished_percentage())
+                        .map(|percentage| percentage.round() as u8)
+                        .unwrap_or(0),
+                })
+                .to_string()
+            }
+            BarStyle::I3blocks => {
+                let short: String = text.chars().take(20).collect();
+                let mut block = json!({
+                    "full_text": text,
+                    "short_text": short,
+                });
+                let color = match status {
+                    Some(PlaybackStatus::Playing) => Some("#8ec07c"),
+                    Some(PlaybackStatus::Paused) => Some("#928374"),
+                    _ => None,
+                };
+                if let Some(color) = color {
+                    block["color"] = color.into();
+                }
+                block.to_string()
+            }
+            BarStyle::Polybar if text.is_empty() => String::new(),
+            BarStyle::Polybar => {
+                let text = text.replace('%', "%%");
+                let text = match status {
+                    Some(PlaybackStatus::Playing) => text,
+                    _ => format!("%{{F#928374}}{}%{{F-}}", text),
+                };
+                // Colons end the command of the action unless escaped
+                format!("%{{A1:{}:}}{}%{{A}}", self.click.replace(':', "\\:"), text)
+            }
+        }
+    }
+
+    /// The part of the text which fits the width at the current offset
+    fn scrolled(&self, text: &str) -> String {
+        if self.width == 0 || text.chars().count() <= self.width {
+            return text.to_string();
+        }
+
+        // Scroll in a loop with a gap between the end and the start again
+        let looped: Vec<char> = text.chars().chain("   ".chars()).collect();
+        (0..self.width)
+            .map(|i| looped[(self.offset + i) % looped.len()])
+            .collect()
+    }
+}
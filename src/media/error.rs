@@ -0,0 +1,96 @@
+use std::fmt;
+
+/// A control which a media session may or may not accept, as reported by its
+/// [`ActiveControls`][crate::media::ActiveControls]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(missing_docs)]
+pub enum Control {
+    Play,
+    Pause,
+    Stop,
+    Record,
+    FastForward,
+    Rewind,
+    Next,
+    Previous,
+    ChannelUp,
+    ChannelDown,
+    PlayPauseToggle,
+    Shuffle,
+    Repeat,
+    PlaybackRate,
+    PlaybackPosition,
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Control::Play => "play",
+            Control::Pause => "pause",
+            Control::Stop => "stop",
+            Control::Record => "record",
+            Control::FastForward => "fast-forward",
+            Control::Rewind => "rewind",
+            Control::Next => "next",
+            Control::Previous => "previous",
+            Control::ChannelUp => "channel-up",
+            Control::ChannelDown => "channel-down",
+            Control::PlayPauseToggle => "toggle",
+            Control::Shuffle => "shuffle",
+            Control::Repeat => "repeat",
+            Control::PlaybackRate => "rate",
+            Control::PlaybackPosition => "seek",
+        })
+    }
+}
+
+/// Everything that can go wrong while talking to media sessions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaError {
+    /// There is no media session, or none matching the selector
+    NoSession,
+    /// The session doesn't accept this control right now
+    Unsupported(Control),
+    /// The session doesn't report a property that was needed
+    PropertyUnavailable(&'static str),
+    /// An argument was out of range, e.g. a negative playback rate
+    InvalidArgument(String),
+    /// The platform's media service failed
+    Backend(String),
+}
+
+impl MediaError {
+    /// Short machine readable name of the error kind, e.g. `no_session`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MediaError::NoSession => "no_session",
+            MediaError::Unsupported(_) => "unsupported",
+            MediaError::PropertyUnavailable(_) => "property_unavailable",
+            MediaError::InvalidArgument(_) => "invalid_argument",
+            MediaError::Backend(_) => "backend",
+        }
+    }
+
+    /// Wraps any displayable error of a platform API
+    pub(crate) fn backend(error: impl fmt::Display) -> Self {
+        MediaError::Backend(error.to_string())
+    }
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaError::NoSession => write!(f, "There is no media session"),
+            MediaError::Unsupported(control) => {
+                write!(f, "The session doesn't support `{}` right now", control)
+            }
+            MediaError::PropertyUnavailable(property) => {
+                write!(f, "The session doesn't report its {}", property)
+            }
+            MediaError::InvalidArgument(reason) => f.write_str(reason),
+            MediaError::Backend(error) => write!(f, "The media service failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {}
@@ -0,0 +1,288 @@
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::media::{
+    estimate_position, time, ManagerMessage, MediaProps, MediaState, PlaybackInfoProps,
+    PlaybackStatus,
+};
+
+/// How far the position may drift from the estimate before it counts as a
+/// seek. Sessions report their position coarsely, so small jumps are noise.
+pub const SEEK_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// What changed between two states of the same session. In JSON the kind of
+/// event is in its `event` field, e.g. `"event": "track_changed"`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum MediaEvent {
+    /// Another track is playing. `from` is `None` for the first state seen of
+    /// a session.
+    TrackChanged {
+        /// App id of the session
+        session_id: String,
+        /// The track played before
+        from: Option<MediaProps>,
+        /// The track playing now
+        to: MediaProps,
+    },
+    /// The metadata or art of the same track changed, e.g. when a player
+    /// fills it in after the track started
+    MetadataChanged {
+        /// App id of the session
+        session_id: String,
+        /// Metadata of the track
+        media: MediaProps,
+        /// SHA-1 of the album art, see [`MediaState::art_hash`]
+        art_hash: Option<String>,
+    },
+    /// The session started playing, paused, stopped and so on. `from` is
+    /// `None` for the first state seen of a session.
+    StatusChanged {
+        /// App id of the session
+        session_id: String,
+        /// Status before
+        from: Option<PlaybackStatus>,
+        /// Status now
+        to: PlaybackStatus,
+    },
+    /// The position jumped further than the time since the last state
+    /// explains
+    Seeked {
+        /// App id of the session
+        session_id: String,
+        /// Where the position would be without the seek
+        #[serde(rename = "from_ms", with = "time::millis")]
+        from: Duration,
+        /// Where the position is now
+        #[serde(rename = "to_ms", with = "time::millis")]
+        to: Duration,
+    },
+    /// Shuffle, repeat, the playback rate or the accepted controls changed
+    PlaybackChanged {
+        /// App id of the session
+        session_id: String,
+        /// The new playback state
+        playback: PlaybackInfoProps,
+    },
+}
+
+impl MediaEvent {
+    /// Name of the event, as in its JSON `event` field
+    pub fn name(&self) -> &'static str {
+        match self {
+            MediaEvent::TrackChanged { .. } => "track_changed",
+            MediaEvent::MetadataChanged { .. } => "metadata_changed",
+            MediaEvent::StatusChanged { .. } => "status_changed",
+            MediaEvent::Seeked { .. } => "seeked",
+            MediaEvent::PlaybackChanged { .. } => "playback_changed",
+        }
+    }
+
+    /// Which kind of change the event is
+    pub fn kind(&self) -> EventKind {
+        match self {
+            MediaEvent::TrackChanged { .. } | MediaEvent::MetadataChanged { .. } => {
+                EventKind::Track
+            }
+            MediaEvent::StatusChanged { .. } => EventKind::Status,
+            MediaEvent::Seeked { .. } => EventKind::Seek,
+            MediaEvent::PlaybackChanged { .. } => EventKind::Playback,
+        }
+    }
+
+    /// App id of the session the event is about
+    pub fn session_id(&self) -> &str {
+        match self {
+            MediaEvent::TrackChanged { session_id, .. }
+            | MediaEvent::MetadataChanged { session_id, .. }
+            | MediaEvent::StatusChanged { session_id, .. }
+            | MediaEvent::Seeked { session_id, .. }
+            | MediaEvent::PlaybackChanged { session_id, .. } => session_id,
+        }
+    }
+}
+
+/// Groups of messages published by the [`Manager`][crate::media::Manager],
+/// to pick which ones to listen to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// The current session changed
+    Session,
+    /// Full states of a session
+    State,
+    /// Track and metadata changes
+    Track,
+    /// Playback status changes
+    Status,
+    /// Seeks, also called `timeline` on the command line
+    Seek,
+    /// Shuffle, repeat, rate and control changes
+    Playback,
+}
+
+impl std::str::FromStr for EventKind {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "session" => EventKind::Session,
+            "state" => EventKind::State,
+            "track" => EventKind::Track,
+            "status" => EventKind::Status,
+            "seek" | "timeline" => EventKind::Seek,
+            "playback" => EventKind::Playback,
+            _ => {
+                return Err(
+                    "Events are `session`, `state`, `track`, `status`, `seek` (or `timeline`) or `playback`",
+                )
+            }
+        })
+    }
+}
+
+/// A message published by the [`Manager`][crate::media::Manager] as one JSON
+/// object, like the lines printed by `watch --json`.
+///
+/// `type` is `session_changed`, `state` or the name of a [`MediaEvent`].
+/// `state` carries the full state for `state` records, `event` what changed
+/// for the others.
+///
+/// ```json
+/// {"type":"status_changed","session_id":"Spotify.exe","timestamp":"2022-05-28T14:03:07.250Z",
+///  "event":{"event":"status_changed","session_id":"Spotify.exe","from":"PLAYING","to":"PAUSED"}}
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    /// What the record is about
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// App id of the session, `null` when a `session_changed` record says
+    /// there is no session
+    pub session_id: Option<String>,
+    /// When the message was published
+    #[serde(with = "time::iso8601")]
+    pub timestamp: SystemTime,
+    /// Full state of the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<MediaState>,
+    /// What changed in the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<MediaEvent>,
+}
+
+impl EventRecord {
+    /// Wraps a message published by the manager, `None` for the messages
+    /// backends send to it
+    pub fn from_message(msg: &ManagerMessage, timestamp: SystemTime) -> Option<Self> {
+        let record = |kind: &str, session_id: Option<String>| Self {
+            kind: kind.to_string(),
+            session_id,
+            timestamp,
+            state: None,
+            event: None,
+        };
+
+        match msg {
+            ManagerMessage::SessionChanged(id) => Some(record("session_changed", id.clone())),
+            ManagerMessage::StateChanged(state) => Some(Self {
+                state: Some((**state).clone()),
+                ..record("state", Some(state.session_id.clone()))
+            }),
+            ManagerMessage::Event(event) => Some(Self {
+                event: Some((**event).clone()),
+                ..record(event.name(), Some(event.session_id().to_string()))
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Compares two states of the same session, `old` being `None` if `new` is
+/// the first one seen. An empty list means nothing worth telling anyone
+/// changed, the position merely moved on as expected.
+///
+/// # Example
+/// ```
+/// use window::media::{
+///     diff_states, FakeBackend, FakeScript, MediaBackend, MediaEvent, MediaState, SystemClock,
+/// };
+///
+/// let session = FakeBackend::new(FakeScript::default()).current_session().unwrap();
+/// let first = MediaState::read(&*session, &SystemClock).unwrap();
+/// assert_eq!(diff_states(None, &first).len(), 2);
+///
+/// session.play().unwrap();
+/// let playing = MediaState::read(&*session, &SystemClock).unwrap();
+/// assert!(diff_states(Some(&first), &playing)
+///     .iter()
+///     .any(|event| matches!(event, MediaEvent::StatusChanged { .. })));
+/// assert!(diff_states(Some(&playing), &playing).is_empty());
+/// ```
+pub fn diff_states(old: Option<&MediaState>, new: &MediaState) -> Vec<MediaEvent> {
+    let session_id = &new.session_id;
+    let mut events = vec![];
+
+    let same_track = old.is_some_and(|old| is_same_track(&old.media, &new.media));
+    match old {
+        Some(old) if same_track => {
+            if old.media != new.media || old.art_hash != new.art_hash {
+                events.push(MediaEvent::MetadataChanged {
+                    session_id: session_id.clone(),
+                    media: new.media.clone(),
+                    art_hash: new.art_hash.clone(),
+                });
+            }
+        }
+        _ => events.push(MediaEvent::TrackChanged {
+            session_id: session_id.clone(),
+            from: old.map(|old| old.media.clone()),
+            to: new.media.clone(),
+        }),
+    }
+
+    let status = old.map(|old| old.playback.playback_status);
+    if status != Some(new.playback.playback_status) {
+        events.push(MediaEvent::StatusChanged {
+            session_id: session_id.clone(),
+            from: status,
+            to: new.playback.playback_status,
+        });
+    }
+
+    let old = match old {
+        Some(old) => old,
+        None => return events,
+    };
+
+    let unchanged_playback = PlaybackInfoProps {
+        playback_status: new.playback.playback_status,
+        ..old.playback.clone()
+    };
+    if unchanged_playback != new.playback {
+        events.push(MediaEvent::PlaybackChanged {
+            session_id: session_id.clone(),
+            playback: new.playback.clone(),
+        });
+    }
+
+    if same_track {
+        let expected =
+            estimate_position(&old.timeline, &old.playback, new.timeline.last_updated_time);
+        if expected.abs_diff(new.timeline.pos) > SEEK_TOLERANCE {
+            events.push(MediaEvent::Seeked {
+                session_id: session_id.clone(),
+                from: expected,
+                to: new.timeline.pos,
+            });
+        }
+    }
+
+    events
+}
+
+/// Whether both describe the same track, even if the rest of the metadata
+/// differs
+fn is_same_track(a: &MediaProps, b: &MediaProps) -> bool {
+    a.title == b.title && a.artist == b.artist && a.album_title == b.album_title
+}
@@ -0,0 +1,588 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    controller::ThreadMessage,
+    media::{
+        position::scale, session::refused, ActiveControls, CommandFuture, ManagerMessage,
+        MediaBackend, MediaError, MediaProps, MediaSession, PlaybackInfoProps, PlaybackStatus,
+        PlaybackType, RepeatMode, Subscription, Thumbnail, TimelineProps,
+    },
+};
+
+/// A track in a [`FakeScript`]'s playlist
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+#[allow(missing_docs)]
+pub struct FakeTrack {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub album_artist: String,
+    pub duration_ms: u64,
+    /// Image file used as the track's thumbnail. Relative paths in a script
+    /// file are resolved from the script's directory.
+    pub art: Option<PathBuf>,
+}
+
+/// Something that happens to a fake session
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+#[allow(missing_docs)]
+pub enum FakeAction {
+    Play,
+    Pause,
+    TogglePlayPause,
+    Stop,
+    Next,
+    Previous,
+    Seek {
+        position_ms: u64,
+    },
+    Shuffle {
+        on: bool,
+    },
+    Repeat {
+        mode: RepeatMode,
+    },
+    Rate {
+        rate: f64,
+    },
+    Track(FakeTrack),
+    /// The player goes away, leaving the backend without a session
+    Close,
+    /// The player comes back after a [`FakeAction::Close`]
+    Open,
+}
+
+/// A [`FakeAction`] which is applied `at_ms` milliseconds after the script
+/// starts
+#[derive(Debug, Clone, Deserialize)]
+#[allow(missing_docs)]
+pub struct FakeEvent {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: FakeAction,
+}
+
+/// Script describing a fake session and what happens to it over time.
+///
+/// # Example
+/// ```
+/// use window::media::FakeScript;
+///
+/// let script: FakeScript = serde_json::from_str(r#"{
+///     "id": "demo",
+///     "playing": true,
+///     "tracks": [
+///         { "title": "First", "artist": "Someone", "duration_ms": 180000 },
+///         { "title": "Second", "artist": "Someone", "duration_ms": 240000 }
+///     ],
+///     "events": [
+///         { "at_ms": 1000, "action": "pause" },
+///         { "at_ms": 2000, "action": "seek", "position_ms": 30000 },
+///         { "at_ms": 3000, "action": "next" }
+///     ]
+/// }"#).unwrap();
+///
+/// assert_eq!(script.events.len(), 3);
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FakeScript {
+    /// Source app id the session reports
+    pub id: String,
+    /// Playlist the session skips through
+    pub tracks: Vec<FakeTrack>,
+    /// Whether the session is playing when it starts
+    pub playing: bool,
+    /// Whether the player starts closed, so that the backend has no session
+    /// until an `open` event
+    pub closed: bool,
+    /// Timed events, in any order
+    pub events: Vec<FakeEvent>,
+}
+
+impl Default for FakeScript {
+    fn default() -> Self {
+        Self {
+            id: "fake".to_string(),
+            tracks: vec![],
+            playing: false,
+            closed: false,
+            events: vec![],
+        }
+    }
+}
+
+type Listeners = Vec<(usize, crossbeam_channel::Sender<ThreadMessage>)>;
+
+#[derive(Debug)]
+struct FakeState {
+    id: String,
+    tracks: Vec<FakeTrack>,
+    track: usize,
+    status: PlaybackStatus,
+    pos: Duration,
+    last_updated: SystemTime,
+    shuffle: bool,
+    repeat: RepeatMode,
+    rate: f64,
+    open: bool,
+
+    next_listener: usize,
+    listeners: Listeners,
+    backend_listeners: Listeners,
+}
+
+impl FakeState {
+    fn current_track(&self) -> FakeTrack {
+        self.tracks.get(self.track).cloned().unwrap_or_default()
+    }
+
+    fn emit(&self, messages: &[fn(String) -> ManagerMessage]) {
+        for (_, tx) in &self.listeners {
+            for msg in messages {
+                tx.send(ThreadMessage::Media(msg(self.id.clone()))).ok();
+            }
+        }
+    }
+
+    /// Moves the position on by the time played since the last update, like a
+    /// real player does before it reports a new status or rate
+    fn settle(&mut self) {
+        let now = SystemTime::now();
+        if self.status == PlaybackStatus::Playing {
+            let elapsed = now.duration_since(self.last_updated).unwrap_or_default();
+            self.pos = self.pos.saturating_add(scale(elapsed, self.rate.max(0.0)));
+
+            let duration = Duration::from_millis(self.current_track().duration_ms);
+            if !duration.is_zero() {
+                self.pos = self.pos.min(duration);
+            }
+        }
+        self.last_updated = now;
+    }
+
+    fn set_status(&mut self, status: PlaybackStatus) {
+        self.settle();
+        self.status = status;
+        self.emit(&[ManagerMessage::PlaybackInfoChanged]);
+    }
+
+    fn set_open(&mut self, open: bool) {
+        if self.open == open {
+            return;
+        }
+
+        self.open = open;
+        for (_, tx) in &self.backend_listeners {
+            tx.send(ThreadMessage::Media(ManagerMessage::SessionsChanged))
+                .ok();
+        }
+    }
+
+    fn set_track(&mut self, track: usize) {
+        self.track = track;
+        self.pos = Duration::ZERO;
+        self.last_updated = SystemTime::now();
+        self.emit(&[
+            ManagerMessage::MediaChanged,
+            ManagerMessage::TimelineChanged,
+        ]);
+    }
+
+    fn apply(&mut self, action: &FakeAction) {
+        match action {
+            FakeAction::Play => self.set_status(PlaybackStatus::Playing),
+            FakeAction::Pause => self.set_status(PlaybackStatus::Paused),
+            FakeAction::TogglePlayPause => {
+                if self.status == PlaybackStatus::Playing {
+                    self.set_status(PlaybackStatus::Paused);
+                } else {
+                    self.set_status(PlaybackStatus::Playing);
+                }
+            }
+            FakeAction::Stop => {
+                self.set_status(PlaybackStatus::Stopped);
+                self.pos = Duration::ZERO;
+            }
+            FakeAction::Next => {
+                if self.track + 1 < self.tracks.len() {
+                    self.set_track(self.track + 1);
+                }
+            }
+            FakeAction::Previous => {
+                if self.track > 0 {
+                    self.set_track(self.track - 1);
+                }
+            }
+            FakeAction::Seek { position_ms } => {
+                let duration = Duration::from_millis(self.current_track().duration_ms);
+                self.pos = Duration::from_millis(*position_ms).min(duration);
+                self.last_updated = SystemTime::now();
+                self.emit(&[ManagerMessage::TimelineChanged]);
+            }
+            FakeAction::Shuffle { on } => {
+                self.shuffle = *on;
+                self.emit(&[ManagerMessage::PlaybackInfoChanged]);
+            }
+            FakeAction::Repeat { mode } => {
+                self.repeat = *mode;
+                self.emit(&[ManagerMessage::PlaybackInfoChanged]);
+            }
+            FakeAction::Rate { rate } => {
+                self.settle();
+                self.rate = *rate;
+                self.emit(&[ManagerMessage::PlaybackInfoChanged]);
+            }
+            FakeAction::Track(track) => {
+                if self.tracks.is_empty() {
+                    self.tracks.push(track.clone());
+                } else {
+                    self.tracks[self.track] = track.clone();
+                }
+                self.set_track(self.track);
+            }
+            FakeAction::Close => self.set_open(false),
+            FakeAction::Open => self.set_open(true),
+        }
+    }
+}
+
+/// Media backend with a single in-memory session, for tests and demos.
+///
+/// The session honours play, pause and skip commands and sends the same
+/// [`ManagerMessage`]s as a real player would. Closing the player leaves the
+/// backend without a session until it is opened again. The events of its
+/// [`FakeScript`] are replayed on a background thread once
+/// [`FakeBackend::start`] is called.
+///
+/// # Example
+/// ```
+/// use window::media::{FakeBackend, FakeScript, FakeTrack, MediaBackend, PlaybackStatus};
+///
+/// let backend = FakeBackend::new(FakeScript {
+///     tracks: vec![FakeTrack {
+///         title: "Song".to_string(),
+///         ..Default::default()
+///     }],
+///     ..Default::default()
+/// });
+///
+/// let session = backend.current_session().unwrap();
+/// session.play().unwrap();
+///
+/// assert_eq!(session.media_properties().unwrap().title, "Song");
+/// assert_eq!(
+///     session.playback_info().unwrap().playback_status,
+///     PlaybackStatus::Playing
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct FakeBackend {
+    state: Arc<Mutex<FakeState>>,
+    events: Vec<FakeEvent>,
+}
+
+impl FakeBackend {
+    /// Create a fake backend from a script without starting it
+    pub fn new(script: FakeScript) -> Self {
+        let status = if script.playing {
+            PlaybackStatus::Playing
+        } else {
+            PlaybackStatus::Paused
+        };
+
+        let mut events = script.events;
+        events.sort_by_key(|event| event.at_ms);
+
+        Self {
+            state: Arc::new(Mutex::new(FakeState {
+                id: script.id,
+                tracks: script.tracks,
+                track: 0,
+                status,
+                pos: Duration::ZERO,
+                last_updated: SystemTime::now(),
+                shuffle: false,
+                repeat: RepeatMode::None,
+                rate: 1.0,
+                open: !script.closed,
+
+                next_listener: 0,
+                listeners: vec![],
+                backend_listeners: vec![],
+            })),
+            events,
+        }
+    }
+
+    /// Read a [`FakeScript`] from a JSON file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, MediaError> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(|error| {
+            MediaError::Backend(format!("Could not open the fake session script: {}", error))
+        })?;
+        let mut script: FakeScript = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|error| {
+                MediaError::Backend(format!("The fake session script is not valid: {}", error))
+            })?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let tracks = script
+            .tracks
+            .iter_mut()
+            .chain(
+                script
+                    .events
+                    .iter_mut()
+                    .filter_map(|event| match &mut event.action {
+                        FakeAction::Track(track) => Some(track),
+                        _ => None,
+                    }),
+            );
+        for track in tracks {
+            if let Some(art) = &mut track.art {
+                *art = dir.join(&*art);
+            }
+        }
+
+        Ok(Self::new(script))
+    }
+
+    /// Apply an action to the session right away
+    pub fn apply(&self, action: &FakeAction) {
+        self.state.lock().unwrap().apply(action);
+    }
+
+    /// Replay the script's events on a background thread. The returned
+    /// handle finishes once the last event was applied.
+    pub fn start(&self) -> std::thread::JoinHandle<()> {
+        let state = self.state.clone();
+        let events = self.events.clone();
+
+        std::thread::spawn(move || {
+            let start = std::time::Instant::now();
+            for event in events {
+                let at = Duration::from_millis(event.at_ms);
+                if let Some(wait) = at.checked_sub(start.elapsed()) {
+                    std::thread::sleep(wait);
+                }
+                state.lock().unwrap().apply(&event.action);
+            }
+        })
+    }
+}
+
+impl MediaBackend for FakeBackend {
+    fn current_session(&self) -> Result<Box<dyn MediaSession>, MediaError> {
+        if !self.state.lock().unwrap().open {
+            return Err(MediaError::NoSession);
+        }
+
+        Ok(Box::new(FakeSession {
+            state: self.state.clone(),
+        }))
+    }
+
+    fn sessions(&self) -> Result<Vec<Box<dyn MediaSession>>, MediaError> {
+        match self.current_session() {
+            Ok(session) => Ok(vec![session]),
+            Err(MediaError::NoSession) => Ok(vec![]),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        Ok(subscribe(&self.state, tx, |state| {
+            &mut state.backend_listeners
+        }))
+    }
+}
+
+/// Adds `tx` to the listeners picked by `listeners` until the returned
+/// subscription is dropped
+fn subscribe(
+    state: &Arc<Mutex<FakeState>>,
+    tx: crossbeam_channel::Sender<ThreadMessage>,
+    listeners: fn(&mut FakeState) -> &mut Listeners,
+) -> Subscription {
+    let id = {
+        let mut state = state.lock().unwrap();
+        let id = state.next_listener;
+        state.next_listener += 1;
+        listeners(&mut state).push((id, tx));
+        id
+    };
+
+    let state = state.clone();
+    Subscription::new(move || {
+        listeners(&mut state.lock().unwrap()).retain(|(listener, _)| *listener != id);
+    })
+}
+
+/// The session of a [`FakeBackend`]
+#[derive(Debug, Clone)]
+pub struct FakeSession {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeSession {
+    fn apply(&self, action: FakeAction) -> CommandFuture<'_> {
+        Box::pin(async move {
+            self.state.lock().unwrap().apply(&action);
+            Ok(true)
+        })
+    }
+}
+
+impl MediaSession for FakeSession {
+    fn source_app_id(&self) -> String {
+        self.state.lock().unwrap().id.clone()
+    }
+
+    fn play_async(&self) -> CommandFuture<'_> {
+        self.apply(FakeAction::Play)
+    }
+
+    fn pause_async(&self) -> CommandFuture<'_> {
+        self.apply(FakeAction::Pause)
+    }
+
+    fn next_track_async(&self) -> CommandFuture<'_> {
+        self.apply(FakeAction::Next)
+    }
+
+    fn previous_track_async(&self) -> CommandFuture<'_> {
+        self.apply(FakeAction::Previous)
+    }
+
+    fn stop_async(&self) -> CommandFuture<'_> {
+        self.apply(FakeAction::Stop)
+    }
+
+    fn toggle_play_pause_async(&self) -> CommandFuture<'_> {
+        self.apply(FakeAction::TogglePlayPause)
+    }
+
+    // The fake session has no fast-forward, rewind, channels or recording
+
+    fn fast_forward_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn rewind_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn channel_up_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn channel_down_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn record_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn set_position_async(&self, position: Duration) -> CommandFuture<'_> {
+        self.apply(FakeAction::Seek {
+            position_ms: position.as_millis() as u64,
+        })
+    }
+
+    fn set_shuffle_async(&self, shuffle: bool) -> CommandFuture<'_> {
+        self.apply(FakeAction::Shuffle { on: shuffle })
+    }
+
+    fn set_repeat_async(&self, mode: RepeatMode) -> CommandFuture<'_> {
+        self.apply(FakeAction::Repeat { mode })
+    }
+
+    fn set_playback_rate_async(&self, rate: f64) -> CommandFuture<'_> {
+        self.apply(FakeAction::Rate { rate })
+    }
+
+    fn media_properties(&self) -> Result<MediaProps, MediaError> {
+        let state = self.state.lock().unwrap();
+        let track = state.current_track();
+
+        Ok(MediaProps {
+            album_artist: track.album_artist,
+            album_title: track.album,
+            album_track_count: state.tracks.len() as i32,
+            artist: track.artist,
+            playback_type: PlaybackType::Music,
+            subtitle: String::new(),
+            title: track.title,
+            track_number: state.track as i32 + 1,
+        })
+    }
+
+    fn thumbnail(&self, _max_size: Option<u32>) -> Option<Thumbnail> {
+        let art = self.state.lock().unwrap().current_track().art?;
+        std::fs::read(art).ok().map(Thumbnail::new)
+    }
+
+    fn thumbnail_id(&self) -> Option<String> {
+        let art = self.state.lock().unwrap().current_track().art?;
+        Some(art.display().to_string())
+    }
+
+    fn timeline_properties(&self) -> Result<TimelineProps, MediaError> {
+        let state = self.state.lock().unwrap();
+        let duration = Duration::from_millis(state.current_track().duration_ms);
+
+        Ok(TimelineProps {
+            last_updated_time: state.last_updated,
+            pos: state.pos,
+            max_seek_time: duration,
+            min_seek_time: Duration::ZERO,
+            endtime: duration,
+            start_time: Duration::ZERO,
+        })
+    }
+
+    fn playback_info(&self) -> Result<PlaybackInfoProps, MediaError> {
+        let state = self.state.lock().unwrap();
+
+        Ok(PlaybackInfoProps {
+            auto_repeat_mode: state.repeat,
+            active_controls: ActiveControls {
+                is_play_enabled: true,
+                is_pause_enabled: true,
+                is_stop_enabled: true,
+                is_play_pause_toggle_enabled: true,
+                is_next_enabled: state.track + 1 < state.tracks.len(),
+                is_previous_enabled: state.track > 0,
+                is_shuffle_enabled: true,
+                is_repeat_enabled: true,
+                is_playback_rate_enabled: true,
+                is_playback_position_enabled: true,
+                ..Default::default()
+            },
+            shuffle_active: state.shuffle,
+            playback_status: state.status,
+            playback_type: PlaybackType::Music,
+            playback_rate: state.rate,
+        })
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        Ok(subscribe(&self.state, tx, |state| &mut state.listeners))
+    }
+}
@@ -0,0 +1,417 @@
+use futures::executor::block_on;
+
+use windows::{
+    core::Interface,
+    Foundation::{IAsyncOperation, TypedEventHandler},
+    Graphics::Imaging::{BitmapDecoder, BitmapEncoder, BitmapInterpolationMode},
+    Media::{
+        Control::{
+            GlobalSystemMediaTransportControlsSession,
+            GlobalSystemMediaTransportControlsSessionManager,
+            GlobalSystemMediaTransportControlsSessionPlaybackStatus,
+        },
+        MediaPlaybackAutoRepeatMode, MediaPlaybackType,
+    },
+    Storage::Streams::{DataReader, IRandomAccessStream, InMemoryRandomAccessStream},
+};
+
+use crate::{
+    controller::ThreadMessage,
+    media::{
+        time::{duration_to_ticks, ticks_to_duration, ticks_to_system_time},
+        ActiveControls, CommandFuture, ManagerMessage, MediaBackend, MediaError, MediaProps,
+        MediaSession, PlaybackInfoProps, PlaybackStatus, PlaybackType, RepeatMode, Subscription,
+        Thumbnail, TimelineProps,
+    },
+};
+
+/// Media backend using Windows' Global System Media Transport Controls
+#[derive(Debug)]
+pub struct GsmtcBackend {
+    manager: GlobalSystemMediaTransportControlsSessionManager,
+}
+
+impl GsmtcBackend {
+    /// Connect to the system's media session manager
+    pub fn new() -> Result<Self, MediaError> {
+        let request = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()
+            .map_err(MediaError::backend)?;
+        let manager = block_on(request).map_err(MediaError::backend)?;
+
+        Ok(Self { manager })
+    }
+}
+
+impl MediaBackend for GsmtcBackend {
+    fn current_session(&self) -> Result<Box<dyn MediaSession>, MediaError> {
+        // There being no current session shows up as a null result
+        match self.manager.GetCurrentSession() {
+            Ok(session) => Ok(Box::new(GsmtcSession { session })),
+            Err(_) => Err(MediaError::NoSession),
+        }
+    }
+
+    fn sessions(&self) -> Result<Vec<Box<dyn MediaSession>>, MediaError> {
+        let sessions = self.manager.GetSessions().map_err(MediaError::backend)?;
+
+        Ok(sessions
+            .into_iter()
+            .map(|session| Box::new(GsmtcSession { session }) as Box<dyn MediaSession>)
+            .collect())
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        let new_tx = tx.clone();
+        let session_changed = self
+            .manager
+            .CurrentSessionChanged(TypedEventHandler::new(move |_, _| {
+                new_tx
+                    .send(ThreadMessage::Media(ManagerMessage::SessionsChanged))
+                    .ok();
+                Ok(())
+            }))
+            .map_err(MediaError::backend)?;
+
+        let sessions_changed = self
+            .manager
+            .SessionsChanged(TypedEventHandler::new(move |_, _| {
+                tx.send(ThreadMessage::Media(ManagerMessage::SessionsChanged))
+                    .ok();
+                Ok(())
+            }))
+            .map_err(MediaError::backend)?;
+
+        let manager = self.manager.clone();
+        Ok(Subscription::new(move || {
+            manager.RemoveCurrentSessionChanged(session_changed).ok();
+            manager.RemoveSessionsChanged(sessions_changed).ok();
+        }))
+    }
+}
+
+/// A single Global System Media Transport Controls session
+#[derive(Debug)]
+pub struct GsmtcSession {
+    session: GlobalSystemMediaTransportControlsSession,
+}
+
+/// Waits for the session to answer a command. Sessions answer `false` when
+/// they refuse it.
+fn answer(operation: windows::core::Result<IAsyncOperation<bool>>) -> CommandFuture<'static> {
+    Box::pin(async move {
+        operation
+            .map_err(MediaError::backend)?
+            .await
+            .map_err(MediaError::backend)
+    })
+}
+
+/// Reads a property which the session may not report
+fn property<T>(value: windows::core::Result<T>, name: &'static str) -> Result<T, MediaError> {
+    value.map_err(|_| MediaError::PropertyUnavailable(name))
+}
+
+fn playback_status(
+    status: GlobalSystemMediaTransportControlsSessionPlaybackStatus,
+) -> PlaybackStatus {
+    match status {
+        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Closed => PlaybackStatus::Closed,
+        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Opened => PlaybackStatus::Opened,
+        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Changing => {
+            PlaybackStatus::Changing
+        }
+        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Stopped => PlaybackStatus::Stopped,
+        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Playing => PlaybackStatus::Playing,
+        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Paused => PlaybackStatus::Paused,
+        // Statuses newer versions of Windows might add
+        _ => PlaybackStatus::Closed,
+    }
+}
+
+fn playback_type(playback_type: MediaPlaybackType) -> PlaybackType {
+    match playback_type {
+        MediaPlaybackType::Music => PlaybackType::Music,
+        MediaPlaybackType::Video => PlaybackType::Video,
+        MediaPlaybackType::Image => PlaybackType::Image,
+        _ => PlaybackType::Unknown,
+    }
+}
+
+impl MediaSession for GsmtcSession {
+    fn source_app_id(&self) -> String {
+        self.session
+            .SourceAppUserModelId()
+            .map(|id| id.to_string())
+            .unwrap_or_default()
+    }
+
+    fn play_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryPlayAsync())
+    }
+
+    fn pause_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryPauseAsync())
+    }
+
+    fn next_track_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TrySkipNextAsync())
+    }
+
+    fn previous_track_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TrySkipPreviousAsync())
+    }
+
+    fn stop_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryStopAsync())
+    }
+
+    fn toggle_play_pause_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryTogglePlayPauseAsync())
+    }
+
+    fn fast_forward_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryFastForwardAsync())
+    }
+
+    fn rewind_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryRewindAsync())
+    }
+
+    fn channel_up_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryChangeChannelUpAsync())
+    }
+
+    fn channel_down_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryChangeChannelDownAsync())
+    }
+
+    fn record_async(&self) -> CommandFuture<'_> {
+        answer(self.session.TryRecordAsync())
+    }
+
+    fn set_position_async(&self, position: std::time::Duration) -> CommandFuture<'_> {
+        answer(
+            self.session
+                .TryChangePlaybackPositionAsync(duration_to_ticks(position)),
+        )
+    }
+
+    fn set_shuffle_async(&self, shuffle: bool) -> CommandFuture<'_> {
+        answer(self.session.TryChangeShuffleActiveAsync(shuffle))
+    }
+
+    fn set_repeat_async(&self, mode: RepeatMode) -> CommandFuture<'_> {
+        let mode = match mode {
+            RepeatMode::None => MediaPlaybackAutoRepeatMode::None,
+            RepeatMode::Track => MediaPlaybackAutoRepeatMode::Track,
+            RepeatMode::List => MediaPlaybackAutoRepeatMode::List,
+        };
+        answer(self.session.TryChangeAutoRepeatModeAsync(mode))
+    }
+
+    fn set_playback_rate_async(&self, rate: f64) -> CommandFuture<'_> {
+        answer(self.session.TryChangePlaybackRateAsync(rate))
+    }
+
+    fn media_properties(&self) -> Result<MediaProps, MediaError> {
+        let request = self
+            .session
+            .TryGetMediaPropertiesAsync()
+            .map_err(MediaError::backend)?;
+        let props = property(block_on(request), "media properties")?;
+
+        Ok(MediaProps {
+            album_artist: property(props.AlbumArtist(), "album artist")?.to_string(),
+            album_title: property(props.AlbumTitle(), "album title")?.to_string(),
+            album_track_count: property(props.AlbumTrackCount(), "album track count")?,
+            artist: property(props.Artist(), "artist")?.to_string(),
+            playback_type: props
+                .PlaybackType()
+                .and_then(|t| t.Value())
+                .map(playback_type)
+                .unwrap_or_default(),
+            subtitle: property(props.Subtitle(), "subtitle")?.to_string(),
+            title: property(props.Title(), "title")?.to_string(),
+            track_number: property(props.TrackNumber(), "track number")?,
+        })
+    }
+
+    fn thumbnail(&self, max_size: Option<u32>) -> Option<Thumbnail> {
+        let props = block_on(self.session.TryGetMediaPropertiesAsync().ok()?).ok()?;
+        let stream = block_on(props.Thumbnail().ok()?.OpenReadAsync().ok()?).ok()?;
+        let stream: IRandomAccessStream = stream.cast().ok()?;
+
+        let data = match max_size {
+            Some(max_size) => scale_image(&stream, max_size)?,
+            None => read_stream(&stream)?,
+        };
+        Some(Thumbnail::new(data))
+    }
+
+    fn scales_thumbnails(&self) -> bool {
+        true
+    }
+
+    fn timeline_properties(&self) -> Result<TimelineProps, MediaError> {
+        let props = property(self.session.GetTimelineProperties(), "timeline")?;
+
+        Ok(TimelineProps {
+            last_updated_time: ticks_to_system_time(
+                property(props.LastUpdatedTime(), "last updated time")?.UniversalTime,
+            ),
+            pos: ticks_to_duration(property(props.Position(), "position")?.Duration),
+            max_seek_time: ticks_to_duration(
+                property(props.MaxSeekTime(), "max seek time")?.Duration,
+            ),
+            min_seek_time: ticks_to_duration(
+                property(props.MinSeekTime(), "min seek time")?.Duration,
+            ),
+            endtime: ticks_to_duration(property(props.EndTime(), "end time")?.Duration),
+            start_time: ticks_to_duration(property(props.StartTime(), "start time")?.Duration),
+        })
+    }
+
+    fn playback_info(&self) -> Result<PlaybackInfoProps, MediaError> {
+        let info = property(self.session.GetPlaybackInfo(), "playback info")?;
+        let controls = property(info.Controls(), "controls")?;
+
+        Ok(PlaybackInfoProps {
+            auto_repeat_mode: match info.AutoRepeatMode().and_then(|m| m.Value()) {
+                Ok(MediaPlaybackAutoRepeatMode::Track) => RepeatMode::Track,
+                Ok(MediaPlaybackAutoRepeatMode::List) => RepeatMode::List,
+                _ => RepeatMode::None,
+            },
+            active_controls: ActiveControls {
+                is_play_enabled: controls.IsPlayEnabled().unwrap_or(false),
+                is_pause_enabled: controls.IsPauseEnabled().unwrap_or(false),
+                is_stop_enabled: controls.IsStopEnabled().unwrap_or(false),
+                is_record_enabled: controls.IsRecordEnabled().unwrap_or(false),
+                is_fast_forward_enabled: controls.IsFastForwardEnabled().unwrap_or(false),
+                is_rewind_enabled: controls.IsRewindEnabled().unwrap_or(false),
+                is_next_enabled: controls.IsNextEnabled().unwrap_or(false),
+                is_previous_enabled: controls.IsPreviousEnabled().unwrap_or(false),
+                is_channel_up_enabled: controls.IsChannelUpEnabled().unwrap_or(false),
+                is_channel_down_enabled: controls.IsChannelDownEnabled().unwrap_or(false),
+                is_play_pause_toggle_enabled: controls.IsPlayPauseToggleEnabled().unwrap_or(false),
+                is_shuffle_enabled: controls.IsShuffleEnabled().unwrap_or(false),
+                is_repeat_enabled: controls.IsRepeatEnabled().unwrap_or(false),
+                is_playback_rate_enabled: controls.IsPlaybackRateEnabled().unwrap_or(false),
+                is_playback_position_enabled: controls.IsPlaybackPositionEnabled().unwrap_or(false),
+            },
+            shuffle_active: info
+                .IsShuffleActive()
+                .and_then(|s| s.Value())
+                .unwrap_or(false),
+            playback_status: playback_status(property(info.PlaybackStatus(), "playback status")?),
+            playback_type: info
+                .PlaybackType()
+                .and_then(|t| t.Value())
+                .map(playback_type)
+                .unwrap_or_default(),
+            playback_rate: info.PlaybackRate().and_then(|r| r.Value()).unwrap_or(1.0),
+        })
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        let id = self.source_app_id();
+
+        let new_tx = tx.clone();
+        let new_id = id.clone();
+        let media_changed = self
+            .session
+            .MediaPropertiesChanged(TypedEventHandler::new(move |_, _| {
+                new_tx
+                    .send(ThreadMessage::Media(ManagerMessage::MediaChanged(
+                        new_id.clone(),
+                    )))
+                    .ok();
+                Ok(())
+            }))
+            .map_err(MediaError::backend)?;
+
+        let new_tx = tx.clone();
+        let new_id = id.clone();
+        let timeline_changed = self
+            .session
+            .TimelinePropertiesChanged(TypedEventHandler::new(move |_, _| {
+                new_tx
+                    .send(ThreadMessage::Media(ManagerMessage::TimelineChanged(
+                        new_id.clone(),
+                    )))
+                    .ok();
+                Ok(())
+            }))
+            .map_err(MediaError::backend)?;
+
+        let playbackinfo_changed = self
+            .session
+            .PlaybackInfoChanged(TypedEventHandler::new(move |_, _| {
+                tx.send(ThreadMessage::Media(ManagerMessage::PlaybackInfoChanged(
+                    id.clone(),
+                )))
+                .ok();
+                Ok(())
+            }))
+            .map_err(MediaError::backend)?;
+
+        let session = self.session.clone();
+        Ok(Subscription::new(move || {
+            session.RemoveMediaPropertiesChanged(media_changed).ok();
+            session
+                .RemoveTimelinePropertiesChanged(timeline_changed)
+                .ok();
+            session.RemovePlaybackInfoChanged(playbackinfo_changed).ok();
+        }))
+    }
+}
+
+/// Reads a whole stream into memory
+fn read_stream(stream: &IRandomAccessStream) -> Option<Vec<u8>> {
+    let size = stream.Size().ok()? as u32;
+    let reader = DataReader::CreateDataReader(&stream.GetInputStreamAt(0).ok()?).ok()?;
+    reader.LoadAsync(size).ok()?.get().ok()?;
+
+    let mut data = vec![0; size as usize];
+    reader.ReadBytes(&mut data).ok()?;
+    Some(data)
+}
+
+/// Re-encodes an image as a PNG so neither side is larger than `max_size`.
+/// Images which are small enough already are returned as they are.
+fn scale_image(stream: &IRandomAccessStream, max_size: u32) -> Option<Vec<u8>> {
+    let decoder = block_on(BitmapDecoder::CreateAsync(stream).ok()?).ok()?;
+    let width = decoder.PixelWidth().ok()?;
+    let height = decoder.PixelHeight().ok()?;
+    if width <= max_size && height <= max_size {
+        return read_stream(stream);
+    }
+
+    let scale = max_size as f64 / width.max(height) as f64;
+    let bitmap = block_on(decoder.GetSoftwareBitmapAsync().ok()?).ok()?;
+
+    let output = InMemoryRandomAccessStream::new().ok()?;
+    let encoder =
+        block_on(BitmapEncoder::CreateAsync(BitmapEncoder::PngEncoderId().ok()?, &output).ok()?)
+            .ok()?;
+    encoder.SetSoftwareBitmap(&bitmap).ok()?;
+
+    let transform = encoder.BitmapTransform().ok()?;
+    transform
+        .SetScaledWidth(((width as f64 * scale).round() as u32).max(1))
+        .ok()?;
+    transform
+        .SetScaledHeight(((height as f64 * scale).round() as u32).max(1))
+        .ok()?;
+    transform
+        .SetInterpolationMode(BitmapInterpolationMode::Fant)
+        .ok()?;
+    block_on(encoder.FlushAsync().ok()?).ok()?;
+
+    read_stream(&output.cast().ok()?)
+}
@@ -1,376 +1,342 @@
-use futures::executor::block_on;
-use serde::Serialize;
-use windows::{
-    Foundation::{EventRegistrationToken, TypedEventHandler},
-    Media::{
-        Control::{
-            GlobalSystemMediaTransportControlsSession,
-            GlobalSystemMediaTransportControlsSessionManager,
-            GlobalSystemMediaTransportControlsSessionPlaybackStatus,
-        },
-        MediaPlaybackType,
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::RecvTimeoutError;
+
+use crate::{
+    controller::ThreadMessage,
+    media::{
+        diff_states, ArtCache, EventKind, MediaBackend, MediaError, MediaEvent, MediaSession,
+        MediaState, Subscription, SystemClock,
     },
 };
 
-use crate::controller::ThreadMessage;
+/// How long the manager waits for more events of a session before reading
+/// its state, unless told otherwise with [`Manager::coalesce_window`]
+pub const DEFAULT_COALESCE_WINDOW: Duration = Duration::from_millis(100);
 
-/// Messages that the MediaManager can send
-#[derive(Debug, Clone, Copy)]
+/// Messages that the MediaManager can send. Session specific messages carry
+/// the [`MediaSession::source_app_id`] of the session they came from.
+#[derive(Debug, Clone)]
 #[allow(missing_docs)]
 pub enum ManagerMessage {
-    SessionChanged,
-    TimelineChanged,
-    PlaybackInfoChanged,
-    MediaChanged,
+    /// Sent by backends when a session appeared or went away, or the current
+    /// session changed
+    SessionsChanged,
+    /// Sent by the [`Manager`] once it watches a different current session.
+    /// Carries its app id, or `None` when there is no session at all.
+    SessionChanged(Option<String>),
+    TimelineChanged(String),
+    PlaybackInfoChanged(String),
+    MediaChanged(String),
+    /// Published by the [`Manager`] with the full state of a session whenever
+    /// something in it changed, followed by the events describing what did
+    StateChanged(Box<MediaState>),
+    /// Published by the [`Manager`] for every change between two states of a
+    /// session
+    Event(Box<MediaEvent>),
 }
 
-/// Media Manager.
-#[derive(Debug)]
-pub struct Manager {
-    current_session: GlobalSystemMediaTransportControlsSession,
-    manager: GlobalSystemMediaTransportControlsSessionManager,
-    media_changed: EventRegistrationToken,
-    session_changed: EventRegistrationToken,
-    timeline_changed: EventRegistrationToken,
-    playbackinfo_changed: EventRegistrationToken,
-
-    tx: crossbeam_channel::Sender<ThreadMessage>,
-    rx: crossbeam_channel::Receiver<ThreadMessage>,
+impl ManagerMessage {
+    /// Which kind of message this is, `None` for the messages backends send to
+    /// the manager
+    pub fn kind(&self) -> Option<EventKind> {
+        match self {
+            ManagerMessage::SessionChanged(_) => Some(EventKind::Session),
+            ManagerMessage::StateChanged(_) => Some(EventKind::State),
+            ManagerMessage::Event(event) => Some(event.kind()),
+            _ => None,
+        }
+    }
 }
 
-#[derive(Serialize)]
-struct MediaProps {
-    // TODO Maybe check out adding thumbnails
-    album_artist: String,   // HSTRING -> String
-    album_title: String,    // HSTRING -> String
-    album_track_count: i32, // (UNCHANGED)
-    artist: String,         // HSTRING -> String
-    playback_type: i32,     // MediaPlaybackType -> i32
-    subtitle: String,       // HSTRING -> String
-    title: String,          // HSTRING -> String
-    track_number: i32,      // (UNCHANGED)
-}
+/// Receives everything a [`Manager`] publishes
+struct Subscriber(Box<dyn FnMut(&ManagerMessage)>);
 
-#[derive(Serialize)]
-struct TimelineProps {
-    last_updated_time: i32, // DateTime -> i32
-    pos: i32,               // TimeSpan -> i32
-    max_seek_time: i32,     // TimeSpan -> i32
-    min_seek_time: i32,     // TimeSpan -> i32
-    endtime: i32,           // TimeSpan -> i32
-    start_time: i32,        // TimeSpan -> i32
+impl std::fmt::Debug for Subscriber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Subscriber")
+    }
 }
 
-#[derive(Serialize)]
-struct PlaybackInfoProps {
-    auto_repeat_mode: i32,           // MediaPlaybackAutoRepeatMode -> i32
-    active_controls: ActiveControls, // GlobalSystemMediaTransportControlsSessionPlaybackControls -> ActiveControls
-    shuffle_active: bool,            // (UNCHANGED)
-    playback_status: i32,            // MediaPlaybackStatus -> i32
-    playback_type: i32,              // MediaPlaybackType -> i32
-    playback_rate: f64,              // (UNCHANGED)
+/// A session together with the listener forwarding its events
+#[derive(Debug)]
+struct WatchedSession {
+    // Declared first so the listener is detached before its session is dropped
+    _listener: Subscription,
+    id: String,
+    session: Box<dyn MediaSession>,
+    art: ArtCache,
 }
 
-#[derive(Serialize)]
-struct ActiveControls {
-    is_play_enabled: bool,
-    is_pause_enabled: bool,
-    is_stop_enabled: bool,
-    is_record_enabled: bool,
-    is_fast_forward_enabled: bool,
-    is_rewind_enabled: bool,
-    is_next_enabled: bool,
-    is_previous_enabled: bool,
-    is_channel_up_enabled: bool,
-    is_channel_down_enabled: bool,
-    is_play_pause_toggle_enabled: bool,
-    is_shuffle_enabled: bool,
-    is_repeat_enabled: bool,
-    is_playback_rate_enabled: bool,
-    is_playback_position_enabled: bool,
+/// Media Manager.
+///
+/// Watches sessions and publishes [`ManagerMessage::SessionChanged`],
+/// [`ManagerMessage::StateChanged`] and [`ManagerMessage::Event`] to the other
+/// threads of the thread controller, through `tx`, and to its subscribers.
+///
+/// Sessions tend to report the same change several times in a row, so the
+/// events of a session are collected for a short window before its state is
+/// read, and the state is only published when it differs from the last one.
+#[derive(Debug)]
+pub struct Manager {
+    // Listeners are declared first so they are detached before the backend
+    // they belong to is dropped
+    sessions: Vec<WatchedSession>,
+    _backend_listener: Subscription,
+    backend: Box<dyn MediaBackend>,
+    all_sessions: bool,
+    /// App id of the current session, `None` while there is no session
+    current: Option<String>,
+    subscribers: Vec<Subscriber>,
+    coalesce: Duration,
+    /// Sessions which reported changes, with when their state will be read
+    pending: HashMap<String, Instant>,
+    /// Last published state of each session
+    states: HashMap<String, MediaState>,
+
+    tx: crossbeam_channel::Sender<ThreadMessage>,
+    rx: crossbeam_channel::Receiver<ThreadMessage>,
 }
 
 impl Manager {
-    /// Create a new media manager
+    /// Create a new media manager using the platform's media backend
     pub fn new(
         tx: crossbeam_channel::Sender<ThreadMessage>,
         rx: crossbeam_channel::Receiver<ThreadMessage>,
-    ) -> Self {
-        let manager =
-            block_on(GlobalSystemMediaTransportControlsSessionManager::RequestAsync().unwrap())
-                .unwrap();
-        // TODO wait until a session is availible if there isn't one on creation
-        let current_session = manager.GetCurrentSession().unwrap();
+    ) -> Result<Self, MediaError> {
+        Self::with_backend(crate::media::default_backend()?, tx, rx)
+    }
 
+    /// Create a new media manager which watches the current session of
+    /// `backend`. Without any session the manager waits for one to appear.
+    pub fn with_backend(
+        backend: Box<dyn MediaBackend>,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+        rx: crossbeam_channel::Receiver<ThreadMessage>,
+    ) -> Result<Self, MediaError> {
         // Add event listeners
-        let new_tx = tx.clone();
-        let media_changed = current_session
-            .MediaPropertiesChanged(TypedEventHandler::new(move |_, _| {
-                new_tx
-                    .send(ThreadMessage::Media(ManagerMessage::MediaChanged))
-                    .unwrap();
-                Ok(())
-            }))
-            .unwrap();
-
-        let new_tx = tx.clone();
-        let timeline_changed = current_session
-            .TimelinePropertiesChanged(TypedEventHandler::new(move |_, _| {
-                new_tx
-                    .send(ThreadMessage::Media(ManagerMessage::TimelineChanged))
-                    .unwrap();
-                Ok(())
-            }))
-            .unwrap();
-
-        let new_tx = tx.clone();
-        let playbackinfo_changed = current_session
-            .PlaybackInfoChanged(TypedEventHandler::new(move |_, _| {
-                new_tx
-                    .send(ThreadMessage::Media(ManagerMessage::PlaybackInfoChanged))
-                    .unwrap();
-                Ok(())
-            }))
-            .unwrap();
-
-        let new_tx = tx.clone();
-        let session_changed = manager
-            .CurrentSessionChanged(TypedEventHandler::new(move |_, _| {
-                new_tx
-                    .send(ThreadMessage::Media(ManagerMessage::SessionChanged))
-                    .unwrap();
-                Ok(())
-            }))
-            .unwrap();
-
-        println!("[Media Manager] Spawned new media manager");
-
-        Self {
-            current_session,
-            manager,
-            media_changed,
-            session_changed,
-            timeline_changed,
-            playbackinfo_changed,
+        let backend_listener = backend.listen(tx.clone())?;
+
+        let mut manager = Self {
+            sessions: vec![],
+            _backend_listener: backend_listener,
+            backend,
+            all_sessions: false,
+            current: None,
+            subscribers: vec![],
+            coalesce: DEFAULT_COALESCE_WINDOW,
+            pending: HashMap::new(),
+            states: HashMap::new(),
 
             tx,
             rx,
-        }
+        };
+        manager.attach_sessions()?;
+
+        eprintln!("[Media Manager] Spawned new media manager");
+
+        Ok(manager)
+    }
+
+    /// Call `callback` with every message the manager publishes
+    pub fn on_event<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&ManagerMessage) + 'static,
+    {
+        self.subscribers.push(Subscriber(Box::new(callback)));
+
+        self
+    }
+
+    /// Send every message the manager publishes to `tx`
+    pub fn subscribe(self, tx: crossbeam_channel::Sender<ManagerMessage>) -> Self {
+        self.on_event(move |msg| {
+            tx.send(msg.clone()).ok();
+        })
+    }
+
+    /// Collect the events of a session for `window` before reading its state.
+    /// Zero only merges events which are already queued.
+    pub fn coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce = window;
+
+        self
     }
 
-    /// Start a thread blocking event loop
+    /// Watch every session of the backend instead of only the current one
+    pub fn watch_all_sessions(mut self) -> Result<Self, MediaError> {
+        self.all_sessions = true;
+        self.attach_sessions()?;
+
+        Ok(self)
+    }
+
+    /// Start a thread blocking event loop. Publishes the current session and
+    /// the state of every watched session first.
     pub fn start_sync(&mut self) {
+        self.announce_session();
+        let ids: Vec<String> = self
+            .sessions
+            .iter()
+            .map(|watched| watched.id.clone())
+            .collect();
+        for id in ids {
+            self.state_changed(&id, true);
+        }
+
         loop {
-            let msg = self.rx.recv().unwrap();
+            let msg = match self.pending.values().min() {
+                Some(&deadline) => match self.rx.recv_deadline(deadline) {
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.read_pending();
+                        continue;
+                    }
+                    msg => msg.unwrap(),
+                },
+                None => self.rx.recv().unwrap(),
+            };
 
             match msg {
                 ThreadMessage::Stop => {
-                    println!("[Media Manager] Stopping Manager...");
-                    drop(self);
+                    eprintln!("[Media Manager] Stopping Manager...");
                     break;
                 }
-                ThreadMessage::Media(ManagerMessage::SessionChanged) => {
-                    println!(
+                ThreadMessage::Media(ManagerMessage::SessionsChanged) => {
+                    eprintln!(
                         "[Media Manager] Session changed... Attempting to update session info."
                     );
                     self.session_changed();
                 }
-                ThreadMessage::Media(ManagerMessage::TimelineChanged) => {
-                    Self::timeline_changed().unwrap();
-                }
-                ThreadMessage::Media(ManagerMessage::PlaybackInfoChanged) => {
-                    Self::playback_info_changed().unwrap();
-                }
-                ThreadMessage::Media(ManagerMessage::MediaChanged) => {
-                    Self::media_props_changed().unwrap();
+                ThreadMessage::Media(ManagerMessage::TimelineChanged(id))
+                | ThreadMessage::Media(ManagerMessage::PlaybackInfoChanged(id))
+                | ThreadMessage::Media(ManagerMessage::MediaChanged(id)) => {
+                    let deadline = Instant::now() + self.coalesce;
+                    self.pending.entry(id).or_insert(deadline);
                 }
                 _ => (),
             }
         }
     }
 
+    /// Replace the watched sessions with the backend's current ones. Having no
+    /// session at all is not an error, the manager then watches nothing until
+    /// the backend reports a new session.
+    fn attach_sessions(&mut self) -> Result<(), MediaError> {
+        let current = match self.backend.current_session() {
+            Ok(session) => Some(session),
+            Err(MediaError::NoSession) => None,
+            Err(error) => return Err(error),
+        };
+        self.current = current.as_ref().map(|session| session.source_app_id());
+
+        let sessions = if self.all_sessions {
+            self.backend.sessions()?
+        } else {
+            current.into_iter().collect()
+        };
+
+        // Drop old event listeners before attaching the new ones
+        self.sessions.clear();
+        let ids: Vec<String> = sessions.iter().map(|s| s.source_app_id()).collect();
+        self.pending.retain(|id, _| ids.contains(id));
+        self.states.retain(|id, _| ids.contains(id));
+        for session in sessions {
+            self.sessions.push(WatchedSession {
+                _listener: session.listen(self.tx.clone())?,
+                id: session.source_app_id(),
+                session,
+                art: ArtCache::default(),
+            });
+        }
+
+        Ok(())
+    }
+
     fn session_changed(&mut self) {
-        // Drop old event listeners
-        self.current_session
-            .RemoveMediaPropertiesChanged(self.media_changed)
-            .unwrap();
-        self.current_session
-            .RemoveTimelinePropertiesChanged(self.timeline_changed)
-            .unwrap();
-        self.current_session
-            .RemoveTimelinePropertiesChanged(self.playbackinfo_changed)
-            .unwrap();
-
-        // Re-add event listeners to the new session
-        // TODO PANIC: when closing all sessions
-        //        Solution: wait for new sessions in a blocked loop
-        //              --> this for when you start the manager without a session too
-        //              --> i think making a function for this is the right way
-        let current_session = self.manager.GetCurrentSession().unwrap();
-
-        let new_tx = self.tx.clone();
-        let media_changed = current_session
-            .MediaPropertiesChanged(TypedEventHandler::new(move |_, _| {
-                new_tx
-                    .send(ThreadMessage::Media(ManagerMessage::MediaChanged))
-                    .unwrap();
-                Ok(())
-            }))
-            .unwrap();
-
-        let new_tx = self.tx.clone();
-        let timeline_changed = current_session
-            .TimelinePropertiesChanged(TypedEventHandler::new(move |_, _| {
-                new_tx
-                    .send(ThreadMessage::Media(ManagerMessage::TimelineChanged))
-                    .unwrap();
-                Ok(())
-            }))
-            .unwrap();
-
-        let new_tx = self.tx.clone();
-        let playbackinfo_changed = current_session
-            .PlaybackInfoChanged(TypedEventHandler::new(move |_, _| {
-                new_tx
-                    .send(ThreadMessage::Media(ManagerMessage::PlaybackInfoChanged))
-                    .unwrap();
-                Ok(())
-            }))
-            .unwrap();
-
-        // Update manager
-        self.current_session = current_session;
-        self.media_changed = media_changed;
-        self.timeline_changed = timeline_changed;
-        self.playbackinfo_changed = playbackinfo_changed;
-
-        println!(
-            "[Media Manager] New Session ID: {}",
-            self.current_session.SourceAppUserModelId().unwrap()
-        );
+        let previous = self.current.clone();
+        if let Err(error) = self.attach_sessions() {
+            eprintln!("[Media Manager] Could not watch the new session: {}", error);
+            return;
+        }
+
+        for watched in &self.sessions {
+            eprintln!("[Media Manager] New Session ID: {}", watched.id);
+        }
+        if self.current != previous {
+            self.announce_session();
+            if let Some(current) = self.current.clone() {
+                self.state_changed(&current, true);
+            }
+        }
     }
 
-    fn timeline_changed() -> windows::core::Result<()> {
-        let session = crate::media::get_current_session().unwrap();
-        let status = session.GetPlaybackInfo().unwrap().PlaybackStatus().unwrap();
-        if status != GlobalSystemMediaTransportControlsSessionPlaybackStatus::Playing {
-            return Ok(());
+    /// Tell everyone which session is the current one now
+    fn announce_session(&mut self) {
+        if self.current.is_none() {
+            eprintln!("[Media Manager] There is no session, waiting for one to appear");
         }
 
-        let timeline_props = session.GetTimelineProperties()?;
-
-        println!(
-            "\
-            -- START TIMELINE CHANGE --\n\
-            \tendtime: {}\n\
-            \tlast updated time: {}\n\
-            \tmax seek time: {}\n\
-            \tmin seek time: {}\n\
-            \tpos: {}\n\
-            \tstart time: {}\n\
-            -- END TIMELINE CHANGE --\
-            \n",
-            timeline_props.EndTime().unwrap().Duration,
-            timeline_props.LastUpdatedTime().unwrap().UniversalTime,
-            timeline_props.MaxSeekTime().unwrap().Duration,
-            timeline_props.MinSeekTime().unwrap().Duration,
-            timeline_props.Position().unwrap().Duration,
-            timeline_props.StartTime().unwrap().Duration,
-        );
-        Ok(())
+        self.publish(ManagerMessage::SessionChanged(self.current.clone()));
     }
 
-    fn media_props_changed() -> windows::core::Result<()> {
-        let session = crate::media::get_current_session().unwrap();
-        let media_props = block_on(session.TryGetMediaPropertiesAsync().unwrap()).unwrap();
-
-        println!(
-            "\
-            -- START MEDIA_PROP CHANGE --\n\
-            \talbum artist: {}\n\
-            \talbum title: {}\n\
-            \talbum track count: {}\n\
-            \tartist: {}\n\
-            \tpb type: {:?}\n\
-            \tsubtitle: {}\n\
-            \ttitle: {}\n\
-            \ttrack #: {}\n\
-            -- END MEDIA_PROP CHANGE --\
-            \n",
-            media_props.AlbumArtist().unwrap(),
-            media_props.AlbumTitle().unwrap(),
-            media_props.AlbumTrackCount().unwrap(),
-            media_props.Artist().unwrap(),
-            {
-                if media_props.PlaybackType().is_ok() {
-                    media_props.PlaybackType().unwrap().Value().unwrap()
-                } else {
-                    MediaPlaybackType::Unknown
-                }
-            },
-            media_props.Subtitle().unwrap(),
-            media_props.Title().unwrap(),
-            media_props.TrackNumber().unwrap(),
-        );
-        Ok(())
+    /// Read the state of every session whose window is over
+    fn read_pending(&mut self) {
+        let now = Instant::now();
+        let due: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in due {
+            self.pending.remove(&id);
+            self.state_changed(&id, false);
+        }
     }
 
-    fn playback_info_changed() -> windows::core::Result<()> {
-        let session = crate::media::get_current_session().unwrap();
-        let playback_info = session.GetPlaybackInfo()?;
-
-        println!(
-            "\
-            -- START PLAYBACK_INFO CHANGE --\n\
-            \tshuffle active?: {}\n\
-            \tpb status: {:?}\n\
-            \tpb type: {:?}\n\
-            -- END PLAYBACK_INFO CHANGE --\
-            \n",
-            // playback_info.AutoRepeatMode().unwrap().Value().unwrap().0,
-            // playback_info.Controls().unwrap(),
-            {
-                if playback_info.IsShuffleActive().is_ok() {
-                    playback_info.IsShuffleActive().unwrap().Value().unwrap()
-                } else {
-                    false
-                }
-            },
-            // playback_info.PlaybackRate().unwrap().Value().unwrap(),
-            {
-                if playback_info.PlaybackStatus().is_ok() {
-                    crate::media::playback_status_string(playback_info.PlaybackStatus().unwrap())
-                } else {
-                    "UNDEFINED".to_string()
-                }
-            },
-            {
-                if playback_info.PlaybackType().is_ok() {
-                    playback_info.PlaybackType().unwrap().Value().unwrap()
-                } else {
-                    MediaPlaybackType::Unknown
-                }
-            },
-        );
+    /// Read the full state of a watched session and publish it along with
+    /// what changed since the last one. Unless `always` is set nothing is
+    /// published when nothing changed.
+    fn state_changed(&mut self, id: &str, always: bool) {
+        let watched = match self.sessions.iter_mut().find(|watched| watched.id == id) {
+            Some(watched) => watched,
+            None => return,
+        };
+        let state = match MediaState::read_cached(&*watched.session, &SystemClock, &mut watched.art)
+        {
+            Ok(state) => state,
+            Err(error) => {
+                eprintln!("[Media Manager] Could not read session {}: {}", id, error);
+                return;
+            }
+        };
 
-        Ok(())
+        let events = diff_states(self.states.get(id), &state);
+        self.states.insert(id.to_string(), state.clone());
+        if events.is_empty() && !always {
+            return;
+        }
+
+        self.publish(ManagerMessage::StateChanged(Box::new(state)));
+        for event in events {
+            self.publish(ManagerMessage::Event(Box::new(event)));
+        }
+    }
+
+    fn publish(&mut self, msg: ManagerMessage) {
+        for subscriber in &mut self.subscribers {
+            (subscriber.0)(&msg);
+        }
+        self.tx.send(ThreadMessage::Media(msg)).ok();
     }
 }
 
 impl Drop for Manager {
     fn drop(&mut self) {
-        self.current_session
-            .RemoveMediaPropertiesChanged(self.media_changed)
-            .ok();
-        self.current_session
-            .RemoveTimelinePropertiesChanged(self.timeline_changed)
-            .ok();
-        self.current_session
-            .RemovePlaybackInfoChanged(self.playbackinfo_changed)
-            .ok();
-        self.manager
-            .RemoveCurrentSessionChanged(self.session_changed)
-            .ok();
-        println!("[Media Manager] Disposed of the media manager");
+        eprintln!("[Media Manager] Disposed of the media manager");
     }
 }
@@ -1,124 +1,490 @@
 use futures::executor::block_on;
-
 use serde::Serialize;
 
-use windows::Media::Control::{
-    GlobalSystemMediaTransportControlsSession, GlobalSystemMediaTransportControlsSessionManager,
-    GlobalSystemMediaTransportControlsSessionPlaybackStatus,
-};
+use crate::media::session::accepted;
 
 mod manager;
 pub use manager::*;
+mod session;
+pub use session::*;
+mod fake;
+pub use fake::*;
+mod seek;
+pub use seek::*;
+mod art;
+pub use art::*;
+mod error;
+pub use error::*;
+mod position;
+pub use position::*;
+mod state;
+pub use state::*;
+mod events;
+pub use events::*;
+mod template;
+pub use template::*;
+mod bar;
+pub use bar::*;
+pub(crate) mod time;
+
+#[cfg(windows)]
+mod gsmtc;
+#[cfg(windows)]
+pub use gsmtc::*;
+
+#[cfg(target_os = "linux")]
+mod mpris;
+#[cfg(target_os = "linux")]
+pub use mpris::*;
+
+/// Gets the media backend of the current platform
+#[cfg(windows)]
+pub fn default_backend() -> Result<Box<dyn MediaBackend>, MediaError> {
+    Ok(Box::new(GsmtcBackend::new()?))
+}
+
+/// Gets the media backend of the current platform
+#[cfg(target_os = "linux")]
+pub fn default_backend() -> Result<Box<dyn MediaBackend>, MediaError> {
+    Ok(Box::new(MprisBackend::new()?))
+}
 
-#[derive(Serialize)]
-struct MusicInfo {
-    title: String,
-    artist: String,
-    album_title: String,
-    finished_percentage: String,
-    status: String,
+/// Gets the media backend of the current platform
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn default_backend() -> Result<Box<dyn MediaBackend>, MediaError> {
+    Err(MediaError::Backend(
+        "There is no media backend for this platform".to_string(),
+    ))
 }
 
 /// Gets the current media session. This value will be used in most other function
-pub fn get_current_session() -> Result<GlobalSystemMediaTransportControlsSession, &'static str> {
-    let sessions = GlobalSystemMediaTransportControlsSessionManager::RequestAsync();
-    let sessions_results = block_on(sessions.unwrap()).unwrap();
+pub fn get_current_session() -> Result<Box<dyn MediaSession>, MediaError> {
+    default_backend()?.current_session()
+}
+
+/// Picks which media session a command is sent to
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SessionSelector {
+    /// The session the platform considers current
+    #[default]
+    Current,
+    /// Position of the session in [`list_sessions`]
+    Index(usize),
+    /// Source app id of the session. The first session with this id is used.
+    AppId(String),
+}
+
+impl std::str::FromStr for SessionSelector {
+    type Err = std::convert::Infallible;
 
-    if sessions_results.GetCurrentSession().is_err() {
-        return Err("There is no current session");
+    /// Numbers select by index, anything else by app id
+    ///
+    /// # Example
+    /// ```
+    /// use window::media::SessionSelector;
+    ///
+    /// assert_eq!("1".parse(), Ok(SessionSelector::Index(1)));
+    /// assert_eq!(
+    ///     "Spotify.exe".parse(),
+    ///     Ok(SessionSelector::AppId("Spotify.exe".to_string()))
+    /// );
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => SessionSelector::Index(index),
+            Err(_) => SessionSelector::AppId(s.to_string()),
+        })
     }
-    let current_session = sessions_results.GetCurrentSession().unwrap();
-    return Ok(current_session);
 }
 
-/// Gets a hashmap containing information of currently playing music/media
-fn get_music_info(session: GlobalSystemMediaTransportControlsSession) -> MusicInfo {
-    let media_properties = block_on(session.TryGetMediaPropertiesAsync().unwrap()).unwrap();
-    let title = media_properties.Title().unwrap();
-    let artist = media_properties.Artist().unwrap();
-    let album_title = media_properties.AlbumTitle().unwrap();
+/// Gets the session of `backend` chosen by `selector`
+pub fn select_session(
+    backend: &dyn MediaBackend,
+    selector: &SessionSelector,
+) -> Result<Box<dyn MediaSession>, MediaError> {
+    match selector {
+        SessionSelector::Current => backend.current_session(),
+        SessionSelector::Index(index) => backend
+            .sessions()?
+            .into_iter()
+            .nth(*index)
+            .ok_or(MediaError::NoSession),
+        SessionSelector::AppId(id) => backend
+            .sessions()?
+            .into_iter()
+            .find(|session| &session.source_app_id() == id)
+            .ok_or(MediaError::NoSession),
+    }
+}
 
-    let timeline_props = session.GetTimelineProperties().unwrap();
-    let finished_percentage = ((timeline_props.Position().unwrap().Duration as f32
-        / timeline_props.MaxSeekTime().unwrap().Duration as f32)
-        * 100.0)
-        .round() as u8;
+/// Waits until `backend` has a session chosen by `selector` and returns it.
+/// Gives up with [`MediaError::NoSession`] after `timeout`, or waits forever
+/// without one.
+pub fn wait_for_session(
+    backend: &dyn MediaBackend,
+    selector: &SessionSelector,
+    timeout: Option<std::time::Duration>,
+) -> Result<Box<dyn MediaSession>, MediaError> {
+    // Look again now and then in case the backend misses an event
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
 
-    let status = session.GetPlaybackInfo().unwrap().PlaybackStatus().unwrap();
+    let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+    let (tx, rx) = crossbeam_channel::unbounded();
+    // Listen before looking, so a session appearing in between isn't missed
+    let _listener = backend.listen(tx)?;
 
-    let status_string = playback_status_string(status);
+    loop {
+        match select_session(backend, selector) {
+            Err(MediaError::NoSession) => (),
+            result => return result,
+        }
 
-    MusicInfo {
-        title: title.to_string(),
-        artist: artist.to_string(),
-        album_title: album_title.to_string(),
-        finished_percentage: finished_percentage.to_string(),
-        status: status_string,
+        let wait = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(std::time::Instant::now()) {
+                Some(remaining) if !remaining.is_zero() => remaining.min(POLL_INTERVAL),
+                _ => return Err(MediaError::NoSession),
+            },
+            None => POLL_INTERVAL,
+        };
+        rx.recv_timeout(wait).ok();
     }
 }
 
-fn playback_status_string(
-    status: GlobalSystemMediaTransportControlsSessionPlaybackStatus,
-) -> String {
-    match status {
-        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Closed => "CLOSED".to_string(),
-        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Opened => "OPENED".to_string(),
-        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Changing => "CHANGING".to_string(),
-        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Stopped => "STOPPED".to_string(),
-        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Playing => "PLAYING".to_string(),
-        GlobalSystemMediaTransportControlsSessionPlaybackStatus::Paused => "PAUSED".to_string(),
-        _ => unreachable!(), // Default case should be unreachable
-    }
+/// Summary of a media session as listed by [`list_sessions`]
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    /// Position of the session, usable with [`SessionSelector::Index`]
+    pub index: usize,
+    /// Source app id of the session
+    pub source_app_id: String,
+    /// Title of the media the session is playing
+    pub title: String,
+    /// Playback status of the session
+    pub status: PlaybackStatus,
+}
+
+/// Lists every session of `backend`
+pub fn list_sessions(backend: &dyn MediaBackend) -> Result<Vec<SessionInfo>, MediaError> {
+    backend
+        .sessions()?
+        .iter()
+        .enumerate()
+        .map(|(index, session)| {
+            Ok(SessionInfo {
+                index,
+                source_app_id: session.source_app_id(),
+                title: session.media_properties()?.title,
+                status: session.playback_info()?.playback_status,
+            })
+        })
+        .collect()
 }
 
-/// Needed make sure that the command is fully processed before exiting
-#[doc(hidden)]
-fn post_change_routine(
-    res: Result<windows::Foundation::IAsyncOperation<bool>, windows::core::Error>,
-) {
-    if res.is_ok() {
-        std::thread::sleep(std::time::Duration::from_millis(50))
+/// Fails with [`MediaError::Unsupported`] unless the session currently
+/// accepts `control`
+fn require(session: &dyn MediaSession, control: Control) -> Result<(), MediaError> {
+    if session.playback_info()?.active_controls.is_enabled(control) {
+        Ok(())
+    } else {
+        Err(MediaError::Unsupported(control))
     }
 }
 
 /// Goes to the previous track on the given session
-pub fn previous_track(session: GlobalSystemMediaTransportControlsSession) {
-    let res = session.TrySkipPreviousAsync();
-    post_change_routine(res);
+pub fn previous_track(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(previous_track_async(session)), Control::Previous)
+}
+
+/// Like [`previous_track`], but resolves to whether the session accepted the
+/// command
+pub async fn previous_track_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::Previous)?;
+    session.previous_track_async().await
 }
 
 /// Goes to the next track on the given session
-pub fn next_track(session: GlobalSystemMediaTransportControlsSession) {
-    let res = session.TrySkipNextAsync();
-    post_change_routine(res);
+pub fn next_track(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(next_track_async(session)), Control::Next)
+}
+
+/// Like [`next_track`], but resolves to whether the session accepted the
+/// command
+pub async fn next_track_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::Next)?;
+    session.next_track_async().await
 }
 
 /// Resumes playback on the given session
-pub fn play(session: GlobalSystemMediaTransportControlsSession) {
-    let res = session.TryPlayAsync();
-    post_change_routine(res);
+pub fn play(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(play_async(session)), Control::Play)
+}
+
+/// Like [`play`], but resolves to whether the session accepted the command
+pub async fn play_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::Play)?;
+    session.play_async().await
 }
 
 /// Pauses playback on the given session
-pub fn pause(session: GlobalSystemMediaTransportControlsSession) {
-    let res = session.TryPauseAsync();
-    post_change_routine(res);
+pub fn pause(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(pause_async(session)), Control::Pause)
 }
 
-/// Returns raw currently playing of the given session
-pub fn currently_playing_raw(session: GlobalSystemMediaTransportControlsSession) -> String {
-    let music_info = get_music_info(session);
-    format!("{}", serde_json::to_string(&music_info).unwrap())
+/// Like [`pause`], but resolves to whether the session accepted the command
+pub async fn pause_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::Pause)?;
+    session.pause_async().await
+}
+
+/// Stops playback on the given session
+pub fn stop(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(stop_async(session)), Control::Stop)
+}
+
+/// Like [`stop`], but resolves to whether the session accepted the command
+pub async fn stop_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::Stop)?;
+    session.stop_async().await
+}
+
+/// Pauses the given session when it is playing and resumes it otherwise,
+/// without having to check its status first
+pub fn toggle_play_pause(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(
+        block_on(toggle_play_pause_async(session)),
+        Control::PlayPauseToggle,
+    )
+}
+
+/// Like [`toggle_play_pause`], but resolves to whether the session accepted the
+/// command
+pub async fn toggle_play_pause_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::PlayPauseToggle)?;
+    session.toggle_play_pause_async().await
+}
+
+/// Starts fast-forwarding on the given session
+pub fn fast_forward(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(fast_forward_async(session)), Control::FastForward)
+}
+
+/// Like [`fast_forward`], but resolves to whether the session accepted the
+/// command
+pub async fn fast_forward_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::FastForward)?;
+    session.fast_forward_async().await
+}
+
+/// Starts rewinding on the given session
+pub fn rewind(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(rewind_async(session)), Control::Rewind)
+}
+
+/// Like [`rewind`], but resolves to whether the session accepted the command
+pub async fn rewind_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::Rewind)?;
+    session.rewind_async().await
+}
+
+/// Switches the given session to the next channel
+pub fn channel_up(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(channel_up_async(session)), Control::ChannelUp)
+}
+
+/// Like [`channel_up`], but resolves to whether the session accepted the
+/// command
+pub async fn channel_up_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::ChannelUp)?;
+    session.channel_up_async().await
+}
+
+/// Switches the given session to the previous channel
+pub fn channel_down(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(channel_down_async(session)), Control::ChannelDown)
+}
+
+/// Like [`channel_down`], but resolves to whether the session accepted the
+/// command
+pub async fn channel_down_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::ChannelDown)?;
+    session.channel_down_async().await
+}
+
+/// Starts recording on the given session
+pub fn record(session: &dyn MediaSession) -> Result<(), MediaError> {
+    accepted(block_on(record_async(session)), Control::Record)
+}
+
+/// Like [`record`], but resolves to whether the session accepted the command
+pub async fn record_async(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    require(session, Control::Record)?;
+    session.record_async().await
+}
+
+/// Moves the playback position of the given session, clamped to the range the
+/// session can seek in. Returns the position that was requested.
+pub fn seek(
+    session: &dyn MediaSession,
+    target: SeekTarget,
+) -> Result<std::time::Duration, MediaError> {
+    block_on(seek_async(session, target))?.ok_or(MediaError::Unsupported(Control::PlaybackPosition))
+}
+
+/// Like [`seek`], but resolves to `None` when the session refused to seek
+pub async fn seek_async(
+    session: &dyn MediaSession,
+    target: SeekTarget,
+) -> Result<Option<std::time::Duration>, MediaError> {
+    require(session, Control::PlaybackPosition)?;
+
+    let timeline = current_timeline(session, &SystemClock)?;
+    let (min, max) = (timeline.min_seek_time, timeline.max_seek_time);
+    if max <= min {
+        return Err(MediaError::PropertyUnavailable("seekable range"));
+    }
+
+    let position = target.resolve(timeline.pos).clamp(min, max);
+    let accepted = session.set_position_async(position).await?;
+
+    Ok(accepted.then_some(position))
+}
+
+/// Starts the current track of the given session from the beginning
+pub fn restart_track(session: &dyn MediaSession) -> Result<std::time::Duration, MediaError> {
+    seek(session, SeekTarget::Absolute(std::time::Duration::ZERO))
+}
+
+/// Turns shuffle on or off on the given session
+pub fn set_shuffle(session: &dyn MediaSession, shuffle: bool) -> Result<(), MediaError> {
+    accepted(
+        block_on(set_shuffle_async(session, shuffle)),
+        Control::Shuffle,
+    )
+}
+
+/// Like [`set_shuffle`], but resolves to whether the session accepted the
+/// command
+pub async fn set_shuffle_async(
+    session: &dyn MediaSession,
+    shuffle: bool,
+) -> Result<bool, MediaError> {
+    require(session, Control::Shuffle)?;
+    session.set_shuffle_async(shuffle).await
+}
+
+/// Flips shuffle on the given session. Returns whether shuffle is now on.
+pub fn toggle_shuffle(session: &dyn MediaSession) -> Result<bool, MediaError> {
+    let shuffle = !session.playback_info()?.shuffle_active;
+    set_shuffle(session, shuffle)?;
+
+    Ok(shuffle)
+}
+
+/// Changes the repeat mode of the given session
+pub fn set_repeat(session: &dyn MediaSession, mode: RepeatMode) -> Result<(), MediaError> {
+    accepted(block_on(set_repeat_async(session, mode)), Control::Repeat)
+}
+
+/// Like [`set_repeat`], but resolves to whether the session accepted the
+/// command
+pub async fn set_repeat_async(
+    session: &dyn MediaSession,
+    mode: RepeatMode,
+) -> Result<bool, MediaError> {
+    require(session, Control::Repeat)?;
+    session.set_repeat_async(mode).await
+}
+
+/// Highest playback rate [`set_playback_rate`] asks a session for, far above
+/// what players support
+pub const MAX_PLAYBACK_RATE: f64 = 100.0;
+
+/// Changes the playback rate of the given session, `1.0` being normal speed
+pub fn set_playback_rate(session: &dyn MediaSession, rate: f64) -> Result<(), MediaError> {
+    accepted(
+        block_on(set_playback_rate_async(session, rate)),
+        Control::PlaybackRate,
+    )
+}
+
+/// Like [`set_playback_rate`], but resolves to whether the session accepted
+/// the command
+pub async fn set_playback_rate_async(
+    session: &dyn MediaSession,
+    rate: f64,
+) -> Result<bool, MediaError> {
+    if !(rate > 0.0 && rate <= MAX_PLAYBACK_RATE) {
+        return Err(MediaError::InvalidArgument(
+            "The playback rate has to be a positive number up to 100".to_string(),
+        ));
+    }
+    require(session, Control::PlaybackRate)?;
+    session.set_playback_rate_async(rate).await
+}
+
+/// Returns the [`MediaState`] of the given session as JSON
+pub fn currently_playing_raw(session: &dyn MediaSession) -> Result<String, MediaError> {
+    let state = MediaState::read(session, &SystemClock)?;
+    Ok(serde_json::to_string(&state).unwrap())
+}
+
+/// Get the [`MediaState`] of the given session as JSON, with the album art
+/// embedded as a `data:` URL, scaled down to `max_size`. Fails with
+/// [`MediaError::InvalidArgument`] when a size is asked for but the backend
+/// can't scale images, see [`MediaSession::scales_thumbnails`].
+pub fn currently_playing_raw_with_art(
+    session: &dyn MediaSession,
+    max_size: Option<u32>,
+) -> Result<String, MediaError> {
+    check_art_size(session, max_size)?;
+    let mut state = MediaState::read(session, &SystemClock)?;
+    state.art = session.thumbnail(max_size).map(|art| art.data_url());
+    Ok(serde_json::to_string(&state).unwrap())
+}
+
+/// Reads the album art of the given session's current track, scaled down to
+/// `max_size` like [`currently_playing_raw_with_art`]
+pub fn album_art(
+    session: &dyn MediaSession,
+    max_size: Option<u32>,
+) -> Result<Thumbnail, MediaError> {
+    check_art_size(session, max_size)?;
+    session
+        .thumbnail(max_size)
+        .ok_or(MediaError::PropertyUnavailable("album art"))
+}
+
+fn check_art_size(session: &dyn MediaSession, max_size: Option<u32>) -> Result<(), MediaError> {
+    if max_size.is_some() && !session.scales_thumbnails() {
+        return Err(MediaError::InvalidArgument(
+            "Album art can only be scaled on Windows, leave out the size".to_string(),
+        ));
+    }
+
+    Ok(())
 }
 
 /// Get formated currently playing info (printed out in console)
-pub fn currently_playing(session: GlobalSystemMediaTransportControlsSession) {
-    let music_info = get_music_info(session);
+pub fn currently_playing(session: &dyn MediaSession) -> Result<(), MediaError> {
+    let state = MediaState::read(session, &SystemClock)?;
+    let progress = match state.finished_percentage {
+        Some(percentage) => format!("{}% Finished", percentage),
+        None => "Live".to_string(),
+    };
     println!(
         "=======================================\n\
         Currently Playing: {} - {}\n\
-        {}% Finished -- {}\n\
+        {} -- {}\n\
          =======================================",
-        music_info.artist, music_info.title, music_info.finished_percentage, music_info.status
+        state.media.artist, state.media.title, progress, state.playback.playback_status
     );
+    Ok(())
+}
+
+/// Fills in `template` with the state of the given session
+pub fn currently_playing_formatted(
+    session: &dyn MediaSession,
+    template: &Template,
+) -> Result<String, MediaError> {
+    Ok(template.render(&MediaState::read(session, &SystemClock)?))
 }
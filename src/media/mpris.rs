@@ -0,0 +1,580 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
+
+use futures::{channel::oneshot, executor::block_on, future, stream, StreamExt};
+use zbus::{
+    blocking::{Connection, Proxy, ProxyBuilder},
+    zvariant::{OwnedObjectPath, OwnedValue},
+    CacheProperties, DBusError,
+};
+
+use crate::{
+    controller::ThreadMessage,
+    media::{
+        session::refused, ActiveControls, CommandFuture, ManagerMessage, MediaBackend, MediaError,
+        MediaProps, MediaSession, PlaybackInfoProps, PlaybackStatus, PlaybackType, RepeatMode,
+        Subscription, Thumbnail, TimelineProps,
+    },
+};
+
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Media backend talking to MPRIS media players over the D-Bus session bus
+#[derive(Debug)]
+pub struct MprisBackend {
+    conn: Connection,
+}
+
+impl MprisBackend {
+    /// Connect to the session bus
+    pub fn new() -> Result<Self, MediaError> {
+        let conn = Connection::session().map_err(MediaError::backend)?;
+
+        Ok(Self { conn })
+    }
+
+    /// Connect to the bus at `address`, e.g. a private `dbus-daemon`
+    pub fn with_address(address: &str) -> Result<Self, MediaError> {
+        let conn = zbus::blocking::ConnectionBuilder::address(address)
+            .and_then(|builder| builder.build())
+            .map_err(MediaError::backend)?;
+
+        Ok(Self { conn })
+    }
+
+    /// Bus names of all MPRIS players, sorted by name
+    fn player_names(&self) -> Result<Vec<String>, MediaError> {
+        let dbus = zbus::blocking::fdo::DBusProxy::new(&self.conn).map_err(MediaError::backend)?;
+        let mut names: Vec<String> = dbus
+            .list_names()
+            .map_err(MediaError::backend)?
+            .into_iter()
+            .map(|name| name.to_string())
+            .filter(|name| name.starts_with(BUS_NAME_PREFIX))
+            .collect();
+        names.sort();
+
+        Ok(names)
+    }
+
+    fn players(&self) -> Result<Vec<MprisSession>, MediaError> {
+        Ok(self
+            .player_names()?
+            .into_iter()
+            .filter_map(|name| MprisSession::new(&self.conn, name).ok())
+            .collect())
+    }
+}
+
+impl MediaBackend for MprisBackend {
+    fn current_session(&self) -> Result<Box<dyn MediaSession>, MediaError> {
+        let sessions = self.players()?;
+
+        // MPRIS has no notion of a current player, so prefer one that is
+        // actually playing something
+        let mut current = None;
+        for session in sessions {
+            if session.status() == PlaybackStatus::Playing {
+                current = Some(session);
+                break;
+            }
+            current.get_or_insert(session);
+        }
+
+        match current {
+            Some(session) => Ok(Box::new(session)),
+            None => Err(MediaError::NoSession),
+        }
+    }
+
+    fn sessions(&self) -> Result<Vec<Box<dyn MediaSession>>, MediaError> {
+        Ok(self
+            .players()?
+            .into_iter()
+            .map(|session| Box::new(session) as Box<dyn MediaSession>)
+            .collect())
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        let conn = self.conn.inner().clone();
+
+        spawn_listener(async move {
+            let dbus = zbus::fdo::DBusProxy::new(&conn).await?;
+            let changes = dbus.receive_name_owner_changed().await?;
+
+            Ok(changes
+                .filter(|signal| {
+                    let is_player = signal
+                        .args()
+                        .map(|args| args.name().starts_with(BUS_NAME_PREFIX))
+                        .unwrap_or(false);
+                    async move { is_player }
+                })
+                .map(move |_| {
+                    tx.send(ThreadMessage::Media(ManagerMessage::SessionsChanged))
+                        .is_ok()
+                })
+                .boxed())
+        })
+    }
+}
+
+/// A single MPRIS media player
+#[derive(Debug)]
+pub struct MprisSession {
+    name: String,
+    player: Proxy<'static>,
+}
+
+impl MprisSession {
+    fn new(conn: &Connection, name: String) -> zbus::Result<Self> {
+        // Position is never announced through `PropertiesChanged`, so caching
+        // would leave it stuck at the first value read
+        let player = ProxyBuilder::new_bare(conn)
+            .destination(name.clone())?
+            .path(OBJECT_PATH)?
+            .interface(PLAYER_INTERFACE)?
+            .cache_properties(CacheProperties::No)
+            .build()?;
+
+        Ok(Self { name, player })
+    }
+
+    /// Calls a method of the player without arguments
+    fn call(&self, method: &'static str) -> CommandFuture<'_> {
+        Box::pin(async move {
+            self.player
+                .inner()
+                .call_method(method, &())
+                .await
+                .map_err(player_error)?;
+            Ok(true)
+        })
+    }
+
+    fn set<'t, T>(&'t self, name: &'static str, value: T) -> CommandFuture<'t>
+    where
+        T: Into<zbus::zvariant::Value<'t>> + Send + 't,
+    {
+        Box::pin(async move {
+            self.player
+                .inner()
+                .set_property(name, value)
+                .await
+                .map_err(|error| player_error(error.into()))?;
+            Ok(true)
+        })
+    }
+
+    fn property<T>(&self, name: &str) -> Option<T>
+    where
+        T: TryFrom<OwnedValue>,
+    {
+        self.player
+            .get_property::<OwnedValue>(name)
+            .ok()
+            .and_then(|value| T::try_from(value).ok())
+    }
+
+    /// Reads a property every MPRIS player has to implement
+    fn required_property<T>(&self, name: &'static str) -> Result<T, MediaError>
+    where
+        T: TryFrom<OwnedValue>,
+    {
+        let value = self
+            .player
+            .get_property::<OwnedValue>(name)
+            .map_err(|error| property_error(error, name))?;
+
+        T::try_from(value).map_err(|_| MediaError::PropertyUnavailable(name))
+    }
+
+    fn metadata(&self) -> Result<HashMap<String, OwnedValue>, MediaError> {
+        self.required_property("Metadata")
+    }
+
+    fn status(&self) -> PlaybackStatus {
+        playback_status(
+            &self
+                .property::<String>("PlaybackStatus")
+                .unwrap_or_default(),
+        )
+    }
+}
+
+fn playback_status(status: &str) -> PlaybackStatus {
+    match status {
+        "Playing" => PlaybackStatus::Playing,
+        "Paused" => PlaybackStatus::Paused,
+        "Stopped" => PlaybackStatus::Stopped,
+        _ => PlaybackStatus::Closed,
+    }
+}
+
+/// A required property couldn't be read, either because the player went away
+/// or because it doesn't implement it
+fn property_error(error: zbus::Error, name: &'static str) -> MediaError {
+    match player_error(error) {
+        MediaError::NoSession => MediaError::NoSession,
+        _ => MediaError::PropertyUnavailable(name),
+    }
+}
+
+/// Maps a failed call on a player. Players which left the bus in the
+/// meantime count as a missing session.
+fn player_error(error: zbus::Error) -> MediaError {
+    let name = match &error {
+        zbus::Error::MethodError(name, _, _) => Some(name.to_string()),
+        zbus::Error::FDO(error) => Some(error.name().to_string()),
+        _ => None,
+    };
+
+    match name.as_deref() {
+        Some("org.freedesktop.DBus.Error.ServiceUnknown")
+        | Some("org.freedesktop.DBus.Error.NameHasNoOwner") => MediaError::NoSession,
+        _ => MediaError::backend(error),
+    }
+}
+
+fn metadata_string(metadata: &HashMap<String, OwnedValue>, key: &str) -> String {
+    metadata
+        .get(key)
+        .and_then(|value| String::try_from(value.clone()).ok())
+        .unwrap_or_default()
+}
+
+fn metadata_list(metadata: &HashMap<String, OwnedValue>, key: &str) -> String {
+    metadata
+        .get(key)
+        .and_then(|value| Vec::<String>::try_from(value.clone()).ok())
+        .map(|list| list.join(", "))
+        .unwrap_or_default()
+}
+
+fn metadata_i64(metadata: &HashMap<String, OwnedValue>, key: &str) -> i64 {
+    metadata
+        .get(key)
+        .and_then(|value| {
+            i64::try_from(value.clone())
+                .ok()
+                .or_else(|| i32::try_from(value.clone()).ok().map(i64::from))
+                .or_else(|| u64::try_from(value.clone()).ok().map(|v| v as i64))
+        })
+        .unwrap_or_default()
+}
+
+/// Turns a `file://` URL into a path. Players also hand out `http(s)://`
+/// art URLs, but fetching those is left to the client.
+fn file_url_path(url: &str) -> Option<std::path::PathBuf> {
+    let path = url.strip_prefix("file://")?;
+
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' && tail.len() >= 2 {
+            let hex = std::str::from_utf8(&tail[..2]).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    Some(String::from_utf8(bytes).ok()?.into())
+}
+
+impl MediaSession for MprisSession {
+    fn source_app_id(&self) -> String {
+        self.name
+            .strip_prefix(BUS_NAME_PREFIX)
+            .unwrap_or(&self.name)
+            .to_string()
+    }
+
+    fn play_async(&self) -> CommandFuture<'_> {
+        self.call("Play")
+    }
+
+    fn pause_async(&self) -> CommandFuture<'_> {
+        self.call("Pause")
+    }
+
+    fn next_track_async(&self) -> CommandFuture<'_> {
+        self.call("Next")
+    }
+
+    fn previous_track_async(&self) -> CommandFuture<'_> {
+        self.call("Previous")
+    }
+
+    fn stop_async(&self) -> CommandFuture<'_> {
+        self.call("Stop")
+    }
+
+    fn toggle_play_pause_async(&self) -> CommandFuture<'_> {
+        self.call("PlayPause")
+    }
+
+    // MPRIS has no fast-forward, rewind, channels or recording
+
+    fn fast_forward_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn rewind_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn channel_up_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn channel_down_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn record_async(&self) -> CommandFuture<'_> {
+        refused()
+    }
+
+    fn set_position_async(&self, position: Duration) -> CommandFuture<'_> {
+        Box::pin(async move {
+            let player = self.player.inner();
+            let position = position.as_micros() as i64;
+            let metadata: HashMap<String, OwnedValue> = player
+                .get_property("Metadata")
+                .await
+                .map_err(|error| property_error(error, "Metadata"))?;
+            let track = metadata
+                .get("mpris:trackid")
+                .and_then(|id| OwnedObjectPath::try_from(id.clone()).ok());
+
+            let reply = match track {
+                Some(track) => player.call_method("SetPosition", &(track, position)).await,
+                // `SetPosition` needs the track id, so fall back to a relative seek
+                None => {
+                    let current = player
+                        .get_property::<i64>("Position")
+                        .await
+                        .unwrap_or_default();
+                    player.call_method("Seek", &(position - current)).await
+                }
+            };
+            reply.map_err(player_error)?;
+
+            Ok(true)
+        })
+    }
+
+    fn set_shuffle_async(&self, shuffle: bool) -> CommandFuture<'_> {
+        self.set("Shuffle", shuffle)
+    }
+
+    fn set_repeat_async(&self, mode: RepeatMode) -> CommandFuture<'_> {
+        let status = match mode {
+            RepeatMode::None => "None",
+            RepeatMode::Track => "Track",
+            RepeatMode::List => "Playlist",
+        };
+        self.set("LoopStatus", status)
+    }
+
+    fn set_playback_rate_async(&self, rate: f64) -> CommandFuture<'_> {
+        self.set("Rate", rate)
+    }
+
+    fn media_properties(&self) -> Result<MediaProps, MediaError> {
+        let metadata = self.metadata()?;
+
+        Ok(MediaProps {
+            album_artist: metadata_list(&metadata, "xesam:albumArtist"),
+            album_title: metadata_string(&metadata, "xesam:album"),
+            album_track_count: 0,
+            artist: metadata_list(&metadata, "xesam:artist"),
+            playback_type: PlaybackType::Unknown,
+            subtitle: String::new(),
+            title: metadata_string(&metadata, "xesam:title"),
+            track_number: metadata_i64(&metadata, "xesam:trackNumber") as i32,
+        })
+    }
+
+    fn thumbnail(&self, _max_size: Option<u32>) -> Option<Thumbnail> {
+        let url = metadata_string(&self.metadata().ok()?, "mpris:artUrl");
+        std::fs::read(file_url_path(&url)?).ok().map(Thumbnail::new)
+    }
+
+    fn thumbnail_id(&self) -> Option<String> {
+        let url = metadata_string(&self.metadata().ok()?, "mpris:artUrl");
+        (!url.is_empty()).then_some(url)
+    }
+
+    fn timeline_properties(&self) -> Result<TimelineProps, MediaError> {
+        // MPRIS reports microseconds. Live streams have no `mpris:length`.
+        let micros = |value: i64| Duration::from_micros(value.max(0) as u64);
+        let length = micros(metadata_i64(&self.metadata()?, "mpris:length"));
+        let pos = micros(self.property::<i64>("Position").unwrap_or_default());
+
+        Ok(TimelineProps {
+            last_updated_time: SystemTime::now(),
+            pos,
+            max_seek_time: length,
+            min_seek_time: Duration::ZERO,
+            endtime: length,
+            start_time: Duration::ZERO,
+        })
+    }
+
+    fn playback_info(&self) -> Result<PlaybackInfoProps, MediaError> {
+        let status: String = self.required_property("PlaybackStatus")?;
+        let can_control = self.property::<bool>("CanControl").unwrap_or(false);
+        let can_play = self.property::<bool>("CanPlay").unwrap_or(false);
+        let can_pause = self.property::<bool>("CanPause").unwrap_or(false);
+        let can_seek = self.property::<bool>("CanSeek").unwrap_or(false);
+        let loop_status = self.property::<String>("LoopStatus");
+        let shuffle = self.property::<bool>("Shuffle");
+        let rate = self.property::<f64>("Rate");
+
+        Ok(PlaybackInfoProps {
+            auto_repeat_mode: match loop_status.as_deref() {
+                Some("Track") => RepeatMode::Track,
+                Some("Playlist") => RepeatMode::List,
+                _ => RepeatMode::None,
+            },
+            active_controls: ActiveControls {
+                is_play_enabled: can_play,
+                is_pause_enabled: can_pause,
+                is_stop_enabled: can_control,
+                is_next_enabled: self.property::<bool>("CanGoNext").unwrap_or(false),
+                is_previous_enabled: self.property::<bool>("CanGoPrevious").unwrap_or(false),
+                is_play_pause_toggle_enabled: can_play && can_pause,
+                is_shuffle_enabled: can_control && shuffle.is_some(),
+                is_repeat_enabled: can_control && loop_status.is_some(),
+                is_playback_rate_enabled: can_control && rate.is_some(),
+                is_playback_position_enabled: can_seek,
+                ..Default::default()
+            },
+            shuffle_active: shuffle.unwrap_or(false),
+            playback_status: playback_status(&status),
+            playback_type: PlaybackType::Unknown,
+            playback_rate: rate.unwrap_or(1.0),
+        })
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        let conn = self.player.connection().inner().clone();
+        let name = self.name.clone();
+        let id = self.source_app_id();
+
+        spawn_listener(async move {
+            let properties = zbus::fdo::PropertiesProxy::builder(&conn)
+                .destination(name.clone())?
+                .path(OBJECT_PATH)?
+                .build()
+                .await?;
+            let player = zbus::Proxy::new(&conn, name, OBJECT_PATH, PLAYER_INTERFACE).await?;
+
+            let changed = properties
+                .receive_properties_changed()
+                .await?
+                .flat_map(|signal| {
+                    let mut messages = vec![];
+                    if let Ok(args) = signal.args() {
+                        if args.interface_name().as_str() == PLAYER_INTERFACE {
+                            messages = changed_messages(args.changed_properties().keys().copied());
+                        }
+                    }
+                    stream::iter(messages)
+                });
+            let seeked = player
+                .receive_signal("Seeked")
+                .await?
+                .map(|_| ManagerMessage::TimelineChanged as fn(String) -> ManagerMessage);
+
+            Ok(stream::select(changed, seeked)
+                .map(move |msg| tx.send(ThreadMessage::Media(msg(id.clone()))).is_ok())
+                .boxed())
+        })
+    }
+}
+
+/// Maps the names of changed player properties to the manager messages they
+/// should trigger
+fn changed_messages<'a>(
+    properties: impl Iterator<Item = &'a str>,
+) -> Vec<fn(String) -> ManagerMessage> {
+    let mut media = false;
+    let mut playback = false;
+
+    for property in properties {
+        match property {
+            "Metadata" => media = true,
+            "PlaybackStatus" | "LoopStatus" | "Shuffle" | "Rate" | "CanPlay" | "CanPause"
+            | "CanGoNext" | "CanGoPrevious" | "CanSeek" | "CanControl" => playback = true,
+            _ => (),
+        }
+    }
+
+    let mut messages: Vec<fn(String) -> ManagerMessage> = vec![];
+    if media {
+        // A new track also comes with a new length and position
+        messages.push(ManagerMessage::MediaChanged);
+        messages.push(ManagerMessage::TimelineChanged);
+    }
+    if playback {
+        messages.push(ManagerMessage::PlaybackInfoChanged);
+    }
+
+    messages
+}
+
+/// Drives the stream built by `setup` on its own thread until the returned
+/// subscription is dropped. The stream yields whether it could pass on what
+/// it got, and stops once the receiving end went away.
+fn spawn_listener<F>(setup: F) -> Result<Subscription, MediaError>
+where
+    F: std::future::Future<Output = zbus::Result<stream::BoxStream<'static, bool>>>
+        + Send
+        + 'static,
+{
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let (ready_tx, ready_rx) = crossbeam_channel::bounded(1);
+
+    std::thread::spawn(move || {
+        block_on(async move {
+            match setup.await {
+                Ok(events) => {
+                    ready_tx.send(Ok(())).ok();
+                    events
+                        .take_while(|sent| future::ready(*sent))
+                        .take_until(stop_rx)
+                        .for_each(|_| future::ready(()))
+                        .await
+                }
+                Err(error) => {
+                    ready_tx.send(Err(player_error(error))).ok();
+                }
+            }
+        })
+    });
+
+    // Don't hand out the subscription before the match rules are in place,
+    // otherwise changes right after subscribing would be missed
+    ready_rx
+        .recv()
+        .map_err(|_| MediaError::Backend("The event listener stopped".to_string()))??;
+
+    Ok(Subscription::new(move || {
+        stop_tx.send(()).ok();
+    }))
+}
@@ -0,0 +1,127 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use crate::media::{MediaError, MediaSession, PlaybackInfoProps, PlaybackStatus, TimelineProps};
+
+/// Source of the current time for position estimates
+pub trait Clock: std::fmt::Debug {
+    /// The current time
+    fn now(&self) -> SystemTime;
+}
+
+/// The system's wall clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock which only moves when told to, for tests. Clones share the same
+/// time.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl ManualClock {
+    /// Create a clock stopped at `now`
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Move the clock forward by `duration`
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    /// Move the clock to `now`
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+}
+
+/// Estimates the playback position at `now` from the last reported position.
+///
+/// Sessions only report their position once in a while, so while playing the
+/// time since [`TimelineProps::last_updated_time`], scaled by the playback
+/// rate, is added to it. The estimate never runs past the end of the media.
+///
+/// # Example
+/// ```
+/// use std::time::{Duration, SystemTime};
+/// use window::media::{estimate_position, FakeBackend, FakeScript, FakeTrack, MediaBackend};
+///
+/// let backend = FakeBackend::new(FakeScript {
+///     tracks: vec![FakeTrack {
+///         duration_ms: 60_000,
+///         ..Default::default()
+///     }],
+///     playing: true,
+///     ..Default::default()
+/// });
+/// let session = backend.current_session().unwrap();
+/// let timeline = session.timeline_properties().unwrap();
+/// let playback = session.playback_info().unwrap();
+///
+/// let later = timeline.last_updated_time + Duration::from_secs(10);
+/// assert_eq!(
+///     estimate_position(&timeline, &playback, later),
+///     Duration::from_secs(10)
+/// );
+/// ```
+pub fn estimate_position(
+    timeline: &TimelineProps,
+    playback: &PlaybackInfoProps,
+    now: SystemTime,
+) -> Duration {
+    let mut position = timeline.pos;
+
+    if playback.playback_status == PlaybackStatus::Playing {
+        let rate = playback.playback_rate;
+        let rate = if rate.is_finite() { rate.max(0.0) } else { 1.0 };
+        let elapsed = now
+            .duration_since(timeline.last_updated_time)
+            .unwrap_or_default();
+        position = position.saturating_add(scale(elapsed, rate));
+    }
+
+    match timeline.duration() {
+        Some(_) => position.min(timeline.endtime),
+        None => position,
+    }
+}
+
+/// `elapsed` at playback `rate`, saturating rather than overflowing on rates
+/// far beyond what players support
+pub(crate) fn scale(elapsed: Duration, rate: f64) -> Duration {
+    Duration::try_from_secs_f64(elapsed.as_secs_f64() * rate).unwrap_or(Duration::MAX)
+}
+
+/// Reads the timeline of the given session with the position estimated at
+/// the clock's current time, see [`estimate_position`]
+pub fn current_timeline(
+    session: &dyn MediaSession,
+    clock: &dyn Clock,
+) -> Result<TimelineProps, MediaError> {
+    let playback = session.playback_info()?;
+    let mut timeline = session.timeline_properties()?;
+
+    let now = clock.now();
+    timeline.pos = estimate_position(&timeline, &playback, now);
+    timeline.last_updated_time = now;
+
+    Ok(timeline)
+}
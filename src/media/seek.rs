@@ -0,0 +1,139 @@
+use std::time::Duration;
+
+use crate::media::MediaError;
+
+/// Where to move the playback position of a session to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeekTarget {
+    /// Absolute position from the start of the track
+    Absolute(Duration),
+    /// Skip ahead from the current position
+    Forward(Duration),
+    /// Skip back from the current position
+    Backward(Duration),
+}
+
+impl SeekTarget {
+    /// Resolves the target into an absolute position, given the current one
+    pub fn resolve(&self, current: Duration) -> Duration {
+        match self {
+            SeekTarget::Absolute(position) => *position,
+            SeekTarget::Forward(offset) => current.saturating_add(*offset),
+            SeekTarget::Backward(offset) => current.saturating_sub(*offset),
+        }
+    }
+}
+
+impl std::str::FromStr for SeekTarget {
+    type Err = MediaError;
+
+    /// Parses either a clock position (`1:23`, `1:02:03`) or a duration with
+    /// units (`90s`, `1m30s`, `2m`). Plain numbers are seconds. A leading `+`
+    /// or `-` makes the target relative to the current position.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use window::media::SeekTarget;
+    ///
+    /// assert_eq!("1:23".parse(), Ok(SeekTarget::Absolute(Duration::from_secs(83))));
+    /// assert_eq!("+30s".parse(), Ok(SeekTarget::Forward(Duration::from_secs(30))));
+    /// assert_eq!("-1m15s".parse(), Ok(SeekTarget::Backward(Duration::from_secs(75))));
+    /// assert!("soon".parse::<SeekTarget>().is_err());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let target = if let Some(offset) = s.strip_prefix('+') {
+            parse_time(offset).map(SeekTarget::Forward)
+        } else if let Some(offset) = s.strip_prefix('-') {
+            parse_time(offset).map(SeekTarget::Backward)
+        } else {
+            parse_time(s).map(SeekTarget::Absolute)
+        };
+
+        target.ok_or_else(|| {
+            MediaError::InvalidArgument(format!(
+                "`{}` isn't a position, positions look like `1:23`, `90s` or `1m30s`",
+                s
+            ))
+        })
+    }
+}
+
+/// Parses a duration like `30s`, `1m30s`, `1h2m3.5s` or `1:30`. Plain
+/// numbers are seconds.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use window::media::parse_duration;
+///
+/// assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+/// assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+/// assert!(parse_duration("-5s").is_err());
+/// ```
+pub fn parse_duration(s: &str) -> Result<Duration, MediaError> {
+    let s = s.trim();
+    parse_time(s).ok_or_else(|| {
+        MediaError::InvalidArgument(format!(
+            "`{}` isn't a duration, durations look like `30s`, `1m30s` or `1:30`",
+            s
+        ))
+    })
+}
+
+/// Parses `1:23`, `1:02:03`, `90`, `90s`, `1m30s` or `1h2m3.5s` into a duration
+fn parse_time(s: &str) -> Option<Duration> {
+    if s.is_empty() {
+        return None;
+    }
+
+    if s.contains(':') {
+        let mut seconds = 0.0;
+        for (i, part) in s.split(':').enumerate() {
+            // Signs only go in front of the whole time, and only the leading
+            // part may count past 59
+            if !part.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                return None;
+            }
+            let value: f64 = part.parse().ok()?;
+            if i > 0 && value >= 60.0 {
+                return None;
+            }
+            seconds = seconds * 60.0 + value;
+        }
+        return seconds_to_duration(seconds);
+    }
+
+    if let Ok(seconds) = s.parse::<f64>() {
+        return seconds_to_duration(seconds);
+    }
+
+    let mut seconds = 0.0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+
+        let value: f64 = number.parse().ok()?;
+        number.clear();
+        seconds += match c {
+            'h' => value * 3600.0,
+            'm' => value * 60.0,
+            's' => value,
+            _ => return None,
+        };
+    }
+    if !number.is_empty() {
+        return None;
+    }
+
+    seconds_to_duration(seconds)
+}
+
+fn seconds_to_duration(seconds: f64) -> Option<Duration> {
+    // Fails on negative, non-finite and too large values rather than panicking
+    Duration::try_from_secs_f64(seconds).ok()
+}
@@ -0,0 +1,433 @@
+use std::time::{Duration, SystemTime};
+
+use futures::{executor::block_on, future::BoxFuture};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controller::ThreadMessage,
+    media::{time, Control, MediaError, Thumbnail},
+};
+
+/// Playback status of a media session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[allow(missing_docs)]
+pub enum PlaybackStatus {
+    Closed,
+    Opened,
+    Changing,
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl std::fmt::Display for PlaybackStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            PlaybackStatus::Closed => "CLOSED",
+            PlaybackStatus::Opened => "OPENED",
+            PlaybackStatus::Changing => "CHANGING",
+            PlaybackStatus::Stopped => "STOPPED",
+            PlaybackStatus::Playing => "PLAYING",
+            PlaybackStatus::Paused => "PAUSED",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// Repeat mode of a media session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum RepeatMode {
+    /// Stop at the end of the list
+    #[default]
+    None,
+    /// Repeat the current track
+    Track,
+    /// Repeat the whole list
+    List,
+}
+
+impl std::fmt::Display for RepeatMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self {
+            RepeatMode::None => "NONE",
+            RepeatMode::Track => "TRACK",
+            RepeatMode::List => "LIST",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+impl std::str::FromStr for RepeatMode {
+    type Err = &'static str;
+
+    /// Parses `none`, `track` or `list`, ignoring case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Ok(RepeatMode::None),
+            "track" | "one" => Ok(RepeatMode::Track),
+            "list" | "all" | "playlist" => Ok(RepeatMode::List),
+            _ => Err("Repeat modes are `none`, `track` and `list`"),
+        }
+    }
+}
+
+/// Kind of media playing in a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+#[allow(missing_docs)]
+pub enum PlaybackType {
+    #[default]
+    Unknown,
+    Music,
+    Video,
+    Image,
+}
+
+/// Metadata of the media playing in a session
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[allow(missing_docs)]
+pub struct MediaProps {
+    pub album_artist: String,
+    pub album_title: String,
+    pub album_track_count: i32,
+    pub artist: String,
+    pub playback_type: PlaybackType,
+    pub subtitle: String,
+    pub title: String,
+    pub track_number: i32,
+}
+
+/// Timeline of the media playing in a session. Positions are measured from
+/// the start of the media. In JSON they are whole milliseconds, with an `_ms`
+/// suffix, and `last_updated` is an ISO-8601 timestamp.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct TimelineProps {
+    /// When the session last reported its position
+    #[serde(rename = "last_updated", with = "time::iso8601")]
+    #[schemars(schema_with = "time::iso8601::schema")]
+    pub last_updated_time: SystemTime,
+    /// Position at `last_updated_time`
+    #[serde(rename = "position_ms", with = "time::millis")]
+    #[schemars(with = "u64")]
+    pub pos: Duration,
+    /// Furthest position the session can seek to
+    #[serde(rename = "max_seek_time_ms", with = "time::millis")]
+    #[schemars(with = "u64")]
+    pub max_seek_time: Duration,
+    /// Earliest position the session can seek to
+    #[serde(rename = "min_seek_time_ms", with = "time::millis")]
+    #[schemars(with = "u64")]
+    pub min_seek_time: Duration,
+    /// End of the media. Zero when the length is unknown, e.g. live streams.
+    #[serde(rename = "end_time_ms", with = "time::millis")]
+    #[schemars(with = "u64")]
+    pub endtime: Duration,
+    /// Start of the media
+    #[serde(rename = "start_time_ms", with = "time::millis")]
+    #[schemars(with = "u64")]
+    pub start_time: Duration,
+}
+
+impl TimelineProps {
+    /// Length of the media, or `None` for live streams and other media of
+    /// unknown length
+    pub fn duration(&self) -> Option<Duration> {
+        let duration = self.endtime.saturating_sub(self.start_time);
+        (!duration.is_zero()).then_some(duration)
+    }
+
+    /// Whether the media has no known length, like a live stream
+    pub fn is_live(&self) -> bool {
+        self.duration().is_none()
+    }
+
+    /// How much of the media was played, from `0.0` to `100.0`. `None` when
+    /// the length is unknown.
+    pub fn finished_percentage(&self) -> Option<f64> {
+        let duration = self.duration()?;
+        let played = self.pos.saturating_sub(self.start_time);
+
+        Some((played.as_secs_f64() / duration.as_secs_f64() * 100.0).min(100.0))
+    }
+}
+
+/// Playback state of a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[allow(missing_docs)]
+pub struct PlaybackInfoProps {
+    pub auto_repeat_mode: RepeatMode,
+    pub active_controls: ActiveControls,
+    pub shuffle_active: bool,
+    pub playback_status: PlaybackStatus,
+    pub playback_type: PlaybackType,
+    pub playback_rate: f64,
+}
+
+/// Controls that the session currently accepts
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize, JsonSchema)]
+#[allow(missing_docs)]
+pub struct ActiveControls {
+    pub is_play_enabled: bool,
+    pub is_pause_enabled: bool,
+    pub is_stop_enabled: bool,
+    pub is_record_enabled: bool,
+    pub is_fast_forward_enabled: bool,
+    pub is_rewind_enabled: bool,
+    pub is_next_enabled: bool,
+    pub is_previous_enabled: bool,
+    pub is_channel_up_enabled: bool,
+    pub is_channel_down_enabled: bool,
+    pub is_play_pause_toggle_enabled: bool,
+    pub is_shuffle_enabled: bool,
+    pub is_repeat_enabled: bool,
+    pub is_playback_rate_enabled: bool,
+    pub is_playback_position_enabled: bool,
+}
+
+impl ActiveControls {
+    /// Whether the session currently accepts `control`
+    pub fn is_enabled(&self, control: Control) -> bool {
+        match control {
+            Control::Play => self.is_play_enabled,
+            Control::Pause => self.is_pause_enabled,
+            Control::Stop => self.is_stop_enabled,
+            Control::Record => self.is_record_enabled,
+            Control::FastForward => self.is_fast_forward_enabled,
+            Control::Rewind => self.is_rewind_enabled,
+            Control::Next => self.is_next_enabled,
+            Control::Previous => self.is_previous_enabled,
+            Control::ChannelUp => self.is_channel_up_enabled,
+            Control::ChannelDown => self.is_channel_down_enabled,
+            Control::PlayPauseToggle => self.is_play_pause_toggle_enabled,
+            Control::Shuffle => self.is_shuffle_enabled,
+            Control::Repeat => self.is_repeat_enabled,
+            Control::PlaybackRate => self.is_playback_rate_enabled,
+            Control::PlaybackPosition => self.is_playback_position_enabled,
+        }
+    }
+}
+
+/// Keeps event listeners attached until it is dropped.
+///
+/// Backends hand one of these out whenever they start forwarding events to a
+/// channel. Dropping it detaches the listeners again.
+pub struct Subscription {
+    detach: Option<Box<dyn FnOnce()>>,
+}
+
+impl Subscription {
+    /// Create a subscription which runs `detach` when it is dropped
+    pub fn new<F>(detach: F) -> Self
+    where
+        F: FnOnce() + 'static,
+    {
+        Subscription {
+            detach: Some(Box::new(detach)),
+        }
+    }
+
+    /// Create a subscription that has nothing to detach
+    pub fn empty() -> Self {
+        Subscription { detach: None }
+    }
+}
+
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("attached", &self.detach.is_some())
+            .finish()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(detach) = self.detach.take() {
+            detach();
+        }
+    }
+}
+
+/// Future of a command sent to a session, resolving to whether the session
+/// accepted it
+pub type CommandFuture<'a> = BoxFuture<'a, Result<bool, MediaError>>;
+
+/// Turns a session refusing a command into [`MediaError::Unsupported`]
+pub(crate) fn accepted(
+    answer: Result<bool, MediaError>,
+    control: Control,
+) -> Result<(), MediaError> {
+    if answer? {
+        Ok(())
+    } else {
+        Err(MediaError::Unsupported(control))
+    }
+}
+
+/// Answer of a session to a command it has no way of following
+pub(crate) fn refused<'a>() -> CommandFuture<'a> {
+    Box::pin(futures::future::ready(Ok(false)))
+}
+
+/// A single media session, usually one per media player.
+///
+/// Backends implement the `*_async` commands, which resolve to whether the
+/// player accepted the command. The blocking commands wait for them and fail
+/// with [`MediaError::Unsupported`] when the player refused.
+pub trait MediaSession: std::fmt::Debug + Send + Sync {
+    /// Identifier of the application which owns the session
+    fn source_app_id(&self) -> String;
+
+    /// Asks the session to resume playback
+    fn play_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to pause
+    fn pause_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to go to the next track
+    fn next_track_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to go to the previous track
+    fn previous_track_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to stop playback
+    fn stop_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to pause when playing and resume otherwise
+    fn toggle_play_pause_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to start fast-forwarding
+    fn fast_forward_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to start rewinding
+    fn rewind_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to switch to the next channel
+    fn channel_up_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to switch to the previous channel
+    fn channel_down_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to start recording
+    fn record_async(&self) -> CommandFuture<'_>;
+    /// Asks the session to move the playback position to `position` from the start of the track
+    fn set_position_async(&self, position: std::time::Duration) -> CommandFuture<'_>;
+    /// Asks the session to turn shuffle on or off
+    fn set_shuffle_async(&self, shuffle: bool) -> CommandFuture<'_>;
+    /// Asks the session to change the repeat mode
+    fn set_repeat_async(&self, mode: RepeatMode) -> CommandFuture<'_>;
+    /// Asks the session to change the playback rate, `1.0` being normal speed
+    fn set_playback_rate_async(&self, rate: f64) -> CommandFuture<'_>;
+
+    /// Resumes playback
+    fn play(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.play_async()), Control::Play)
+    }
+    /// Pauses playback
+    fn pause(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.pause_async()), Control::Pause)
+    }
+    /// Goes to the next track
+    fn next_track(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.next_track_async()), Control::Next)
+    }
+    /// Goes to the previous track
+    fn previous_track(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.previous_track_async()), Control::Previous)
+    }
+    /// Stops playback
+    fn stop(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.stop_async()), Control::Stop)
+    }
+    /// Pauses when playing and resumes otherwise
+    fn toggle_play_pause(&self) -> Result<(), MediaError> {
+        accepted(
+            block_on(self.toggle_play_pause_async()),
+            Control::PlayPauseToggle,
+        )
+    }
+    /// Starts fast-forwarding
+    fn fast_forward(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.fast_forward_async()), Control::FastForward)
+    }
+    /// Starts rewinding
+    fn rewind(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.rewind_async()), Control::Rewind)
+    }
+    /// Switches to the next channel
+    fn channel_up(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.channel_up_async()), Control::ChannelUp)
+    }
+    /// Switches to the previous channel
+    fn channel_down(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.channel_down_async()), Control::ChannelDown)
+    }
+    /// Starts recording
+    fn record(&self) -> Result<(), MediaError> {
+        accepted(block_on(self.record_async()), Control::Record)
+    }
+    /// Moves the playback position to `position` from the start of the track
+    fn set_position(&self, position: std::time::Duration) -> Result<(), MediaError> {
+        accepted(
+            block_on(self.set_position_async(position)),
+            Control::PlaybackPosition,
+        )
+    }
+    /// Turns shuffle on or off
+    fn set_shuffle(&self, shuffle: bool) -> Result<(), MediaError> {
+        accepted(block_on(self.set_shuffle_async(shuffle)), Control::Shuffle)
+    }
+    /// Changes the repeat mode
+    fn set_repeat(&self, mode: RepeatMode) -> Result<(), MediaError> {
+        accepted(block_on(self.set_repeat_async(mode)), Control::Repeat)
+    }
+    /// Changes the playback rate, `1.0` being normal speed
+    fn set_playback_rate(&self, rate: f64) -> Result<(), MediaError> {
+        accepted(
+            block_on(self.set_playback_rate_async(rate)),
+            Control::PlaybackRate,
+        )
+    }
+
+    /// Metadata of the current media
+    fn media_properties(&self) -> Result<MediaProps, MediaError>;
+    /// Reads the thumbnail of the current track, if it has one. With a
+    /// `max_size` the image is scaled down so neither side is larger than it,
+    /// on backends which [scale thumbnails][MediaSession::scales_thumbnails];
+    /// others return it unscaled.
+    fn thumbnail(&self, max_size: Option<u32>) -> Option<Thumbnail>;
+    /// Whether [`MediaSession::thumbnail`] honours `max_size`. Only the
+    /// Windows backend can decode images.
+    fn scales_thumbnails(&self) -> bool {
+        false
+    }
+    /// Identifies the thumbnail of the current track without reading it, like
+    /// the URL a player serves it from. Lets art which a player fills in after
+    /// the track started be told apart. `None` when the backend can't tell.
+    fn thumbnail_id(&self) -> Option<String> {
+        None
+    }
+    /// Timeline of the current media
+    fn timeline_properties(&self) -> Result<TimelineProps, MediaError>;
+    /// Playback state of the session
+    fn playback_info(&self) -> Result<PlaybackInfoProps, MediaError>;
+
+    /// Start sending [`ManagerMessage::MediaChanged`][crate::media::ManagerMessage],
+    /// `TimelineChanged` and `PlaybackInfoChanged` messages, tagged with
+    /// [`MediaSession::source_app_id`], to `tx` whenever this session changes.
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError>;
+}
+
+/// Platform media service which owns the media sessions. Shared between
+/// threads, like the server runs commands on threads of their own.
+pub trait MediaBackend: std::fmt::Debug + Send + Sync {
+    /// Gets the session the platform considers current
+    fn current_session(&self) -> Result<Box<dyn MediaSession>, MediaError>;
+
+    /// Gets every session, in the order the platform lists them
+    fn sessions(&self) -> Result<Vec<Box<dyn MediaSession>>, MediaError>;
+
+    /// Start sending [`ManagerMessage::SessionsChanged`][crate::media::ManagerMessage]
+    /// messages to `tx` whenever the current session changes or a session is
+    /// added or removed.
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError>;
+}
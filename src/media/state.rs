@@ -0,0 +1,108 @@
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::media::{
+    current_timeline, ArtCache, Clock, MediaError, MediaProps, MediaSession, PlaybackInfoProps,
+    TimelineProps,
+};
+
+/// Everything about a session at one instant, as sent to clients.
+///
+/// This is the JSON printed by `current-json` and the shape other clients can
+/// rely on. Fields are only ever added to it, never renamed or removed. Enums
+/// are `SCREAMING_SNAKE_CASE` strings, durations are whole milliseconds with
+/// an `_ms` suffix and times are ISO-8601 UTC timestamps. See
+/// [`media_state_schema`] for the full JSON Schema.
+///
+/// ```json
+/// {
+///   "session_id": "Spotify.exe",
+///   "media": {
+///     "album_artist": "", "album_title": "Some Album", "album_track_count": 12,
+///     "artist": "Some Artist", "playback_type": "MUSIC", "subtitle": "",
+///     "title": "Some Song", "track_number": 3
+///   },
+///   "timeline": {
+///     "last_updated": "2022-05-28T14:03:07.250Z", "position_ms": 83000,
+///     "max_seek_time_ms": 200000, "min_seek_time_ms": 0,
+///     "end_time_ms": 200000, "start_time_ms": 0
+///   },
+///   "playback": {
+///     "auto_repeat_mode": "NONE",
+///     "active_controls": { "is_play_enabled": true, "...": "..." },
+///     "shuffle_active": false, "playback_status": "PLAYING",
+///     "playback_type": "MUSIC", "playback_rate": 1.0
+///   },
+///   "duration_ms": 200000,
+///   "live": false,
+///   "finished_percentage": 42.0,
+///   "art_hash": "5f0c1b4c7bfa5c2ec8e15bd5e4a8e35f7a2d6c11"
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Everything about a media session at one instant. \
+    Fields are only ever added, never renamed or removed.")]
+pub struct MediaState {
+    /// App id of the session, see [`MediaSession::source_app_id`]
+    pub session_id: String,
+    /// Metadata of the current media
+    pub media: MediaProps,
+    /// Timeline of the current media. The position is estimated for the time
+    /// the state was read, which is also its `last_updated` time.
+    pub timeline: TimelineProps,
+    /// Playback state, including the controls the session accepts
+    pub playback: PlaybackInfoProps,
+    /// Length of the media, `null` for live streams and other media of
+    /// unknown length
+    pub duration_ms: Option<u64>,
+    /// Whether the media has no known length, like a live stream
+    pub live: bool,
+    /// How much of the media was played, from `0` to `100`, rounded. `null`
+    /// when the length is unknown.
+    pub finished_percentage: Option<f64>,
+    /// SHA-1 of the album art, `null` without art. Only changes along with
+    /// the art.
+    pub art_hash: Option<String>,
+    /// The album art as a `data:` URL, only when it was asked for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub art: Option<String>,
+}
+
+impl MediaState {
+    /// Reads the state of the given session, with its position estimated at
+    /// the clock's current time
+    pub fn read(session: &dyn MediaSession, clock: &dyn Clock) -> Result<Self, MediaError> {
+        Self::read_cached(session, clock, &mut ArtCache::default())
+    }
+
+    /// Same as [`MediaState::read`], but only hashes the album art when the
+    /// track changed since the last read through `art`
+    pub fn read_cached(
+        session: &dyn MediaSession,
+        clock: &dyn Clock,
+        art: &mut ArtCache,
+    ) -> Result<Self, MediaError> {
+        let timeline = current_timeline(session, clock)?;
+
+        Ok(Self {
+            session_id: session.source_app_id(),
+            media: session.media_properties()?,
+            playback: session.playback_info()?,
+            duration_ms: timeline
+                .duration()
+                .map(|duration| duration.as_millis() as u64),
+            live: timeline.is_live(),
+            finished_percentage: timeline
+                .finished_percentage()
+                .map(|percentage| percentage.round()),
+            timeline,
+            art_hash: art.get(session, None).map(|art| art.hash()),
+            art: None,
+        })
+    }
+}
+
+/// JSON Schema of [`MediaState`]
+pub fn media_state_schema() -> RootSchema {
+    schema_for!(MediaState)
+}
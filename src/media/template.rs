@@ -0,0 +1,494 @@
+use std::time::Duration;
+
+use serde_json::Value as Json;
+
+use crate::media::MediaState;
+
+/// A format string for [`MediaState`]s, like `{{artist}} - {{title}}`.
+///
+/// `{{name}}` is replaced with a field of the state. Names are looked up in
+/// the JSON of the state, so every field of it can be used, either by its
+/// path (`{{media.title}}`) or just by its name (`{{title}}`). A few names are
+/// added for convenience:
+///
+/// - `position`, `duration` and `remaining` are durations, printed like `1:23`.
+///   `duration` and `remaining` are missing for live streams.
+/// - `status` is the playback status, `album` the album title and `session`
+///   the app id of the session.
+///
+/// Values go through filters separated by `|`, e.g. `{{title | trunc 20}}`:
+///
+/// - `clock`, `secs` and `ms` print durations as `1:23`, whole seconds or
+///   milliseconds. Plain numbers are taken to be milliseconds.
+/// - `upper` and `lower` change the case.
+/// - `trunc N` cuts the text down to `N` characters, ending it with `…`.
+/// - `shell`, `json` and `markup` escape the text for a shell, a JSON string
+///   or Pango and XML markup.
+/// - `default "text"` replaces a missing or empty value.
+///
+/// `{{#if name}}…{{else}}…{{/if}}` only prints its first part when the value
+/// is there, and neither empty, `false` nor zero. `{{#unless name}}` is the
+/// opposite. `{{"text"}}` prints text as is, e.g. `{{"{{"}}`.
+///
+/// # Example
+/// ```
+/// use window::media::{
+///     FakeBackend, FakeScript, FakeTrack, MediaBackend, MediaState, SystemClock, Template,
+/// };
+///
+/// let backend = FakeBackend::new(FakeScript {
+///     tracks: vec![FakeTrack {
+///         title: "Some Song".to_string(),
+///         duration_ms: 200_000,
+///         ..Default::default()
+///     }],
+///     ..Default::default()
+/// });
+/// let session = backend.current_session().unwrap();
+/// let state = MediaState::read(&*session, &SystemClock).unwrap();
+///
+/// let template: Template =
+///     "{{#if artist}}{{artist}} - {{/if}}{{title | upper}} [{{position}}/{{duration}}]"
+///         .parse()
+///         .unwrap();
+/// assert_eq!(template.render(&state), "SOME SONG [0:00/3:20]");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Expr(Expr),
+    If {
+        negate: bool,
+        condition: Expr,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Expr {
+    source: Source,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Source {
+    Field(String),
+    Literal(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Filter {
+    Clock,
+    Secs,
+    Ms,
+    Upper,
+    Lower,
+    Trunc(usize),
+    Shell,
+    Json,
+    Markup,
+    Default(String),
+}
+
+/// A value while it goes through the filters
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Missing,
+    Text(String),
+    Number(f64),
+    Bool(bool),
+    Duration(Duration),
+}
+
+impl Template {
+    /// Fills in the template with the given state
+    pub fn render(&self, state: &MediaState) -> String {
+        let json = serde_json::to_value(state).unwrap_or(Json::Null);
+        let mut out = String::new();
+        render_nodes(&self.nodes, state, &json, &mut out);
+
+        out
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut blocks = vec![Block::default()];
+        let mut rest = s;
+
+        while let Some(start) = rest.find("{{") {
+            push_text(&mut blocks, &rest[..start]);
+            let (tag, after) = split_tag(&rest[start + 2..])?;
+            rest = after;
+
+            let tag = tag.trim();
+            if let Some(condition) = tag.strip_prefix("#if ") {
+                blocks.push(Block::open(false, parse_expr(condition)?));
+            } else if let Some(condition) = tag.strip_prefix("#unless ") {
+                blocks.push(Block::open(true, parse_expr(condition)?));
+            } else if tag == "else" {
+                match blocks.last_mut() {
+                    Some(block) if block.condition.is_some() && !block.in_else => {
+                        block.in_else = true;
+                    }
+                    _ => return Err("`{{else}}` outside of `{{#if}}`".to_string()),
+                }
+            } else if tag == "/if" || tag == "/unless" {
+                if blocks.len() < 2
+                    || blocks.last().map(|block| block.negate) != Some(tag == "/unless")
+                {
+                    return Err(format!("`{{{{{}}}}}` without a matching opening tag", tag));
+                }
+                let block = blocks.pop().unwrap();
+                blocks.last_mut().unwrap().push(block.close());
+            } else {
+                let expr = parse_expr(tag)?;
+                blocks.last_mut().unwrap().push(Node::Expr(expr));
+            }
+        }
+        push_text(&mut blocks, rest);
+
+        if blocks.len() > 1 {
+            return Err("`{{#if}}` is never closed with `{{/if}}`".to_string());
+        }
+
+        Ok(Self {
+            nodes: blocks.pop().unwrap().then,
+        })
+    }
+}
+
+/// Nodes of the template, or of an `#if` while it is being parsed
+#[derive(Debug, Default)]
+struct Block {
+    negate: bool,
+    condition: Option<Expr>,
+    then: Vec<Node>,
+    otherwise: Vec<Node>,
+    in_else: bool,
+}
+
+impl Block {
+    fn open(negate: bool, condition: Expr) -> Self {
+        Self {
+            negate,
+            condition: Some(condition),
+            ..Default::default()
+        }
+    }
+
+    fn push(&mut self, node: Node) {
+        if self.in_else {
+            self.otherwise.push(node);
+        } else {
+            self.then.push(node);
+        }
+    }
+
+    fn close(self) -> Node {
+        Node::If {
+            negate: self.negate,
+            condition: self.condition.unwrap(),
+            then: self.then,
+            otherwise: self.otherwise,
+        }
+    }
+}
+
+fn push_text(blocks: &mut [Block], text: &str) {
+    if !text.is_empty() {
+        let block = blocks.last_mut().unwrap();
+        block.push(Node::Text(text.to_string()));
+    }
+}
+
+/// Splits `s` after the `}}` closing the current tag, skipping over quoted
+/// text
+fn split_tag(s: &str) -> Result<(&str, &str), String> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '}' if !quoted && s[i..].starts_with("}}") => return Ok((&s[..i], &s[i + 2..])),
+            _ => {}
+        }
+    }
+
+    Err("`{{` is never closed with `}}`".to_string())
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    let mut parts = split_pipes(s).into_iter();
+    let source = parts.next().unwrap_or_default();
+    let source = match parse_string(source.trim())? {
+        Some(literal) => Source::Literal(literal),
+        None if is_field_name(source.trim()) => Source::Field(source.trim().to_string()),
+        None => return Err(format!("`{}` is not a field name", source.trim())),
+    };
+
+    let filters = parts
+        .map(|filter| parse_filter(&filter))
+        .collect::<Result<_, _>>()?;
+
+    Ok(Expr { source, filters })
+}
+
+fn is_field_name(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Splits an expression at the `|` outside of quotes
+fn split_pipes(s: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in s.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '|' if !quoted => {
+                parts.push(String::new());
+                continue;
+            }
+            _ => {}
+        }
+        parts.last_mut().unwrap().push(c);
+    }
+
+    parts
+}
+
+/// Parses a quoted string, `None` if `s` isn't quoted
+fn parse_string(s: &str) -> Result<Option<String>, String> {
+    if !s.starts_with('"') {
+        return Ok(None);
+    }
+
+    serde_json::from_str(s)
+        .map(Some)
+        .map_err(|_| format!("{} is not a valid string", s))
+}
+
+fn parse_filter(s: &str) -> Result<Filter, String> {
+    let s = s.trim();
+    let (name, argument) = match s.split_once(char::is_whitespace) {
+        Some((name, argument)) => (name, Some(argument.trim())),
+        None => (s, None),
+    };
+
+    let filter = match (name, argument) {
+        ("clock", None) => Filter::Clock,
+        ("secs", None) => Filter::Secs,
+        ("ms", None) => Filter::Ms,
+        ("upper", None) => Filter::Upper,
+        ("lower", None) => Filter::Lower,
+        ("shell", None) => Filter::Shell,
+        ("json", None) => Filter::Json,
+        ("markup", None) => Filter::Markup,
+        ("trunc", Some(length)) => Filter::Trunc(
+            length
+                .parse()
+                .map_err(|_| format!("`trunc` needs a length, not `{}`", length))?,
+        ),
+        ("trunc", None) => return Err("`trunc` needs a length".to_string()),
+        ("default", Some(text)) => match parse_string(text)? {
+            Some(text) => Filter::Default(text),
+            None => return Err(format!("`default` needs quoted text, not `{}`", text)),
+        },
+        ("default", None) => return Err("`default` needs quoted text".to_string()),
+        ("clock" | "secs" | "ms" | "upper" | "lower" | "shell" | "json" | "markup", Some(_)) => {
+            return Err(format!("`{}` doesn't take an argument", name))
+        }
+        (name, _) => return Err(format!("There is no filter called `{}`", name)),
+    };
+
+    Ok(filter)
+}
+
+fn render_nodes(nodes: &[Node], state: &MediaState, json: &Json, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Expr(expr) => out.push_str(&eval(expr, state, json).to_string()),
+            Node::If {
+                negate,
+                condition,
+                then,
+                otherwise,
+            } => {
+                let nodes = if eval(condition, state, json).is_truthy() != *negate {
+                    then
+                } else {
+                    otherwise
+                };
+                render_nodes(nodes, state, json, out);
+            }
+        }
+    }
+}
+
+fn eval(expr: &Expr, state: &MediaState, json: &Json) -> Value {
+    let value = match &expr.source {
+        Source::Literal(text) => Value::Text(text.clone()),
+        Source::Field(name) => field(name, state, json),
+    };
+
+    expr.filters
+        .iter()
+        .fold(value, |value, filter| value.apply(filter))
+}
+
+/// Looks up a field of the state
+fn field(name: &str, state: &MediaState, json: &Json) -> Value {
+    let duration = state.timeline.duration();
+    match name {
+        "position" => return Value::Duration(state.timeline.pos),
+        "duration" => return duration.map_or(Value::Missing, Value::Duration),
+        "remaining" => {
+            return duration.map_or(Value::Missing, |duration| {
+                Value::Duration(duration.saturating_sub(state.timeline.pos))
+            })
+        }
+        _ => {}
+    }
+
+    let path = match name {
+        "status" => "playback.playback_status",
+        "album" => "media.album_title",
+        "session" => "session_id",
+        _ => name,
+    };
+
+    let found = lookup(json, path).or_else(|| {
+        ["media", "playback", "timeline"]
+            .iter()
+            .find_map(|section| lookup(&json[section], path))
+    });
+    match found {
+        Some(Json::String(text)) => Value::Text(text.clone()),
+        Some(Json::Number(number)) => number.as_f64().map_or(Value::Missing, Value::Number),
+        Some(Json::Bool(value)) => Value::Bool(*value),
+        Some(Json::Null) | None => Value::Missing,
+        Some(other) => Value::Text(other.to_string()),
+    }
+}
+
+fn lookup<'a>(json: &'a Json, path: &str) -> Option<&'a Json> {
+    path.split('.').try_fold(json, |json, key| json.get(key))
+}
+
+impl Value {
+    fn apply(self, filter: &Filter) -> Value {
+        match (filter, self) {
+            (Filter::Clock, Value::Duration(duration)) => Value::Text(clock(duration)),
+            (Filter::Clock, Value::Number(ms)) => Value::Text(clock(millis(ms))),
+            (Filter::Secs, Value::Duration(duration)) => Value::Number(duration.as_secs() as f64),
+            (Filter::Secs, Value::Number(ms)) => Value::Number(millis(ms).as_secs() as f64),
+            (Filter::Ms, Value::Duration(duration)) => Value::Number(duration.as_millis() as f64),
+            (Filter::Clock | Filter::Secs | Filter::Ms, value) => value,
+            (Filter::Default(text), value) if !value.is_present() => Value::Text(text.clone()),
+            (Filter::Default(_), value) => value,
+            (_, Value::Missing) => Value::Missing,
+            (Filter::Upper, value) => Value::Text(value.to_string().to_uppercase()),
+            (Filter::Lower, value) => Value::Text(value.to_string().to_lowercase()),
+            (Filter::Trunc(length), value) => Value::Text(truncate(&value.to_string(), *length)),
+            (Filter::Shell, value) => {
+                Value::Text(format!("'{}'", value.to_string().replace('\'', r"'\''")))
+            }
+            (Filter::Json, value) => {
+                Value::Text(serde_json::to_string(&value.to_string()).unwrap_or_default())
+            }
+            (Filter::Markup, value) => Value::Text(escape_markup(&value.to_string())),
+        }
+    }
+
+    /// Whether the value is there at all, empty text counts as missing
+    fn is_present(&self) -> bool {
+        match self {
+            Value::Missing => false,
+            Value::Text(text) => !text.is_empty(),
+            _ => true,
+        }
+    }
+
+    fn is_truthy(&self) -> bool {
+        match self {
+            Value::Bool(value) => *value,
+            Value::Number(number) => *number != 0.0,
+            Value::Duration(duration) => !duration.is_zero(),
+            value => value.is_present(),
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Missing => Ok(()),
+            Value::Text(text) => f.write_str(text),
+            Value::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => {
+                write!(f, "{}", *number as i64)
+            }
+            Value::Number(number) => write!(f, "{}", number),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Duration(duration) => f.write_str(&clock(*duration)),
+        }
+    }
+}
+
+fn millis(ms: f64) -> Duration {
+    Duration::from_millis(ms.max(0.0) as u64)
+}
+
+/// Formats a duration like `1:23`, or `1:02:03` once it is an hour or longer
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(length.saturating_sub(1)).collect();
+    if length > 0 {
+        truncated.push('…');
+    }
+    truncated
+}
+
+/// Escapes text for Pango and XML markup
+pub(crate) fn escape_markup(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,178 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 100ns ticks between 1601-01-01, the epoch of WinRT's `DateTime`, and the
+/// unix epoch
+const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+
+/// Converts WinRT 100ns ticks into a duration. Negative ticks become zero.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn ticks_to_duration(ticks: i64) -> Duration {
+    Duration::from_nanos(ticks.max(0) as u64 * 100)
+}
+
+/// Converts a duration into WinRT 100ns ticks
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn duration_to_ticks(duration: Duration) -> i64 {
+    (duration.as_nanos() / 100) as i64
+}
+
+/// Converts WinRT 100ns ticks since 1601-01-01 into a system time
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) fn ticks_to_system_time(ticks: i64) -> SystemTime {
+    let since_unix_epoch = ticks - UNIX_EPOCH_TICKS;
+    if since_unix_epoch >= 0 {
+        UNIX_EPOCH + ticks_to_duration(since_unix_epoch)
+    } else {
+        UNIX_EPOCH - ticks_to_duration(-since_unix_epoch)
+    }
+}
+
+/// Formats a system time as an ISO-8601 UTC timestamp with milliseconds, e.g.
+/// `2022-05-28T14:03:07.250Z`. Times before the unix epoch are clamped to it.
+pub(crate) fn format_iso8601(time: SystemTime) -> String {
+    let since_unix_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_unix_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let secs_of_day = secs % 86_400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_unix_epoch.subsec_millis()
+    )
+}
+
+/// Turns days since the unix epoch into a proleptic Gregorian date, see
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month as u32, day as u32)
+}
+
+/// Parses the ISO-8601 UTC timestamps written by [`format_iso8601`]. The
+/// fraction of a second may have any number of digits or be left out.
+pub(crate) fn parse_iso8601(s: &str) -> Option<SystemTime> {
+    let s = s.strip_suffix('Z')?;
+    let (date, time) = s.split_once('T')?;
+
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let (time, fraction) = match time.split_once('.') {
+        Some((time, fraction)) => (time, Some(fraction)),
+        None => (time, None),
+    };
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    let nanos = match fraction {
+        Some(fraction) if fraction.bytes().all(|b| b.is_ascii_digit()) && !fraction.is_empty() => {
+            format!("{:0<9}", &fraction[..fraction.len().min(9)])
+                .parse()
+                .ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    let days = days_from_civil(year, month as u32, day as u32);
+    let secs = days * 86_400 + (hours * 3600 + minutes * 60 + seconds) as i64;
+    let since_unix_epoch = Duration::new(secs.unsigned_abs(), 0);
+    if secs >= 0 {
+        Some(UNIX_EPOCH + since_unix_epoch + Duration::from_nanos(nanos))
+    } else {
+        Some(UNIX_EPOCH - since_unix_epoch + Duration::from_nanos(nanos))
+    }
+}
+
+/// Turns a proleptic Gregorian date into days since the unix epoch, the
+/// inverse of [`civil_from_days`]
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// (De)serializes a `Duration` as whole milliseconds
+pub(crate) mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        duration: &Duration,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+/// (De)serializes a `SystemTime` as an ISO-8601 UTC timestamp
+pub(crate) mod iso8601 {
+    use std::time::SystemTime;
+
+    use schemars::{
+        gen::SchemaGenerator,
+        schema::{InstanceType, Schema, SchemaObject},
+    };
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(crate) fn serialize<S: Serializer>(
+        time: &SystemTime,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_iso8601(*time))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SystemTime, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        super::parse_iso8601(&s)
+            .ok_or_else(|| D::Error::custom(format!("invalid ISO-8601 timestamp `{}`", s)))
+    }
+
+    pub(crate) fn schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            format: Some("date-time".to_string()),
+            ..Default::default()
+        }
+        .into()
+    }
+}
@@ -0,0 +1,235 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::media::{time, MediaError};
+
+/// A phone, or anything else, paired with the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    /// Short id to manage the device by
+    pub id: String,
+    /// Name the device gave itself when pairing, or was renamed to
+    pub name: String,
+    /// When the device was paired
+    #[serde(with = "time::iso8601")]
+    pub paired_at: SystemTime,
+}
+
+/// A device as kept in the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    device: Device,
+    /// SHA-1 of the token of the device. The token itself is only ever known
+    /// to the device.
+    token_sha1: String,
+}
+
+/// The paired devices, kept in a JSON file. Changes made to the file by
+/// another process, like `window devices revoke`, are picked up the next time
+/// a token is checked.
+#[derive(Debug)]
+pub struct Devices {
+    path: PathBuf,
+    entries: Vec<Entry>,
+    /// Which version of the file was read, see [`version`]
+    version: Option<Version>,
+}
+
+impl Devices {
+    /// Reads the devices kept in `path`. There are no devices yet when the
+    /// file doesn't exist.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, MediaError> {
+        let mut devices = Self {
+            path: path.into(),
+            entries: vec![],
+            version: None,
+        };
+        devices.reload()?;
+
+        Ok(devices)
+    }
+
+    /// The paired devices, oldest first
+    pub fn list(&self) -> Vec<&Device> {
+        self.entries.iter().map(|entry| &entry.device).collect()
+    }
+
+    /// Pairs a new device called `name`. Returns the device and the token it
+    /// has to present from now on.
+    pub fn pair(&mut self, name: &str) -> Result<(Device, String), MediaError> {
+        let name = check_name(name)?;
+        self.reload()?;
+
+        let token = random_hex(32)?;
+        let device = Device {
+            id: random_hex(4)?,
+            name: name.to_string(),
+            paired_at: SystemTime::now(),
+        };
+        self.entries.push(Entry {
+            device: device.clone(),
+            token_sha1: hash(&token),
+        });
+        self.save()?;
+
+        Ok((device, token))
+    }
+
+    /// Renames the device with the id `id`
+    pub fn rename(&mut self, id: &str, name: &str) -> Result<(), MediaError> {
+        let name = check_name(name)?;
+        self.reload()?;
+        self.find(id)?.device.name = name.to_string();
+
+        self.save()
+    }
+
+    /// Forgets the device with the id `id`, its token stops working
+    pub fn revoke(&mut self, id: &str) -> Result<Device, MediaError> {
+        self.reload()?;
+        let device = self.find(id)?.device.clone();
+        self.entries.retain(|entry| entry.device.id != id);
+        self.save()?;
+
+        Ok(device)
+    }
+
+    /// The device `token` belongs to, if any
+    pub fn authenticate(&mut self, token: &str) -> Option<&Device> {
+        if let Err(error) = self.reload_if_changed() {
+            eprintln!("[Server] Could not read the paired devices: {}", error);
+        }
+
+        let token_sha1 = hash(token);
+        self.entries
+            .iter()
+            .find(|entry| constant_time_eq(&entry.token_sha1, &token_sha1))
+            .map(|entry| &entry.device)
+    }
+
+    fn find(&mut self, id: &str) -> Result<&mut Entry, MediaError> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.device.id == id)
+            .ok_or_else(|| {
+                MediaError::InvalidArgument("There is no device with this id".to_string())
+            })
+    }
+
+    fn reload_if_changed(&mut self) -> Result<(), MediaError> {
+        if version(&self.path) != self.version {
+            self.reload()?;
+        }
+
+        Ok(())
+    }
+
+    fn reload(&mut self) -> Result<(), MediaError> {
+        self.version = version(&self.path);
+        self.entries = match std::fs::read(&self.path) {
+            Ok(json) => serde_json::from_slice(&json).map_err(|error| {
+                MediaError::Backend(format!("The paired devices are not valid: {}", error))
+            })?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => vec![],
+            Err(error) => {
+                return Err(MediaError::Backend(format!(
+                    "Could not open the paired devices: {}",
+                    error
+                )))
+            }
+        };
+
+        Ok(())
+    }
+
+    /// Writes the devices, readable only by the user as they are what lets
+    /// the devices in
+    fn save(&mut self) -> Result<(), MediaError> {
+        write_private(
+            &self.path,
+            &serde_json::to_vec_pretty(&self.entries).unwrap(),
+        )
+        .map_err(|error| {
+            MediaError::Backend(format!("Could not save the paired devices: {}", error))
+        })?;
+
+        self.version = version(&self.path);
+        Ok(())
+    }
+}
+
+/// Writes `contents` to a new file only the user can read, then replaces the
+/// file at `path` with it so readers never see half of it
+pub(crate) fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let tmp = path.with_extension("json.tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    std::fs::rename(&tmp, path)
+}
+
+/// When a file was last changed and how long it was then
+pub(crate) type Version = (SystemTime, u64);
+
+/// Which version of the file at `path` is there, if it exists. The length
+/// tells apart most writes which land within the resolution of the
+/// timestamps.
+pub(crate) fn version(path: &Path) -> Option<Version> {
+    let metadata = std::fs::metadata(path).ok()?;
+
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// The name a device is paired or renamed with, without surrounding spaces
+pub(crate) fn check_name(name: &str) -> Result<&str, MediaError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        return Err(MediaError::InvalidArgument(
+            "The name needs 1 to 64 characters".to_string(),
+        ));
+    }
+
+    Ok(name)
+}
+
+/// `len` random bytes as hex
+pub(crate) fn random_hex(len: usize) -> Result<String, MediaError> {
+    let mut bytes = vec![0; len];
+    getrandom::getrandom(&mut bytes).map_err(|error| {
+        MediaError::Backend(format!("Could not generate a random token: {}", error))
+    })?;
+
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn hash(token: &str) -> String {
+    Sha1::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Compares without giving away how much of the strings matched
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
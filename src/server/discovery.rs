@@ -0,0 +1,225 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+
+use super::CertificateStatus;
+use crate::media::MediaError;
+
+/// DNS-SD service type servers are advertised as
+pub const SERVICE_TYPE: &str = "_window._tcp.local.";
+
+/// Version of the protocol of the server, advertised as `version`
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// What the server offers, advertised as `caps`: `rest` for the routes and
+/// `events` for the WebSocket. Servers speaking TLS add `tls`.
+pub const CAPABILITIES: [&str; 2] = ["rest", "events"];
+
+/// How long unregistering waits for the goodbye to be sent
+const GOODBYE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Advertises a server as a `_window._tcp.local` service for as long as it is
+/// kept. The TXT record has the protocol `version`, the `host` name and the
+/// `caps` of the server, and with TLS the fingerprint of its certificate as
+/// `fp` and of the next one as `fp_next`, see [`Advertisement::update`].
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    name: String,
+    port: u16,
+    certificates: Option<CertificateStatus>,
+    fullname: String,
+}
+
+impl std::fmt::Debug for Advertisement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Advertisement")
+            .field("fullname", &self.fullname)
+            .field("certificates", &self.certificates)
+            .finish()
+    }
+}
+
+impl Advertisement {
+    /// Advertise the server listening on `port` of every address of this
+    /// machine as the instance `name`, with the `certificates` it speaks TLS
+    /// with if any
+    pub fn start(
+        name: &str,
+        port: u16,
+        certificates: Option<&CertificateStatus>,
+    ) -> Result<Self, MediaError> {
+        let daemon = ServiceDaemon::new().map_err(advertise_error)?;
+        let info = service_info(name, port, certificates)?;
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info).map_err(advertise_error)?;
+
+        Ok(Self {
+            daemon,
+            name: name.to_string(),
+            port,
+            certificates: certificates.cloned(),
+            fullname,
+        })
+    }
+
+    /// Advertise `certificates` from now on, as they changed after a rotation.
+    /// Registering the service again announces the new TXT record.
+    pub fn update(&mut self, certificates: Option<&CertificateStatus>) -> Result<(), MediaError> {
+        if self.certificates.as_ref() == certificates {
+            return Ok(());
+        }
+
+        let info = service_info(&self.name, self.port, certificates)?;
+        self.daemon.register(info).map_err(advertise_error)?;
+        self.certificates = certificates.cloned();
+
+        Ok(())
+    }
+}
+
+/// The service of the server listening on `port`, with the TXT record
+/// described at [`Advertisement`]
+fn service_info(
+    name: &str,
+    port: u16,
+    certificates: Option<&CertificateStatus>,
+) -> Result<ServiceInfo, MediaError> {
+    let host = host_name();
+    let version = PROTOCOL_VERSION.to_string();
+    let mut caps = CAPABILITIES.to_vec();
+    if certificates.is_some() {
+        caps.push("tls");
+    }
+    let caps = caps.join(",");
+    let mut properties = vec![
+        ("version", version.as_str()),
+        ("host", host.as_str()),
+        ("caps", caps.as_str()),
+    ];
+    if let Some(certificates) = certificates {
+        properties.push(("fp", &certificates.fingerprint));
+        if let Some(next) = &certificates.next {
+            properties.push(("fp_next", &next.fingerprint));
+        }
+    }
+
+    Ok(ServiceInfo::new(
+        SERVICE_TYPE,
+        name,
+        &format!("{}.local.", host),
+        "",
+        port,
+        &properties[..],
+    )
+    .map_err(advertise_error)?
+    .enable_addr_auto())
+}
+
+fn advertise_error(error: mdns_sd::Error) -> MediaError {
+    MediaError::Backend(format!("Could not advertise the server: {}", error))
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        // Say goodbye so browsers forget the server right away
+        if let Ok(status) = self.daemon.unregister(&self.fullname) {
+            status.recv_timeout(GOODBYE_TIMEOUT).ok();
+        }
+        self.daemon.shutdown().ok();
+    }
+}
+
+/// Server found on the network by [`discover`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Instance {
+    /// Instance name the server is advertised as
+    pub name: String,
+    /// Host name of the machine, from the TXT record
+    pub host: String,
+    /// Addresses the server is reachable at
+    pub addresses: Vec<IpAddr>,
+    /// Port the server listens on
+    pub port: u16,
+    /// Protocol version of the server
+    pub version: Option<u32>,
+    /// What the server offers, see [`CAPABILITIES`]
+    pub capabilities: Vec<String>,
+    /// Fingerprint of the certificate of the server, when it speaks TLS
+    pub fingerprint: Option<String>,
+    /// Fingerprint of the certificate the server is rotating to
+    pub next_fingerprint: Option<String>,
+}
+
+/// Browses the network for servers for `timeout`
+pub fn discover(timeout: Duration) -> Result<Vec<Instance>, MediaError> {
+    let error = |error: mdns_sd::Error| {
+        MediaError::Backend(format!("Could not browse the network: {}", error))
+    };
+
+    let daemon = ServiceDaemon::new().map_err(error)?;
+    let events = daemon.browse(SERVICE_TYPE).map_err(error)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut found = HashMap::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        match event {
+            ServiceEvent::ServiceResolved(info) => {
+                found.insert(info.get_fullname().to_string(), instance(&info));
+            }
+            ServiceEvent::ServiceRemoved(_, fullname) => {
+                found.remove(&fullname);
+            }
+            _ => {}
+        }
+    }
+    daemon.shutdown().ok();
+
+    let mut instances: Vec<Instance> = found.into_values().collect();
+    instances.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(instances)
+}
+
+fn instance(info: &ServiceInfo) -> Instance {
+    let name = info
+        .get_fullname()
+        .strip_suffix(SERVICE_TYPE)
+        .unwrap_or(info.get_fullname())
+        .trim_end_matches('.')
+        .to_string();
+    let mut addresses: Vec<IpAddr> = info.get_addresses().iter().copied().collect();
+    addresses.sort();
+
+    Instance {
+        name,
+        host: info
+            .get_property_val_str("host")
+            .unwrap_or_else(|| info.get_hostname().trim_end_matches(".local."))
+            .to_string(),
+        addresses,
+        port: info.get_port(),
+        version: info
+            .get_property_val_str("version")
+            .and_then(|version| version.parse().ok()),
+        capabilities: info
+            .get_property_val_str("caps")
+            .map(|caps| caps.split(',').map(str::to_string).collect())
+            .unwrap_or_default(),
+        fingerprint: info.get_property_val_str("fp").map(str::to_string),
+        next_fingerprint: info.get_property_val_str("fp_next").map(str::to_string),
+    }
+}
+
+/// Name of this machine, without its domain
+pub fn host_name() -> String {
+    let host = gethostname::gethostname().to_string_lossy().into_owned();
+    match host.split('.').next() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => "window".to_string(),
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::{self, Read, Write};
+
+use super::tls::Stream;
+
+/// Longest request head read before giving up on the client
+const MAX_HEAD: usize = 16 * 1024;
+
+/// Longest request body read
+const MAX_BODY: u64 = 64 * 1024;
+
+/// Head of a request, read off a connection
+#[derive(Debug, Clone)]
+pub(crate) struct HttpRequest {
+    pub(crate) method: String,
+    /// Path without the query
+    pub(crate) path: String,
+    /// Query of the path, without the `?`
+    pub(crate) query: String,
+    pub(crate) headers: Vec<(String, String)>,
+    /// Bytes read past the head
+    pub(crate) rest: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Reads the head of the next request on `stream`
+    pub(crate) fn read(stream: &mut Stream) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            let read = stream.read(&mut chunk)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            buf.extend_from_slice(&chunk[..read]);
+
+            let mut headers = [httparse::EMPTY_HEADER; 64];
+            let mut request = httparse::Request::new(&mut headers);
+            match request.parse(&buf) {
+                Ok(httparse::Status::Complete(len)) => {
+                    let path = request.path.unwrap_or("/");
+                    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+                    return Ok(Self {
+                        method: request.method.unwrap_or_default().to_string(),
+                        path: path.to_string(),
+                        query: query.to_string(),
+                        headers: request
+                            .headers
+                            .iter()
+                            .map(|header| {
+                                (
+                                    header.name.to_string(),
+                                    String::from_utf8_lossy(header.value).into_owned(),
+                                )
+                            })
+                            .collect(),
+                        rest: buf[len..].to_vec(),
+                    });
+                }
+                Ok(httparse::Status::Partial) if buf.len() < MAX_HEAD => {}
+                Ok(httparse::Status::Partial) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the request head is too long",
+                    ))
+                }
+                Err(error) => return Err(io::Error::new(io::ErrorKind::InvalidData, error)),
+            }
+        }
+    }
+
+    /// Value of the header called `name`, in any case
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the client asks to switch to a WebSocket
+    pub(crate) fn is_websocket(&self) -> bool {
+        self.header("Upgrade")
+            .is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+    }
+
+    /// Value of the query parameter called `name`, as sent
+    pub(crate) fn query_param(&self, name: &str) -> Option<&str> {
+        self.query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Token the client authenticates with, sent as `Authorization: Bearer`
+    /// or, for WebSockets which can't set headers in browsers, as the `token`
+    /// query parameter
+    pub(crate) fn token(&self) -> Option<&str> {
+        self.header("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .or_else(|| self.query_param("token"))
+            .map(str::trim)
+    }
+
+    /// Reads the body off `stream`, up to `MAX_BODY`. Reading it all also
+    /// keeps closing the connection from resetting it before the client read
+    /// the answer.
+    pub(crate) fn body(&self, stream: &mut Stream) -> io::Result<Vec<u8>> {
+        let length: u64 = self
+            .header("Content-Length")
+            .and_then(|length| length.trim().parse().ok())
+            .unwrap_or(0);
+        let mut body = self.rest.clone();
+        body.truncate(length as usize);
+        let remaining = length.saturating_sub(body.len() as u64).min(MAX_BODY);
+        stream.take(remaining).read_to_end(&mut body)?;
+
+        Ok(body)
+    }
+}
+
+/// Writes a JSON answer and closes the connection
+pub(crate) fn respond(
+    stream: &mut Stream,
+    status: u16,
+    body: &serde_json::Value,
+) -> io::Result<()> {
+    let body = body.to_string();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n\
+        Content-Type: application/json\r\n\
+        Content-Length: {}\r\n\
+        Connection: close\r\n\
+        \r\n\
+        {}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    stream.close()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        426 => "Upgrade Required",
+        500 => "Internal Server Error",
+        _ => "",
+    }
+}
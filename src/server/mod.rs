@@ -0,0 +1,716 @@
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::{Sender, TrySendError};
+use serde_json::json;
+
+use crate::{
+    controller::ThreadMessage,
+    media::{
+        next_track, pause, play, previous_track, select_session, EventRecord, MediaBackend,
+        MediaError, MediaSession, MediaState, SessionSelector, SystemClock,
+    },
+};
+
+mod devices;
+pub use devices::*;
+mod discovery;
+pub use discovery::*;
+mod http;
+mod pairing;
+pub use pairing::*;
+mod socket;
+mod tls;
+pub use tls::*;
+
+use http::HttpRequest;
+use pairing::PairingError;
+use socket::Client;
+use tls::{Stream, Tls};
+
+/// How long the server waits for a connection before it checks whether it
+/// was stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the server checks whether its certificates changed, to keep its
+/// advertisement up to date
+const ADVERTISEMENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many records may wait for a WebSocket client before it is considered
+/// too slow and dropped
+const CLIENT_BUFFER: usize = 256;
+
+/// Commands of the server, `current` being the only one which doesn't change
+/// anything
+const COMMANDS: [&str; 5] = ["play", "pause", "next", "previous", "current"];
+
+/// HTTP server controlling the media sessions of a backend.
+///
+/// | Route              | Does                                       |
+/// |--------------------|--------------------------------------------|
+/// | `POST /play`       | Resumes playback                           |
+/// | `POST /pause`      | Pauses playback                            |
+/// | `POST /next`       | Plays the next track                       |
+/// | `POST /previous`   | Plays the previous track                   |
+/// | `GET /current`     | The [`MediaState`] of the session, as JSON |
+/// | `GET /events`      | WebSocket of the changes to the sessions   |
+/// | `POST /pair`       | Pairs a device, see below                  |
+/// | `GET /certificate` | The [`CertificateStatus`] of the server    |
+///
+/// Controls answer `{"ok": true}`. Errors answer with a status matching the
+/// error and the same JSON object `--json` prints, like
+/// `{"error": "no_session", "message": "..."}`.
+///
+/// The WebSocket first sends a `snapshot` [`EventRecord`] with the state of
+/// the session, then a record for everything the [`Manager`] publishes, as
+/// `watch --json` prints them. Clients send commands on it as
+/// `{"command": "pause", "id": 1}`, named as the routes, and get back
+/// `{"type": "reply", "id": 1, "status": 200, "body": {"ok": true}}` with the
+/// status and body the route answers. Clients which fall too far behind are
+/// disconnected rather than holding anything up.
+///
+/// With [`Server::devices`], only paired devices are let in: every request
+/// but `/pair` needs the token of a device as `Authorization: Bearer <token>`,
+/// or as `?token=<token>` when opening the WebSocket, and is answered with
+/// `401` otherwise. While [`Server::pairing`] is open, a device pairs by
+/// sending the PIN and its name, `{"pin": "012345", "name": "Phone"}`, to
+/// `/pair` and gets back `{"token": "...", "device": {...}}`. The PIN works
+/// once and only for a short while. The token of a WebSocket is checked again
+/// for every command and record, so revoking a device also closes its
+/// sockets.
+///
+/// With [`Server::tls`], every connection speaks TLS. Paired devices check
+/// the certificate by its fingerprint, and learn the fingerprint of the next
+/// one from `/certificate` while it is being rotated.
+///
+/// [`Manager`]: crate::media::Manager
+pub struct Server {
+    listener: TcpListener,
+    selector: SessionSelector,
+    devices: Option<Devices>,
+    pairing: Option<Pairing>,
+    tls: Option<Tls>,
+    advertisement: Option<Advertisement>,
+}
+
+impl std::fmt::Debug for Server {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Server")
+            .field("addr", &self.local_addr())
+            .field("selector", &self.selector)
+            .field("devices", &self.devices)
+            .field("pairing", &self.pairing)
+            .field("tls", &self.tls)
+            .field("advertisement", &self.advertisement)
+            .finish()
+    }
+}
+
+/// What the connections of the server hand to the thread running it
+#[derive(Debug)]
+pub(crate) enum Incoming {
+    /// A request with its body, to answer on `reply`
+    Request {
+        request: HttpRequest,
+        body: Vec<u8>,
+        reply: Sender<(u16, serde_json::Value)>,
+    },
+    /// Whether a client with `token` is let in, asked before opening a
+    /// WebSocket
+    Authenticate {
+        token: Option<String>,
+        reply: Sender<bool>,
+    },
+    /// A command sent over a WebSocket opened with `token`, to answer on
+    /// `reply`
+    Command {
+        token: Option<String>,
+        name: String,
+        reply: Sender<(u16, serde_json::Value)>,
+    },
+    /// A WebSocket client opened with `token`, to push records to
+    Client {
+        token: Option<String>,
+        outgoing: Sender<String>,
+    },
+}
+
+/// A WebSocket client the server pushes records to
+#[derive(Debug)]
+struct Subscriber {
+    id: u64,
+    /// The token the socket was opened with, checked before every record
+    token: Option<String>,
+    outgoing: Sender<String>,
+    /// Records pushed while the snapshot is still being read, sent after it
+    waiting: Option<Vec<String>>,
+}
+
+/// Work which may wait on a player, run on a thread of its own so the server
+/// goes on with other connections meanwhile
+type Job = Box<dyn FnOnce(&dyn MediaBackend) + Send>;
+
+impl Server {
+    /// Listen on `addr`, like `127.0.0.1:3000`. Port `0` picks a free port,
+    /// see [`Server::local_addr`].
+    pub fn bind(addr: &str) -> Result<Self, MediaError> {
+        let listener = TcpListener::bind(addr).map_err(|error| {
+            MediaError::Backend(format!("Could not listen on {}: {}", addr, error))
+        })?;
+
+        Ok(Self {
+            listener,
+            selector: SessionSelector::Current,
+            devices: None,
+            pairing: None,
+            tls: None,
+            advertisement: None,
+        })
+    }
+
+    /// Control the session chosen by `selector` instead of the current one
+    pub fn session(mut self, selector: SessionSelector) -> Self {
+        self.selector = selector;
+
+        self
+    }
+
+    /// Only let in the paired `devices`
+    pub fn devices(mut self, devices: Devices) -> Self {
+        self.devices = Some(devices);
+
+        self
+    }
+
+    /// Let a device pair with the PIN of `pairing`, for as long as it is open.
+    /// Needs [`Server::devices`] to keep the device in.
+    pub fn pairing(mut self, pairing: Pairing) -> Self {
+        self.pairing = Some(pairing);
+
+        self
+    }
+
+    /// Speak TLS, presenting the current certificate of `certificates`
+    pub fn tls(mut self, certificates: Certificates) -> Self {
+        self.tls = Some(Tls::new(certificates));
+
+        self
+    }
+
+    /// Keep `advertisement` up to date with the certificates while running.
+    /// It says goodbye once the server stops.
+    pub fn advertise(mut self, advertisement: Advertisement) -> Self {
+        self.advertisement = Some(advertisement);
+
+        self
+    }
+
+    /// Address the server listens on
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listener.local_addr().ok()
+    }
+
+    /// Answers requests with the sessions of `backend` and pushes the
+    /// messages of the manager to WebSocket clients until told to stop. Meant
+    /// to be run as a thread of the thread controller.
+    ///
+    /// Whatever asks a player, like commands and the snapshots of new
+    /// WebSockets, runs on a thread of its own, so a slow player doesn't hold
+    /// up other connections. Stopping waits for those threads.
+    pub fn run(
+        mut self,
+        backend: &dyn MediaBackend,
+        rx: crossbeam_channel::Receiver<ThreadMessage>,
+    ) {
+        let (incoming_tx, incoming_rx) = crossbeam_channel::unbounded();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let acceptor = {
+            let listener = self.listener.try_clone();
+            let tls = self.tls.clone();
+            let stopped = stopped.clone();
+            std::thread::spawn(move || match listener {
+                Ok(listener) => accept(listener, tls, incoming_tx, &stopped),
+                Err(error) => eprintln!("[Server] Could not accept connections: {}", error),
+            })
+        };
+
+        let mut clients: Vec<Subscriber> = vec![];
+        let mut next_id = 0;
+        let (snapshot_tx, snapshot_rx) = crossbeam_channel::unbounded::<(u64, EventRecord)>();
+        let advertisement_ticks = match &self.advertisement {
+            Some(_) => crossbeam_channel::tick(ADVERTISEMENT_INTERVAL),
+            None => crossbeam_channel::never(),
+        };
+        // Waits for the jobs still running once stopped
+        std::thread::scope(|scope| {
+            let spawn = |job: Job| {
+                scope.spawn(move || job(backend));
+            };
+
+            loop {
+                crossbeam_channel::select! {
+                    recv(rx) -> msg => match msg {
+                        Ok(ThreadMessage::Media(msg)) => {
+                            if let Some(record) = EventRecord::from_message(&msg, SystemTime::now()) {
+                                self.drop_revoked(&mut clients);
+                                push(&mut clients, serde_json::to_string(&record).unwrap());
+                            }
+                        }
+                        Ok(ThreadMessage::Stop) | Err(_) => break,
+                        Ok(_) => {}
+                    },
+                    recv(incoming_rx) -> incoming => match incoming {
+                        Ok(Incoming::Request { request, body, reply }) => {
+                            if let Some(job) = self.route(&request, &body, reply) {
+                                spawn(job);
+                            }
+                        }
+                        Ok(Incoming::Authenticate { token, reply }) => {
+                            reply.send(self.authenticate(token.as_deref())).ok();
+                        }
+                        Ok(Incoming::Command { token, name, reply }) => {
+                            if self.authenticate(token.as_deref()) {
+                                if let Some(job) = self.command(&name, reply) {
+                                    spawn(job);
+                                }
+                            } else {
+                                reply.send(unauthorized()).ok();
+                                self.drop_revoked(&mut clients);
+                            }
+                        }
+                        Ok(Incoming::Client { token, outgoing }) => {
+                            let (id, selector) = (next_id, self.selector.clone());
+                            next_id += 1;
+                            clients.push(Subscriber {
+                                id,
+                                token,
+                                outgoing,
+                                waiting: Some(vec![]),
+                            });
+                            let snapshot_tx = snapshot_tx.clone();
+                            spawn(Box::new(move |backend| {
+                                snapshot_tx.send((id, snapshot(backend, &selector))).ok();
+                            }));
+                        }
+                        Err(_) => break,
+                    },
+                    recv(snapshot_rx) -> snapshot => {
+                        if let Ok((id, snapshot)) = snapshot {
+                            send_snapshot(&mut clients, id, &snapshot);
+                        }
+                    }
+                    recv(advertisement_ticks) -> _ => self.update_advertisement(),
+                }
+            }
+
+            // Dropping the clients closes their sockets
+            drop(clients);
+            stopped.store(true, Ordering::Relaxed);
+            acceptor.join().ok();
+        });
+    }
+
+    /// Drops the clients whose device was revoked, which closes their sockets
+    fn drop_revoked(&mut self, clients: &mut Vec<Subscriber>) {
+        clients.retain(|client| self.authenticate(client.token.as_deref()));
+    }
+
+    /// Advertises the certificates as they are now, which rotations change
+    fn update_advertisement(&mut self) {
+        if let Some(advertisement) = &mut self.advertisement {
+            let status = self.tls.as_ref().and_then(Tls::status);
+            if let Err(error) = advertisement.update(status.as_ref()) {
+                eprintln!("[Server] {}", error);
+            }
+        }
+    }
+
+    /// Answers `request` on `reply` with a status and body, or returns the
+    /// job which does
+    fn route(
+        &mut self,
+        request: &HttpRequest,
+        body: &[u8],
+        reply: Sender<(u16, serde_json::Value)>,
+    ) -> Option<Job> {
+        let (method, path) = (request.method.as_str(), request.path.as_str());
+        let name = path.trim_start_matches('/');
+        let answer = if name == "pair" {
+            expect_method(method, "POST").and_then(|_| self.pair(body))
+        } else if !self.authenticate(request.token()) {
+            Err(unauthorized())
+        } else if name == "certificate" {
+            expect_method(method, "GET").and_then(|_| {
+                let status = self.tls.as_ref().and_then(Tls::status).ok_or((
+                    404,
+                    json!({
+                        "error": "not_found",
+                        "message": "The server doesn't use TLS",
+                    }),
+                ))?;
+                Ok(serde_json::to_value(status).unwrap())
+            })
+        } else if name == "events" {
+            Err((
+                426,
+                json!({
+                    "error": "upgrade_required",
+                    "message": "Connect to /events with a WebSocket",
+                }),
+            ))
+        } else if !COMMANDS.contains(&name) {
+            Err((
+                404,
+                json!({
+                    "error": "not_found",
+                    "message": format!("There is nothing at {}", path),
+                }),
+            ))
+        } else {
+            let expected = if name == "current" { "GET" } else { "POST" };
+            match expect_method(method, expected) {
+                Ok(()) => return self.command(name, reply),
+                Err(error) => Err(error),
+            }
+        };
+
+        let answer = match answer {
+            Ok(body) => (200, body),
+            Err(error) => error,
+        };
+        reply.send(answer).ok();
+
+        None
+    }
+
+    /// Whether a client with `token` is let in
+    fn authenticate(&mut self, token: Option<&str>) -> bool {
+        match &mut self.devices {
+            Some(devices) => token.is_some_and(|token| devices.authenticate(token).is_some()),
+            None => true,
+        }
+    }
+
+    /// Pairs the device asking to with `body`
+    fn pair(&mut self, body: &[u8]) -> Result<serde_json::Value, (u16, serde_json::Value)> {
+        #[derive(serde::Deserialize)]
+        struct PairRequest {
+            pin: String,
+            name: String,
+        }
+
+        let request: PairRequest = serde_json::from_slice(body).map_err(|error| {
+            (
+                400,
+                json!({
+                    "error": "invalid_request",
+                    "message": format!("Send {{\"pin\": \"...\", \"name\": \"...\"}}: {}", error),
+                }),
+            )
+        })?;
+        let name = check_name(&request.name).map_err(|error| {
+            (
+                400,
+                json!({
+                    "error": "invalid_request",
+                    "message": error.to_string(),
+                }),
+            )
+        })?;
+
+        let closed = (
+            403,
+            json!({
+                "error": "pairing_closed",
+                "message": "Pairing isn't open, restart the server with --pair",
+            }),
+        );
+        let (devices, pairing) = match (&mut self.devices, &mut self.pairing) {
+            (Some(devices), Some(pairing)) => (devices, pairing),
+            _ => return Err(closed),
+        };
+        match pairing.check(&request.pin) {
+            Ok(()) => {}
+            Err(PairingError::WrongPin) => {
+                return Err((
+                    403,
+                    json!({
+                        "error": "wrong_pin",
+                        "message": "The PIN is wrong",
+                    }),
+                ))
+            }
+            Err(PairingError::Closed) => {
+                self.pairing = None;
+                return Err(closed);
+            }
+        }
+
+        // The PIN works only once
+        self.pairing = None;
+        let (device, token) = devices.pair(name).map_err(error_response)?;
+        eprintln!("[Server] Paired {} as {}", device.name, device.id);
+
+        Ok(json!({ "token": token, "device": device }))
+    }
+
+    /// The job which runs the command called `name` on the session and
+    /// answers on `reply`. Unknown commands are answered right away.
+    fn command(&self, name: &str, reply: Sender<(u16, serde_json::Value)>) -> Option<Job> {
+        let run: fn(&dyn MediaSession) -> Result<serde_json::Value, MediaError> = match name {
+            "play" => |session| play(session).map(|_| json!({ "ok": true })),
+            "pause" => |session| pause(session).map(|_| json!({ "ok": true })),
+            "next" => |session| next_track(session).map(|_| json!({ "ok": true })),
+            "previous" => |session| previous_track(session).map(|_| json!({ "ok": true })),
+            "current" => |session| {
+                let state = MediaState::read(session, &SystemClock)?;
+                Ok(serde_json::to_value(state).unwrap())
+            },
+            _ => {
+                let answer = (
+                    404,
+                    json!({
+                        "error": "not_found",
+                        "message": format!("There is no command `{}`", name),
+                    }),
+                );
+                reply.send(answer).ok();
+                return None;
+            }
+        };
+
+        let selector = self.selector.clone();
+        Some(Box::new(move |backend| {
+            let answer = select_session(backend, &selector).and_then(|session| run(&*session));
+            let answer = match answer {
+                Ok(body) => (200, body),
+                Err(error) => error_response(error),
+            };
+            reply.send(answer).ok();
+        }))
+    }
+}
+
+/// Record with the state of the session chosen by `selector` as it is now,
+/// without a state when there is no session
+fn snapshot(backend: &dyn MediaBackend, selector: &SessionSelector) -> EventRecord {
+    let state = select_session(backend, selector)
+        .and_then(|session| MediaState::read(&*session, &SystemClock));
+    let state = match state {
+        Ok(state) => Some(state),
+        Err(MediaError::NoSession) => None,
+        Err(error) => {
+            eprintln!("[Server] Could not read the state: {}", error);
+            None
+        }
+    };
+
+    EventRecord {
+        kind: "snapshot".to_string(),
+        session_id: state.as_ref().map(|state| state.session_id.clone()),
+        timestamp: SystemTime::now(),
+        state,
+        event: None,
+    }
+}
+
+/// Hands every connection to a thread of its own until `stopped`
+fn accept(
+    listener: TcpListener,
+    tls: Option<Tls>,
+    incoming: Sender<Incoming>,
+    stopped: &AtomicBool,
+) {
+    if let Err(error) = listener.set_nonblocking(true) {
+        return eprintln!("[Server] Could not accept connections: {}", error);
+    }
+
+    while !stopped.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let (tls, incoming) = (tls.clone(), incoming.clone());
+                std::thread::spawn(move || connection(stream, tls, incoming));
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            Err(error) => {
+                eprintln!("[Server] Could not accept a connection: {}", error);
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+}
+
+/// Reads the request of a connection and answers it, or keeps serving it as
+/// a WebSocket
+fn connection(stream: TcpStream, tls: Option<Tls>, incoming: Sender<Incoming>) {
+    let setup = stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(REQUEST_TIMEOUT)));
+    if setup.is_err() {
+        return;
+    }
+    let mut stream = match tls {
+        Some(tls) => match tls.wrap(stream) {
+            Ok(stream) => stream,
+            Err(_) => return,
+        },
+        None => Stream::Plain(stream),
+    };
+    let request = match HttpRequest::read(&mut stream) {
+        Ok(request) => request,
+        Err(_) => return,
+    };
+
+    if request.method == "GET" && request.path == "/events" && request.is_websocket() {
+        let token = request.token().map(str::to_string);
+        let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+        let sent = incoming.send(Incoming::Authenticate {
+            token: token.clone(),
+            reply: reply_tx,
+        });
+        match sent.ok().and_then(|_| reply_rx.recv().ok()) {
+            Some(true) => {}
+            Some(false) => {
+                let (status, body) = unauthorized();
+                http::respond(&mut stream, status, &body).ok();
+                return;
+            }
+            None => return,
+        }
+
+        let socket = match socket::accept(stream, &request) {
+            Ok(socket) => socket,
+            Err(error) => return eprintln!("[Server] Could not open a WebSocket: {}", error),
+        };
+        let (outgoing_tx, outgoing_rx) = crossbeam_channel::bounded(CLIENT_BUFFER);
+        let client = Incoming::Client {
+            token: token.clone(),
+            outgoing: outgoing_tx,
+        };
+        if incoming.send(client).is_ok() {
+            Client {
+                socket,
+                token,
+                outgoing: outgoing_rx,
+                incoming,
+            }
+            .run();
+        }
+        return;
+    }
+
+    let body = match request.body(&mut stream) {
+        Ok(body) => body,
+        Err(_) => return,
+    };
+    let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+    let sent = incoming.send(Incoming::Request {
+        request,
+        body,
+        reply: reply_tx,
+    });
+    let (status, body) = match sent.ok().and_then(|_| reply_rx.recv().ok()) {
+        Some(answer) => answer,
+        None => return,
+    };
+
+    if let Err(error) = http::respond(&mut stream, status, &body) {
+        eprintln!("[Server] Could not answer a request: {}", error);
+    }
+}
+
+/// Sends a record to every client, dropping the ones which went away or can't
+/// keep up
+fn push(clients: &mut Vec<Subscriber>, record: String) {
+    clients.retain_mut(|client| {
+        let full = match &mut client.waiting {
+            Some(waiting) => {
+                waiting.push(record.clone());
+                waiting.len() > CLIENT_BUFFER
+            }
+            None => match client.outgoing.try_send(record.clone()) {
+                Ok(()) => false,
+                Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Disconnected(_)) => return false,
+            },
+        };
+        if full {
+            eprintln!("[Server] Dropping a WebSocket client which can't keep up");
+        }
+
+        !full
+    });
+}
+
+/// Sends the client with `id` its snapshot, followed by what was pushed while
+/// it was being read
+fn send_snapshot(clients: &mut Vec<Subscriber>, id: u64, snapshot: &EventRecord) {
+    let index = match clients.iter().position(|client| client.id == id) {
+        Some(index) => index,
+        None => return,
+    };
+    let client = &mut clients[index];
+    let waiting = client.waiting.take().unwrap_or_default();
+    let sent = std::iter::once(serde_json::to_string(snapshot).unwrap())
+        .chain(waiting)
+        .all(|record| client.outgoing.try_send(record).is_ok());
+    if !sent {
+        clients.remove(index);
+    }
+}
+
+/// The answer to clients without the token of a paired device
+fn unauthorized() -> (u16, serde_json::Value) {
+    (
+        401,
+        json!({
+            "error": "unauthorized",
+            "message": "Pair the device and send its token",
+        }),
+    )
+}
+
+fn expect_method(method: &str, expected: &str) -> Result<(), (u16, serde_json::Value)> {
+    if method == expected {
+        return Ok(());
+    }
+
+    Err((
+        405,
+        json!({
+            "error": "method_not_allowed",
+            "message": format!("Use {} here", expected),
+        }),
+    ))
+}
+
+/// The status and body answering a failed command
+fn error_response(error: MediaError) -> (u16, serde_json::Value) {
+    let status = match error {
+        MediaError::NoSession => 404,
+        MediaError::Unsupported(_) | MediaError::PropertyUnavailable(_) => 409,
+        MediaError::InvalidArgument(_) => 400,
+        MediaError::Backend(_) => 500,
+    };
+
+    let mut body = json!({
+        "error": error.kind(),
+        "message": error.to_string(),
+    });
+    if let MediaError::Unsupported(control) = &error {
+        body["control"] = control.to_string().into();
+    }
+
+    (status, body)
+}
@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+use qrcode::{render::unicode::Dense1x2, QrCode};
+
+use crate::media::MediaError;
+
+/// How long a PIN can be used to pair a device
+pub const PIN_LIFETIME: Duration = Duration::from_secs(120);
+
+/// Wrong PINs after which pairing closes, so the PIN can't be guessed
+const MAX_PIN_ATTEMPTS: u32 = 5;
+
+/// Why a device could not pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PairingError {
+    /// Pairing isn't open, or closed after the PIN expired, was used or was
+    /// guessed at too often
+    Closed,
+    /// The PIN is wrong
+    WrongPin,
+}
+
+/// A one-time PIN a device can pair with, for [`PIN_LIFETIME`]
+#[derive(Debug, Clone)]
+pub struct Pairing {
+    pin: String,
+    expires: Instant,
+    attempts: u32,
+}
+
+impl Pairing {
+    /// Opens pairing with a new random 6 digit PIN
+    pub fn open() -> Result<Self, MediaError> {
+        let mut bytes = [0; 4];
+        getrandom::getrandom(&mut bytes)
+            .map_err(|error| MediaError::Backend(format!("Could not generate a PIN: {}", error)))?;
+
+        Ok(Self {
+            pin: format!("{:06}", u32::from_le_bytes(bytes) % 1_000_000),
+            expires: Instant::now() + PIN_LIFETIME,
+            attempts: 0,
+        })
+    }
+
+    /// The PIN to show the user
+    pub fn pin(&self) -> &str {
+        &self.pin
+    }
+
+    /// Checks a PIN a device sent. Any PIN fails once this fails with
+    /// [`PairingError::Closed`].
+    pub(crate) fn check(&mut self, pin: &str) -> Result<(), PairingError> {
+        if self.attempts >= MAX_PIN_ATTEMPTS || Instant::now() >= self.expires {
+            return Err(PairingError::Closed);
+        }
+
+        if pin.trim() == self.pin {
+            Ok(())
+        } else {
+            self.attempts += 1;
+            Err(PairingError::WrongPin)
+        }
+    }
+
+    /// What the pairing QR code holds: a `window://pair` URI with where the
+    /// server is, the PIN and, with TLS, the `fingerprint` of its certificate
+    /// to pin, e.g.
+    /// `window://pair?host=desk.local&port=3000&name=desk&pin=012345&fp=9f86...`
+    pub fn uri(&self, host: &str, port: u16, name: &str, fingerprint: Option<&str>) -> String {
+        let mut uri = format!(
+            "window://pair?host={}&port={}&name={}&pin={}",
+            encode(host),
+            port,
+            encode(name),
+            self.pin
+        );
+        if let Some(fingerprint) = fingerprint {
+            uri.push_str(&format!("&fp={}", encode(fingerprint)));
+        }
+
+        uri
+    }
+}
+
+/// Renders `text` as a QR code for the terminal, two rows per line of text
+pub fn qr_code(text: &str) -> Result<String, MediaError> {
+    let code = QrCode::new(text.as_bytes())
+        .map_err(|error| MediaError::Backend(format!("Could not make a QR code: {}", error)))?;
+
+    // Light on dark terminals, so the code is drawn inverted
+    Ok(code
+        .render::<Dense1x2>()
+        .dark_color(Dense1x2::Light)
+        .light_color(Dense1x2::Dark)
+        .build())
+}
+
+/// Percent-encodes everything but unreserved URI characters
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
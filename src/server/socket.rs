@@ -0,0 +1,151 @@
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
+use serde::Deserialize;
+use serde_json::json;
+use tungstenite::{handshake::derive_accept_key, protocol::Role, Message, WebSocket};
+
+use super::{http::HttpRequest, tls::Stream, Incoming};
+
+/// How long a client waits for a message from its socket before it sends what
+/// is queued for it again
+const READ_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long writing to a client may block before it is given up on
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Command a client sends over the socket
+#[derive(Debug, Deserialize)]
+struct Command {
+    command: String,
+    /// Anything, sent back with the reply
+    #[serde(default)]
+    id: serde_json::Value,
+}
+
+/// Answers the upgrade `request` and turns the connection into a WebSocket
+pub(crate) fn accept(mut stream: Stream, request: &HttpRequest) -> io::Result<WebSocket<Stream>> {
+    let key = request.header("Sec-WebSocket-Key").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the upgrade has no Sec-WebSocket-Key",
+        )
+    })?;
+
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+        Upgrade: websocket\r\n\
+        Connection: Upgrade\r\n\
+        Sec-WebSocket-Accept: {}\r\n\
+        \r\n",
+        derive_accept_key(key.trim().as_bytes())
+    )?;
+    stream.flush()?;
+    stream.tcp().set_read_timeout(Some(READ_INTERVAL))?;
+    stream.tcp().set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    Ok(WebSocket::from_partially_read(
+        stream,
+        request.rest.clone(),
+        Role::Server,
+        None,
+    ))
+}
+
+/// A connected WebSocket client
+#[derive(Debug)]
+pub(crate) struct Client {
+    pub(crate) socket: WebSocket<Stream>,
+    /// Token the socket was opened with, sent along with every command
+    pub(crate) token: Option<String>,
+    /// Records the server pushes to the client, as JSON
+    pub(crate) outgoing: Receiver<String>,
+    /// Where the commands of the client go
+    pub(crate) incoming: Sender<Incoming>,
+}
+
+impl Client {
+    /// Sends the client what the server pushes and runs its commands, until
+    /// either the client or the server goes away
+    pub(crate) fn run(mut self) {
+        loop {
+            loop {
+                match self.outgoing.try_recv() {
+                    Ok(text) => {
+                        if self.socket.write(Message::Text(text)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        // The server stopped or dropped the client
+                        self.socket.close(None).ok();
+                        self.socket.flush().ok();
+                        return;
+                    }
+                }
+            }
+            if self.socket.flush().is_err() {
+                return;
+            }
+
+            match self.socket.read() {
+                Ok(Message::Text(text)) => {
+                    let reply = match self.command(&text) {
+                        Some(reply) => reply,
+                        None => return,
+                    };
+                    if self.socket.send(Message::Text(reply.to_string())).is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(error))
+                    if matches!(
+                        error.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) => {}
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Runs a command on the server, `None` once the server stopped
+    fn command(&self, text: &str) -> Option<serde_json::Value> {
+        let command: Command = match serde_json::from_str(text) {
+            Ok(command) => command,
+            Err(error) => {
+                return Some(json!({
+                    "type": "reply",
+                    "id": null,
+                    "status": 400,
+                    "body": {
+                        "error": "invalid_command",
+                        "message": format!("Commands look like {{\"command\": \"pause\"}}: {}", error),
+                    },
+                }))
+            }
+        };
+
+        let (reply_tx, reply_rx) = crossbeam_channel::bounded(1);
+        self.incoming
+            .send(Incoming::Command {
+                token: self.token.clone(),
+                name: command.command,
+                reply: reply_tx,
+            })
+            .ok()?;
+        let (status, body) = reply_rx.recv().ok()?;
+
+        Some(json!({
+            "type": "reply",
+            "id": command.id,
+            "status": status,
+            "body": body,
+        }))
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, SystemTime},
+};
+
+use rustls::{
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig, ServerConnection, StreamOwned,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{
+    devices::{version, write_private, Version},
+    discovery::host_name,
+};
+use crate::media::{time, MediaError};
+
+/// Where the certificates of the server stand, as `window cert show` and
+/// `GET /certificate` show it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CertificateStatus {
+    /// Fingerprint of the certificate the server presents, see
+    /// [`fingerprint`]
+    pub fingerprint: String,
+    /// When the certificate was generated
+    #[serde(with = "time::iso8601")]
+    pub created_at: SystemTime,
+    /// Certificate the server switches to, while rotating
+    pub next: Option<NextCertificate>,
+}
+
+/// Certificate a rotation switches to once its grace period is over
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NextCertificate {
+    /// Fingerprint of the certificate, see [`fingerprint`]
+    pub fingerprint: String,
+    /// When the server starts presenting it
+    #[serde(with = "time::iso8601")]
+    pub active_from: SystemTime,
+}
+
+/// A certificate with its key, as PEM
+#[derive(Clone, Serialize, Deserialize)]
+struct Identity {
+    certificate: String,
+    key: String,
+    #[serde(with = "time::iso8601")]
+    created_at: SystemTime,
+}
+
+impl Identity {
+    /// Generates a self-signed certificate for this machine
+    fn generate() -> Result<Self, MediaError> {
+        let names = vec![format!("{}.local", host_name()), "localhost".to_string()];
+        let generated = rcgen::generate_simple_self_signed(names).map_err(|error| {
+            MediaError::Backend(format!("Could not generate a certificate: {}", error))
+        })?;
+
+        Ok(Self {
+            certificate: generated.cert.pem(),
+            key: generated.key_pair.serialize_pem(),
+            created_at: SystemTime::now(),
+        })
+    }
+
+    fn der(&self) -> Result<CertificateDer<'static>, MediaError> {
+        CertificateDer::from_pem_slice(self.certificate.as_bytes()).map_err(invalid)
+    }
+
+    fn certified_key(&self) -> Result<Arc<CertifiedKey>, MediaError> {
+        let key = PrivateKeyDer::from_pem_slice(self.key.as_bytes()).map_err(invalid)?;
+        let key = ring::sign::any_supported_type(&key).map_err(invalid)?;
+
+        Ok(Arc::new(CertifiedKey::new(vec![self.der()?], key)))
+    }
+}
+
+/// The certificates as kept in the file
+#[derive(Clone, Serialize, Deserialize)]
+struct Stored {
+    current: Identity,
+    next: Option<Next>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Next {
+    #[serde(flatten)]
+    identity: Identity,
+    #[serde(with = "time::iso8601")]
+    active_from: SystemTime,
+}
+
+/// The self-signed certificates of the server, kept with their keys in a JSON
+/// file. There is no CA to vouch for them, so devices pin the fingerprint they
+/// got when pairing.
+///
+/// Rotating generates the next certificate but keeps presenting the current
+/// one for a grace period, in which paired devices learn the next fingerprint
+/// from `GET /certificate` or the discovery record. Changes made to the file
+/// by another process, like `window cert rotate`, are picked up by the server
+/// on the next connection.
+pub struct Certificates {
+    path: PathBuf,
+    stored: Stored,
+    status: CertificateStatus,
+    /// The current certificate, as rustls presents it
+    key: Arc<CertifiedKey>,
+    /// Which version of the file was read, see [`version`]
+    version: Option<Version>,
+}
+
+impl std::fmt::Debug for Certificates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Certificates")
+            .field("path", &self.path)
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+impl Certificates {
+    /// Reads the certificates kept in `path`, generating one when the file
+    /// doesn't exist yet
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, MediaError> {
+        let path = path.into();
+        let version = version(&path);
+        let stored = match read(&path)? {
+            Some(stored) => stored,
+            None => Stored {
+                current: Identity::generate()?,
+                next: None,
+            },
+        };
+
+        let mut certificates = Self::new(path, stored)?;
+        certificates.version = version;
+        if certificates.version.is_none() || certificates.promote_if_due()? {
+            certificates.save()?;
+        }
+
+        Ok(certificates)
+    }
+
+    /// Where the certificates stand
+    pub fn status(&self) -> &CertificateStatus {
+        &self.status
+    }
+
+    /// Generates the next certificate, presented once `grace` is over. A next
+    /// certificate which wasn't presented yet is replaced.
+    pub fn rotate(&mut self, grace: Duration) -> Result<&CertificateStatus, MediaError> {
+        if let Some(stored) = read(&self.path)? {
+            self.set(stored)?;
+        }
+
+        let mut stored = self.stored.clone();
+        stored.next = Some(Next {
+            identity: Identity::generate()?,
+            active_from: SystemTime::now() + grace,
+        });
+        self.set(stored)?;
+        self.promote_if_due()?;
+        self.save()?;
+
+        Ok(&self.status)
+    }
+
+    /// Picks up changes to the file and switches to the next certificate
+    /// once it is due
+    fn refresh(&mut self) -> Result<(), MediaError> {
+        if version(&self.path) != self.version {
+            self.version = version(&self.path);
+            if let Some(stored) = read(&self.path)? {
+                self.set(stored)?;
+            }
+        }
+        if self.promote_if_due()? {
+            self.save()?;
+        }
+
+        Ok(())
+    }
+
+    fn new(path: PathBuf, stored: Stored) -> Result<Self, MediaError> {
+        Ok(Self {
+            path,
+            status: status(&stored)?,
+            key: stored.current.certified_key()?,
+            stored,
+            version: None,
+        })
+    }
+
+    /// Uses `stored`, once it proved valid
+    fn set(&mut self, stored: Stored) -> Result<(), MediaError> {
+        self.status = status(&stored)?;
+        self.key = stored.current.certified_key()?;
+        self.stored = stored;
+
+        Ok(())
+    }
+
+    /// Makes the next certificate the current one if its time came. Returns
+    /// whether it did.
+    fn promote_if_due(&mut self) -> Result<bool, MediaError> {
+        let mut stored = match &self.stored.next {
+            Some(next) if next.active_from <= SystemTime::now() => self.stored.clone(),
+            _ => return Ok(false),
+        };
+        stored.current = stored.next.take().unwrap().identity;
+        self.set(stored)?;
+
+        Ok(true)
+    }
+
+    /// Writes the certificates, readable only by the user as they hold the
+    /// keys
+    fn save(&mut self) -> Result<(), MediaError> {
+        write_private(
+            &self.path,
+            &serde_json::to_vec_pretty(&self.stored).unwrap(),
+        )
+        .map_err(|error| {
+            MediaError::Backend(format!("Could not save the certificates: {}", error))
+        })?;
+
+        self.version = version(&self.path);
+        Ok(())
+    }
+}
+
+/// SHA-256 of a DER encoded certificate as lowercase hex, which clients pin
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn status(stored: &Stored) -> Result<CertificateStatus, MediaError> {
+    let next = match &stored.next {
+        Some(next) => Some(NextCertificate {
+            fingerprint: fingerprint(&next.identity.der()?),
+            active_from: next.active_from,
+        }),
+        None => None,
+    };
+
+    Ok(CertificateStatus {
+        fingerprint: fingerprint(&stored.current.der()?),
+        created_at: stored.current.created_at,
+        next,
+    })
+}
+
+/// The certificates kept in `path`, `None` when the file doesn't exist
+fn read(path: &Path) -> Result<Option<Stored>, MediaError> {
+    match std::fs::read(path) {
+        Ok(json) => serde_json::from_slice(&json).map(Some).map_err(invalid),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(MediaError::Backend(format!(
+            "Could not open the certificates: {}",
+            error
+        ))),
+    }
+}
+
+fn invalid(error: impl std::fmt::Display) -> MediaError {
+    MediaError::Backend(format!("The certificates are not valid: {}", error))
+}
+
+/// Hands rustls the current certificate, following rotations
+#[derive(Debug)]
+struct Resolver(Mutex<Certificates>);
+
+impl Resolver {
+    /// The certificates, with the changes made to them since
+    fn refreshed(&self) -> Option<MutexGuard<'_, Certificates>> {
+        let mut certificates = self.0.lock().ok()?;
+        if let Err(error) = certificates.refresh() {
+            eprintln!("[Server] Could not read the certificates: {}", error);
+        }
+
+        Some(certificates)
+    }
+}
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.refreshed()?.key.clone())
+    }
+}
+
+/// What the server speaks TLS with
+#[derive(Debug, Clone)]
+pub(crate) struct Tls {
+    config: Arc<ServerConfig>,
+    resolver: Arc<Resolver>,
+}
+
+impl Tls {
+    pub(crate) fn new(certificates: Certificates) -> Self {
+        let resolver = Arc::new(Resolver(Mutex::new(certificates)));
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("the ring provider supports the default TLS versions")
+            .with_no_client_auth()
+            .with_cert_resolver(resolver.clone());
+
+        Self {
+            config: Arc::new(config),
+            resolver,
+        }
+    }
+
+    /// Where the certificates stand now
+    pub(crate) fn status(&self) -> Option<CertificateStatus> {
+        Some(self.resolver.refreshed()?.status.clone())
+    }
+
+    /// Speaks TLS on `stream`. The handshake happens on the first read.
+    pub(crate) fn wrap(&self, stream: TcpStream) -> io::Result<Stream> {
+        let connection = ServerConnection::new(self.config.clone()).map_err(io::Error::other)?;
+
+        Ok(Stream::Tls(Box::new(StreamOwned::new(connection, stream))))
+    }
+}
+
+/// A connection to a client, over TLS or not
+#[derive(Debug)]
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// The connection under the TLS
+    pub(crate) fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    /// Tells a TLS client that nothing follows, so it can tell the end of the
+    /// answer from a cut connection
+    pub(crate) fn close(&mut self) -> io::Result<()> {
+        if let Stream::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            stream.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buf),
+            Stream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buf),
+            Stream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+        }
+    }
+}
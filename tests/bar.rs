@@ -0,0 +1,124 @@
+use std::time::{Duration, SystemTime};
+
+use window::media::{
+    BarStyle, FakeBackend, ManualClock, MediaBackend, MediaState, PlaybackStatus, StatusBar,
+};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+/// State of the first track, playing 83s in
+fn state() -> MediaState {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    session.pause().unwrap();
+    session.set_position(Duration::from_secs(83)).unwrap();
+
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_653_746_587));
+    let mut state = MediaState::read(&*session, &clock).unwrap();
+    state.playback.playback_status = PlaybackStatus::Playing;
+    state
+}
+
+#[test]
+fn writes_waybar_json() {
+    let mut state = state();
+    state.media.artist = "Tom & Jerry".to_string();
+    let now = state.timeline.last_updated_time + Duration::from_secs(17);
+
+    let mut bar = StatusBar::new(BarStyle::Waybar);
+    let json: serde_json::Value = serde_json::from_str(&bar.render(Some(&state), now)).unwrap();
+    assert_eq!(json["text"], "Tom &amp; Jerry - First Song");
+    assert_eq!(
+        json["tooltip"],
+        "First Song\nby Tom &amp; Jerry\non Some Album\n1:40 / 3:20"
+    );
+    assert_eq!(json["class"], "playing");
+    assert_eq!(json["percentage"], 50);
+
+    let json: serde_json::Value = serde_json::from_str(&bar.render(None, now)).unwrap();
+    assert_eq!(json["text"], "");
+    assert_eq!(json["class"], "none");
+}
+
+#[test]
+fn writes_i3blocks_json() {
+    let mut state = state();
+    let now = state.timeline.last_updated_time;
+
+    let mut bar = StatusBar::new(BarStyle::I3blocks)
+        .format("{{title}} {{position}}".parse().unwrap())
+        .width(0);
+    // One line per update, as persistent blocks replace the text on every line
+    let line = bar.render(Some(&state), now);
+    assert!(!line.contains('\n'));
+    let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(json["full_text"], "First Song 1:23");
+    assert_eq!(json["short_text"], "First Song 1:23");
+    assert_eq!(json["color"], "#8ec07c");
+
+    state.playback.playback_status = PlaybackStatus::Stopped;
+    let json: serde_json::Value = serde_json::from_str(&bar.render(Some(&state), now)).unwrap();
+    assert_eq!(json["full_text"], "First Song 1:23");
+    assert!(json.get("color").is_none());
+}
+
+#[test]
+fn writes_polybar_tags() {
+    let mut state = state();
+    state.media.title = "100% Pure".to_string();
+    let now = state.timeline.last_updated_time;
+
+    let mut bar = StatusBar::new(BarStyle::Polybar).format("{{title}}".parse().unwrap());
+    assert_eq!(
+        bar.render(Some(&state), now),
+        "%{A1:window toggle:}100%% Pure%{A}"
+    );
+
+    state.playback.playback_status = PlaybackStatus::Paused;
+    assert_eq!(
+        bar.render(Some(&state), now),
+        "%{A1:window toggle:}%{F#928374}100%% Pure%{F-}%{A}"
+    );
+    assert_eq!(bar.render(None, now), "");
+
+    let mut bar = StatusBar::new(BarStyle::Polybar)
+        .format("{{title}}".parse().unwrap())
+        .click("'window' '--session' 'a:b' 'toggle'");
+    assert_eq!(
+        bar.render(Some(&state), now),
+        "%{A1:'window' '--session' 'a\\:b' 'toggle':}%{F#928374}100%% Pure%{F-}%{A}"
+    );
+}
+
+#[test]
+fn scrolls_long_text_until_the_track_changes() {
+    let mut state = state();
+    let now = state.timeline.last_updated_time;
+
+    let mut bar = StatusBar::new(BarStyle::I3blocks)
+        .format("{{title}}".parse().unwrap())
+        .width(5);
+    let text = |bar: &mut StatusBar, state: &MediaState| {
+        let json: serde_json::Value = serde_json::from_str(&bar.render(Some(state), now)).unwrap();
+        json["full_text"].as_str().unwrap().to_string()
+    };
+
+    assert_eq!(text(&mut bar, &state), "First");
+    bar.tick();
+    assert_eq!(text(&mut bar, &state), "irst ");
+    for _ in 0..8 {
+        bar.tick();
+    }
+    assert_eq!(text(&mut bar, &state), "g   F");
+
+    state.media.title = "Second Song".to_string();
+    assert_eq!(text(&mut bar, &state), "Secon");
+
+    // Short text never scrolls
+    state.media.title = "Hi".to_string();
+    bar.tick();
+    assert_eq!(text(&mut bar, &state), "Hi");
+}
@@ -0,0 +1,98 @@
+use std::time::{Duration, SystemTime};
+
+use window::{
+    controller::Thread,
+    media::FakeBackend,
+    server::{
+        discover, host_name, Advertisement, CertificateStatus, Certificates, Instance,
+        NextCertificate, Server, PROTOCOL_VERSION,
+    },
+};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+#[test]
+fn advertises_servers_until_dropped() {
+    let name = format!("window-test-{}", std::process::id());
+    let certificates = CertificateStatus {
+        fingerprint: "ab".repeat(32),
+        created_at: SystemTime::now(),
+        next: Some(NextCertificate {
+            fingerprint: "cd".repeat(32),
+            active_from: SystemTime::now(),
+        }),
+    };
+    let advertisement = Advertisement::start(&name, 41234, Some(&certificates)).unwrap();
+
+    let instances = discover(Duration::from_secs(3)).unwrap();
+    let instance = instances
+        .iter()
+        .find(|instance| instance.name == name)
+        .expect("the server is advertised");
+    assert_eq!(instance.port, 41234);
+    assert_eq!(instance.host, host_name());
+    assert_eq!(instance.version, Some(PROTOCOL_VERSION));
+    assert_eq!(instance.capabilities, ["rest", "events", "tls"]);
+    assert_eq!(instance.fingerprint, Some("ab".repeat(32)));
+    assert_eq!(instance.next_fingerprint, Some("cd".repeat(32)));
+    assert!(!instance.addresses.is_empty());
+
+    drop(advertisement);
+    let instances = discover(Duration::from_secs(2)).unwrap();
+    assert!(instances.iter().all(|instance| instance.name != name));
+}
+
+#[test]
+fn advertises_rotated_certificates() {
+    let name = format!("window-test-rotation-{}", std::process::id());
+    let dir = std::env::temp_dir().join(format!("window-discovery-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    let path = dir.join("certificates.json");
+    let certificates = Certificates::open(&path).unwrap();
+    let current = certificates.status().clone();
+    let advertisement = Advertisement::start(&name, 41235, Some(&current)).unwrap();
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .tls(certificates)
+        .advertise(advertisement);
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+
+    // Rotated by another process, like `window cert rotate`
+    let next = Certificates::open(&path)
+        .unwrap()
+        .rotate(Duration::from_secs(3600))
+        .unwrap()
+        .next
+        .clone()
+        .unwrap();
+    let instance = discover_until(&name, |instance| instance.next_fingerprint.is_some());
+    assert_eq!(instance.fingerprint, Some(current.fingerprint.clone()));
+    assert_eq!(instance.next_fingerprint, Some(next.fingerprint));
+
+    let rotated = Certificates::open(&path)
+        .unwrap()
+        .rotate(Duration::ZERO)
+        .unwrap()
+        .clone();
+    let instance = discover_until(&name, |instance| instance.next_fingerprint.is_none());
+    assert_eq!(instance.fingerprint, Some(rotated.fingerprint));
+
+    thread.stop();
+}
+
+/// Browses until the server advertised as `name` matches `done`
+fn discover_until(name: &str, done: impl Fn(&Instance) -> bool) -> Instance {
+    for _ in 0..5 {
+        let instances = discover(Duration::from_secs(2)).unwrap();
+        if let Some(instance) = instances.into_iter().find(|instance| instance.name == name) {
+            if done(&instance) {
+                return instance;
+            }
+        }
+    }
+    panic!("{} is not advertised as expected", name);
+}
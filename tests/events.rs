@@ -0,0 +1,183 @@
+use std::time::{Duration, SystemTime};
+
+use window::media::{
+    diff_states, Clock, EventKind, EventRecord, FakeBackend, ManagerMessage, ManualClock,
+    MediaBackend, MediaEvent, MediaState, PlaybackStatus, RepeatMode,
+};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+/// State of the first track, playing 10s in
+fn playing_state() -> MediaState {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_653_746_587));
+
+    let mut state = MediaState::read(&*session, &clock).unwrap();
+    state.timeline.pos = Duration::from_secs(10);
+    state.timeline.last_updated_time = clock.now();
+    state.playback.playback_status = PlaybackStatus::Playing;
+    state
+}
+
+/// The same state, read `elapsed` later without anything happening
+fn later(state: &MediaState, elapsed: Duration) -> MediaState {
+    let mut later = state.clone();
+    later.timeline.pos += elapsed;
+    later.timeline.last_updated_time += elapsed;
+    later
+}
+
+#[test]
+fn first_state_reports_track_and_status() {
+    let state = playing_state();
+
+    let events = diff_states(None, &state);
+    assert!(matches!(
+        &events[..],
+        [
+            MediaEvent::TrackChanged { from: None, to, .. },
+            MediaEvent::StatusChanged { from: None, to: PlaybackStatus::Playing, .. },
+        ] if to.title == "First Song"
+    ));
+}
+
+#[test]
+fn elapsed_time_is_not_a_change() {
+    let state = playing_state();
+
+    assert!(diff_states(Some(&state), &state).is_empty());
+    assert!(diff_states(Some(&state), &later(&state, Duration::from_secs(30))).is_empty());
+
+    // Coarse position reports are tolerated too
+    let mut coarse = later(&state, Duration::from_secs(30));
+    coarse.timeline.pos -= Duration::from_secs(1);
+    assert!(diff_states(Some(&state), &coarse).is_empty());
+}
+
+#[test]
+fn detects_seeks() {
+    let state = playing_state();
+
+    let mut seeked = later(&state, Duration::from_secs(5));
+    seeked.timeline.pos = Duration::from_secs(120);
+    assert_eq!(
+        diff_states(Some(&state), &seeked),
+        vec![MediaEvent::Seeked {
+            session_id: "demo-player".to_string(),
+            from: Duration::from_secs(15),
+            to: Duration::from_secs(120),
+        }]
+    );
+    let msg = ManagerMessage::Event(Box::new(diff_states(Some(&state), &seeked).remove(0)));
+    assert_eq!(msg.kind(), Some(EventKind::Seek));
+
+    // While paused the position is expected to stay put
+    let mut paused = state.clone();
+    paused.playback.playback_status = PlaybackStatus::Paused;
+    let mut rewound = later(&paused, Duration::from_secs(5));
+    rewound.timeline.pos = Duration::ZERO;
+    assert!(matches!(
+        &diff_states(Some(&paused), &rewound)[..],
+        [MediaEvent::Seeked { from, to, .. }] if *from == Duration::from_secs(10) && to.is_zero()
+    ));
+}
+
+#[test]
+fn separates_track_metadata_and_playback_changes() {
+    let state = playing_state();
+
+    let mut next = later(&state, Duration::from_secs(1));
+    next.media.title = "Second Song".to_string();
+    next.timeline.pos = Duration::ZERO;
+    let events = diff_states(Some(&state), &next);
+    assert!(matches!(
+        &events[..],
+        [MediaEvent::TrackChanged { from: Some(from), to, .. }]
+            if from.title == "First Song" && to.title == "Second Song"
+    ));
+
+    let mut filled_in = state.clone();
+    filled_in.media.track_number = 7;
+    filled_in.art_hash = None;
+    assert!(matches!(
+        &diff_states(Some(&state), &filled_in)[..],
+        [MediaEvent::MetadataChanged { media, art_hash: None, .. }] if media.track_number == 7
+    ));
+
+    let mut repeating = state.clone();
+    repeating.playback.auto_repeat_mode = RepeatMode::List;
+    repeating.playback.playback_status = PlaybackStatus::Paused;
+    let events = diff_states(Some(&state), &repeating);
+    assert_eq!(events.len(), 2);
+    assert!(matches!(
+        &events[0],
+        MediaEvent::StatusChanged {
+            from: Some(PlaybackStatus::Playing),
+            to: PlaybackStatus::Paused,
+            ..
+        }
+    ));
+    assert!(matches!(
+        &events[1],
+        MediaEvent::PlaybackChanged { playback, .. }
+            if playback.auto_repeat_mode == RepeatMode::List
+    ));
+}
+
+#[test]
+fn events_are_tagged_in_json() {
+    let state = playing_state();
+    let mut seeked = state.clone();
+    seeked.timeline.pos = Duration::from_secs(90);
+
+    let event = diff_states(Some(&state), &seeked).remove(0);
+    assert_eq!(event.name(), "seeked");
+    assert_eq!(
+        serde_json::to_value(&event).unwrap(),
+        serde_json::json!({
+            "event": "seeked",
+            "session_id": "demo-player",
+            "from_ms": 10_000,
+            "to_ms": 90_000,
+        })
+    );
+}
+
+#[test]
+fn records_wrap_manager_messages() {
+    let state = playing_state();
+    let timestamp = state.timeline.last_updated_time;
+
+    let msg = ManagerMessage::StateChanged(Box::new(state.clone()));
+    assert_eq!(msg.kind(), Some(EventKind::State));
+    let record = EventRecord::from_message(&msg, timestamp).unwrap();
+    let json = serde_json::to_value(&record).unwrap();
+    assert_eq!(json["type"], "state");
+    assert_eq!(json["session_id"], "demo-player");
+    assert_eq!(json["timestamp"], "2022-05-28T14:03:07.000Z");
+    assert_eq!(json["state"]["media"]["title"], "First Song");
+    assert!(json.get("event").is_none());
+
+    let event = diff_states(None, &state).remove(0);
+    let msg = ManagerMessage::Event(Box::new(event));
+    assert_eq!(msg.kind(), Some(EventKind::Track));
+    let record = EventRecord::from_message(&msg, timestamp).unwrap();
+    assert_eq!(record.kind, "track_changed");
+    let parsed: EventRecord =
+        serde_json::from_str(&serde_json::to_string(&record).unwrap()).unwrap();
+    assert_eq!(parsed.event, record.event);
+
+    let msg = ManagerMessage::SessionChanged(None);
+    let json = serde_json::to_value(EventRecord::from_message(&msg, timestamp)).unwrap();
+    assert_eq!(json["type"], "session_changed");
+    assert!(json["session_id"].is_null());
+
+    assert!(EventRecord::from_message(&ManagerMessage::SessionsChanged, timestamp).is_none());
+    assert_eq!("seek".parse(), Ok(EventKind::Seek));
+    assert_eq!("timeline".parse(), Ok(EventKind::Seek));
+    assert!("everything".parse::<EventKind>().is_err());
+}
@@ -0,0 +1,825 @@
+use std::{
+    io::{BufRead, BufReader},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures::executor::block_on;
+use window::{
+    controller::ThreadMessage,
+    media::{
+        album_art, channel_up, currently_playing_raw, currently_playing_raw_with_art, fast_forward,
+        fast_forward_async, list_sessions, parse_duration, play_async, restart_track, seek,
+        seek_async, select_session, set_playback_rate, set_repeat, set_shuffle, stop,
+        toggle_play_pause, toggle_shuffle, wait_for_session, ArtCache, Control, FakeAction,
+        FakeBackend, FakeScript, FakeTrack, Manager, ManagerMessage, MediaBackend, MediaError,
+        MediaEvent, PlaybackStatus, RepeatMode, SeekTarget, SessionSelector,
+    },
+};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+/// Player which opens 300ms into the script and closes again at 600ms
+const LATE_SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/late_session.json"
+);
+
+fn recv(rx: &crossbeam_channel::Receiver<ThreadMessage>) -> ManagerMessage {
+    match rx.recv_timeout(Duration::from_secs(5)) {
+        Ok(ThreadMessage::Media(msg)) => msg,
+        other => panic!("expected a media message, got {:?}", other),
+    }
+}
+
+#[test]
+fn commands_change_state_and_emit_messages() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let listener = session.listen(tx).unwrap();
+
+    assert_eq!(session.source_app_id(), "demo-player");
+    assert_eq!(session.media_properties().unwrap().title, "First Song");
+
+    session.pause().unwrap();
+    assert!(matches!(recv(&rx), ManagerMessage::PlaybackInfoChanged(_)));
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Paused
+    );
+
+    session.next_track().unwrap();
+    assert!(matches!(recv(&rx), ManagerMessage::MediaChanged(_)));
+    assert!(matches!(recv(&rx), ManagerMessage::TimelineChanged(_)));
+    assert_eq!(session.media_properties().unwrap().title, "Second Song");
+    assert!(
+        !session
+            .playback_info()
+            .unwrap()
+            .active_controls
+            .is_next_enabled
+    );
+
+    // Skipping past the end of the playlist does nothing
+    session.next_track().unwrap();
+    assert_eq!(session.media_properties().unwrap().title, "Second Song");
+
+    backend.apply(&FakeAction::Seek {
+        position_ms: 1_000_000,
+    });
+    assert!(matches!(recv(&rx), ManagerMessage::TimelineChanged(_)));
+    assert_eq!(
+        session.timeline_properties().unwrap().pos,
+        Duration::from_secs(240)
+    );
+
+    drop(listener);
+    session.play().unwrap();
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn script_replays_events() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let _listener = session.listen(tx).unwrap();
+
+    backend.start().join().unwrap();
+
+    assert!(matches!(recv(&rx), ManagerMessage::TimelineChanged(_)));
+    assert!(matches!(recv(&rx), ManagerMessage::MediaChanged(_)));
+    assert!(matches!(recv(&rx), ManagerMessage::TimelineChanged(_)));
+    assert!(matches!(recv(&rx), ManagerMessage::PlaybackInfoChanged(_)));
+    assert_eq!(session.media_properties().unwrap().title, "Second Song");
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Paused
+    );
+}
+
+#[test]
+fn empty_script_has_a_session() {
+    let backend = FakeBackend::new(FakeScript::default());
+    let session = backend.current_session().unwrap();
+
+    assert_eq!(session.source_app_id(), "fake");
+
+    let state: serde_json::Value =
+        serde_json::from_str(&currently_playing_raw(&*session).unwrap()).unwrap();
+    assert_eq!(state["session_id"], "fake");
+    assert_eq!(state["media"]["title"], "");
+    assert_eq!(state["timeline"]["position_ms"], 0);
+    assert_eq!(state["playback"]["playback_status"], "PAUSED");
+    assert_eq!(state["duration_ms"], serde_json::Value::Null);
+    assert_eq!(state["live"], true);
+    assert_eq!(state["finished_percentage"], serde_json::Value::Null);
+    assert_eq!(state["art_hash"], serde_json::Value::Null);
+}
+
+#[test]
+fn selects_fake_session() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+
+    let sessions = list_sessions(&backend).unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].source_app_id, "demo-player");
+    assert_eq!(sessions[0].title, "First Song");
+
+    let by_id = SessionSelector::AppId("demo-player".to_string());
+    assert!(select_session(&backend, &by_id).is_ok());
+    assert!(select_session(&backend, &SessionSelector::Index(0)).is_ok());
+    assert!(matches!(
+        select_session(&backend, &SessionSelector::Index(1)),
+        Err(MediaError::NoSession)
+    ));
+    assert!(matches!(
+        select_session(&backend, &SessionSelector::AppId("nope".to_string())),
+        Err(MediaError::NoSession)
+    ));
+}
+
+#[test]
+fn seeks_within_track() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let target = |s: &str| s.parse::<SeekTarget>().unwrap();
+
+    // Relative seeks start from the estimated position, which only holds
+    // still while paused
+    session.pause().unwrap();
+
+    assert_eq!(seek(&*session, target("1:00")), Ok(Duration::from_secs(60)));
+    assert_eq!(seek(&*session, target("+30s")), Ok(Duration::from_secs(90)));
+    assert_eq!(seek(&*session, target("-2m")), Ok(Duration::ZERO));
+    assert_eq!(seek(&*session, target("+1h")), Ok(Duration::from_secs(200)));
+    assert_eq!(
+        session.timeline_properties().unwrap().pos,
+        Duration::from_secs(200)
+    );
+
+    assert_eq!(restart_track(&*session), Ok(Duration::ZERO));
+    assert_eq!(session.timeline_properties().unwrap().pos, Duration::ZERO);
+}
+
+#[test]
+fn rejects_times_out_of_range() {
+    assert!("1e30".parse::<SeekTarget>().is_err());
+    assert!("+1e30s".parse::<SeekTarget>().is_err());
+    assert!(parse_duration("99999999999999999999999s").is_err());
+    assert!(parse_duration("inf").is_err());
+
+    for time in ["1:-30", "1:90", "1:60", "1:+5", "0:1e1"] {
+        assert!(parse_duration(time).is_err(), "{}", time);
+        assert!(time.parse::<SeekTarget>().is_err(), "{}", time);
+    }
+    assert!(parse_duration("-0:10").is_err());
+    assert!("--0:10".parse::<SeekTarget>().is_err());
+    assert_eq!(
+        "-0:10".parse(),
+        Ok(SeekTarget::Backward(Duration::from_secs(10)))
+    );
+    assert_eq!(
+        parse_duration("90:59.5"),
+        Ok(Duration::from_millis(5_459_500))
+    );
+    assert_eq!(
+        "1:-30".parse::<SeekTarget>(),
+        Err(MediaError::InvalidArgument(
+            "`1:-30` isn't a position, positions look like `1:23`, `90s` or `1m30s`".to_string()
+        ))
+    );
+    assert_eq!(
+        parse_duration(" 1:90 "),
+        Err(MediaError::InvalidArgument(
+            "`1:90` isn't a duration, durations look like `30s`, `1m30s` or `1:30`".to_string()
+        ))
+    );
+}
+
+#[test]
+fn seeking_needs_a_seekable_range() {
+    let backend = FakeBackend::new(FakeScript::default());
+    let session = backend.current_session().unwrap();
+
+    assert_eq!(
+        seek(&*session, SeekTarget::Forward(Duration::from_secs(30))),
+        Err(MediaError::PropertyUnavailable("seekable range"))
+    );
+}
+
+#[test]
+fn changes_shuffle_repeat_and_rate() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+
+    assert_eq!(toggle_shuffle(&*session), Ok(true));
+    assert!(session.playback_info().unwrap().shuffle_active);
+    set_shuffle(&*session, false).unwrap();
+    assert!(!session.playback_info().unwrap().shuffle_active);
+
+    set_repeat(&*session, "list".parse().unwrap()).unwrap();
+    assert_eq!(
+        session.playback_info().unwrap().auto_repeat_mode,
+        RepeatMode::List
+    );
+
+    set_playback_rate(&*session, 1.5).unwrap();
+    assert_eq!(session.playback_info().unwrap().playback_rate, 1.5);
+    assert!(matches!(
+        set_playback_rate(&*session, 0.0),
+        Err(MediaError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        set_playback_rate(&*session, f64::NAN),
+        Err(MediaError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        set_playback_rate(&*session, 1e300),
+        Err(MediaError::InvalidArgument(_))
+    ));
+}
+
+#[test]
+fn cli_reads_fake_session() {
+    let output = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args(["--fake-session", SCRIPT, "--session", "0", "current-json"])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["media"]["title"], "First Song");
+    assert_eq!(info["playback"]["playback_status"], "PLAYING");
+}
+
+#[test]
+fn cli_formats_current_state() {
+    let output = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args([
+            "--fake-session",
+            SCRIPT,
+            "current",
+            "--format",
+            "{{artist}} - {{title}} [{{position | clock}}/{{duration}}]",
+        ])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Some Artist - First Song [0:0"));
+    assert!(stdout.ends_with("/3:20]\n"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args(["--fake-session", SCRIPT, "current", "--format", "{{title"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn cli_watches_fake_session() {
+    let mut watch = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args(["--fake-session", SCRIPT, "watch", "--all"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let lines = BufReader::new(watch.stdout.take().unwrap()).lines();
+    let mut saw_second_song = false;
+    for line in lines {
+        if line.unwrap().contains("title: Second Song") {
+            saw_second_song = true;
+            break;
+        }
+    }
+    watch.kill().ok();
+    watch.wait().ok();
+
+    assert!(saw_second_song);
+}
+
+#[test]
+fn cli_filters_seeks() {
+    // `timeline` is another name for the seeks
+    for kind in ["seek", "timeline"] {
+        let mut watch = Command::new(env!("CARGO_BIN_EXE_window"))
+            .args([
+                "--fake-session",
+                SCRIPT,
+                "watch",
+                "--json",
+                "--events",
+                kind,
+            ])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let line = BufReader::new(watch.stdout.take().unwrap())
+            .lines()
+            .next()
+            .unwrap()
+            .unwrap();
+        watch.kill().ok();
+        watch.wait().ok();
+
+        let record: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(record["type"], "seeked", "{}", kind);
+        let to = record["event"]["to_ms"].as_u64().unwrap();
+        assert!((100_000..102_000).contains(&to), "{}", to);
+    }
+}
+
+#[test]
+fn cli_watches_as_json_lines() {
+    let mut watch = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args([
+            "--fake-session",
+            SCRIPT,
+            "watch",
+            "--json",
+            "--events",
+            "track,status",
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut records = vec![];
+    for line in BufReader::new(watch.stdout.take().unwrap()).lines() {
+        let record: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+        let done = record["type"] == "status_changed" && record["event"]["to"] == "PAUSED";
+        records.push(record);
+        if done {
+            break;
+        }
+    }
+    watch.kill().ok();
+    watch.wait().ok();
+
+    let types: Vec<&str> = records
+        .iter()
+        .map(|record| record["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        types,
+        [
+            "track_changed",
+            "status_changed",
+            "track_changed",
+            "status_changed"
+        ]
+    );
+    assert!(records
+        .iter()
+        .all(|record| record["session_id"] == "demo-player" && record["timestamp"].is_string()));
+    assert_eq!(records[2]["event"]["to"]["title"], "Second Song");
+}
+
+#[test]
+fn cli_writes_a_status_bar() {
+    let mut bar = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args(["--fake-session", SCRIPT, "bar", "--style", "waybar"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut saw_second_song = false;
+    for line in BufReader::new(bar.stdout.take().unwrap()).lines() {
+        let json: serde_json::Value = serde_json::from_str(&line.unwrap()).unwrap();
+        assert_eq!(json["class"], "playing");
+        if json["text"] == "Some Artist - Second Song" {
+            saw_second_song = true;
+            break;
+        }
+    }
+    bar.kill().ok();
+    bar.wait().ok();
+
+    assert!(saw_second_song);
+}
+
+#[test]
+fn toggles_and_stops_playback() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let status = || session.playback_info().unwrap().playback_status;
+
+    assert_eq!(status(), PlaybackStatus::Playing);
+    toggle_play_pause(&*session).unwrap();
+    assert_eq!(status(), PlaybackStatus::Paused);
+    toggle_play_pause(&*session).unwrap();
+    assert_eq!(status(), PlaybackStatus::Playing);
+
+    stop(&*session).unwrap();
+    assert_eq!(status(), PlaybackStatus::Stopped);
+    toggle_play_pause(&*session).unwrap();
+    assert_eq!(status(), PlaybackStatus::Playing);
+
+    // The fake session reports no fast-forward or channel controls
+    assert_eq!(
+        fast_forward(&*session),
+        Err(MediaError::Unsupported(Control::FastForward))
+    );
+    assert_eq!(
+        channel_up(&*session),
+        Err(MediaError::Unsupported(Control::ChannelUp))
+    );
+}
+
+#[test]
+fn reads_and_caches_album_art() {
+    let cover = std::fs::read(FIXTURES.to_string() + "/cover.png").unwrap();
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+
+    let art = album_art(&*session, None).unwrap();
+    assert_eq!(art.data, cover);
+    assert_eq!(art.content_type, "image/png");
+    assert!(currently_playing_raw(&*session)
+        .unwrap()
+        .contains(&format!(r#""art_hash":"{}""#, art.hash())));
+    assert!(currently_playing_raw_with_art(&*session, None)
+        .unwrap()
+        .contains(&art.data_url()));
+
+    // The fake backend can't scale images
+    assert!(matches!(
+        album_art(&*session, Some(64)),
+        Err(MediaError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        currently_playing_raw_with_art(&*session, Some(64)),
+        Err(MediaError::InvalidArgument(_))
+    ));
+
+    // The art is only read again once the track or its art changes
+    let mut cache = ArtCache::default();
+    assert_eq!(cache.get(&*session, None), Some(&art));
+    let mut track = FakeTrack {
+        title: "First Song".to_string(),
+        artist: "Some Artist".to_string(),
+        album: "Some Album".to_string(),
+        ..Default::default()
+    };
+    backend.apply(&FakeAction::Track(track.clone()));
+    assert_eq!(cache.get(&*session, None), None);
+    track.art = Some((FIXTURES.to_string() + "/cover.png").into());
+    backend.apply(&FakeAction::Track(track));
+    assert_eq!(cache.get(&*session, None), Some(&art));
+
+    session.next_track().unwrap();
+    assert_eq!(cache.get(&*session, None), None);
+    assert_eq!(
+        album_art(&*session, None),
+        Err(MediaError::PropertyUnavailable("album art"))
+    );
+}
+
+#[test]
+fn cli_saves_album_art() {
+    let out = std::env::temp_dir().join(format!("window-art-{}.png", std::process::id()));
+    let status = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args(["--fake-session", SCRIPT, "art", "--out"])
+        .arg(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let saved = std::fs::read(&out).unwrap();
+    std::fs::remove_file(&out).ok();
+    assert_eq!(
+        saved,
+        std::fs::read(FIXTURES.to_string() + "/cover.png").unwrap()
+    );
+
+    let scaled = Command::new(env!("CARGO_BIN_EXE_window"))
+        .args(["--fake-session", SCRIPT, "art", "--size", "64"])
+        .output()
+        .unwrap();
+    assert_eq!(scaled.status.code(), Some(6));
+    assert!(scaled.stdout.is_empty());
+}
+
+#[test]
+fn missing_script_is_a_backend_error() {
+    assert!(matches!(
+        FakeBackend::from_file(FIXTURES.to_string() + "/missing.json"),
+        Err(MediaError::Backend(_))
+    ));
+}
+
+#[test]
+fn cli_reports_errors_with_exit_codes() {
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_window"))
+            .args(["--fake-session", SCRIPT])
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--session", "7", "play"]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).starts_with("Error: "));
+
+    let output = run(&["--json", "ff"]);
+    assert_eq!(output.status.code(), Some(4));
+    let error: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    assert_eq!(error["error"], "unsupported");
+    assert_eq!(error["control"], "fast-forward");
+
+    let output = run(&["rate", "0"]);
+    assert_eq!(output.status.code(), Some(6));
+}
+
+#[test]
+fn timeline_uses_milliseconds_and_timestamps() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    backend.apply(&FakeAction::Seek {
+        position_ms: 50_000,
+    });
+
+    let timeline = session.timeline_properties().unwrap();
+    assert_eq!(timeline.duration(), Some(Duration::from_secs(200)));
+    assert_eq!(timeline.finished_percentage(), Some(25.0));
+
+    let json = serde_json::to_value(&timeline).unwrap();
+    assert_eq!(json["position_ms"], 50_000);
+    assert_eq!(json["end_time_ms"], 200_000);
+    let last_updated = json["last_updated"].as_str().unwrap();
+    assert!(last_updated.ends_with('Z') && last_updated.len() == "2022-05-28T14:03:07.250Z".len());
+
+    let info: serde_json::Value =
+        serde_json::from_str(&currently_playing_raw(&*session).unwrap()).unwrap();
+    assert_eq!(info["duration_ms"], 200_000);
+    assert_eq!(info["finished_percentage"], 25.0);
+    assert_eq!(info["live"], false);
+}
+
+#[test]
+fn manager_waits_for_sessions_to_appear() {
+    let backend = FakeBackend::from_file(LATE_SCRIPT).unwrap();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let (manager_tx, manager_rx) = crossbeam_channel::unbounded();
+
+    let manager_backend = backend.clone();
+    let manager = std::thread::spawn(move || {
+        Manager::with_backend(Box::new(manager_backend), tx, manager_rx)
+            .unwrap()
+            .start_sync();
+    });
+
+    // Stand in for the thread controller and forward everything else to the
+    // manager
+    let next_session = || loop {
+        match rx.recv_timeout(Duration::from_secs(5)).unwrap() {
+            ThreadMessage::Media(ManagerMessage::SessionChanged(id)) => return id,
+            msg => manager_tx.send(msg).unwrap(),
+        }
+    };
+
+    assert_eq!(next_session(), None);
+    backend.start();
+    assert_eq!(next_session(), Some("late-player".to_string()));
+    assert_eq!(next_session(), None);
+
+    manager_tx.send(ThreadMessage::Stop).unwrap();
+    manager.join().unwrap();
+}
+
+#[test]
+fn manager_publishes_state_to_subscribers() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let (manager_tx, manager_rx) = crossbeam_channel::unbounded();
+    let (states_tx, states_rx) = crossbeam_channel::unbounded();
+    let titles = Arc::new(Mutex::new(vec![]));
+
+    let manager_titles = titles.clone();
+    let manager = std::thread::spawn(move || {
+        Manager::with_backend(Box::new(backend), tx, manager_rx)
+            .unwrap()
+            .subscribe(states_tx)
+            .on_event(move |msg| {
+                if let ManagerMessage::StateChanged(state) = msg {
+                    manager_titles
+                        .lock()
+                        .unwrap()
+                        .push(state.media.title.clone());
+                }
+            })
+            .start_sync();
+    });
+
+    // Stand in for the thread controller, which hands every message to every
+    // thread, including the manager's own
+    let forward_tx = manager_tx.clone();
+    std::thread::spawn(move || {
+        for msg in rx {
+            forward_tx.send(msg).ok();
+        }
+    });
+
+    let next = || states_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    let next_event = || match next() {
+        ManagerMessage::Event(event) => *event,
+        msg => panic!("expected an event, got {:?}", msg),
+    };
+    assert!(matches!(
+        next(),
+        ManagerMessage::SessionChanged(Some(id)) if id == "demo-player"
+    ));
+    match next() {
+        ManagerMessage::StateChanged(state) => {
+            assert_eq!(state.session_id, "demo-player");
+            assert_eq!(state.media.title, "First Song");
+            assert_eq!(state.duration_ms, Some(200_000));
+            assert!(state.art_hash.is_some());
+        }
+        msg => panic!("expected the initial state, got {:?}", msg),
+    }
+    assert!(matches!(
+        next_event(),
+        MediaEvent::TrackChanged { from: None, .. }
+    ));
+    assert!(matches!(
+        next_event(),
+        MediaEvent::StatusChanged { from: None, .. }
+    ));
+
+    // Both the media and timeline change of the skip end up in one state
+    session.next_track().unwrap();
+    match next() {
+        ManagerMessage::StateChanged(state) => assert_eq!(state.media.title, "Second Song"),
+        msg => panic!("expected a state change, got {:?}", msg),
+    }
+    match next_event() {
+        MediaEvent::TrackChanged {
+            from: Some(from),
+            to,
+            ..
+        } => {
+            assert_eq!(from.title, "First Song");
+            assert_eq!(to.title, "Second Song");
+        }
+        event => panic!("expected a track change, got {:?}", event),
+    }
+    // The last track can't be skipped
+    assert!(matches!(
+        next_event(),
+        MediaEvent::PlaybackChanged { playback, .. } if !playback.active_controls.is_next_enabled
+    ));
+
+    // Reporting the same state again publishes nothing
+    session.set_shuffle(false).unwrap();
+    assert!(states_rx.recv_timeout(Duration::from_millis(300)).is_err());
+
+    session.pause().unwrap();
+    assert!(matches!(
+        next(),
+        ManagerMessage::StateChanged(state)
+            if state.playback.playback_status == PlaybackStatus::Paused
+    ));
+    assert!(matches!(
+        next_event(),
+        MediaEvent::StatusChanged {
+            to: PlaybackStatus::Paused,
+            ..
+        }
+    ));
+
+    manager_tx.send(ThreadMessage::Stop).unwrap();
+    manager.join().unwrap();
+
+    let titles = titles.lock().unwrap();
+    assert_eq!(titles.first().map(String::as_str), Some("First Song"));
+    assert_eq!(titles.last().map(String::as_str), Some("Second Song"));
+}
+
+#[test]
+fn manager_survives_absurd_playback_rates() {
+    let backend = FakeBackend::new(FakeScript {
+        tracks: vec![FakeTrack {
+            duration_ms: 200_000,
+            ..Default::default()
+        }],
+        playing: true,
+        ..Default::default()
+    });
+    // Players report what they like, unlike `set_playback_rate`
+    backend.apply(&FakeAction::Rate { rate: 1e300 });
+    let (tx, rx) = crossbeam_channel::unbounded();
+    let (manager_tx, manager_rx) = crossbeam_channel::unbounded();
+    let (states_tx, states_rx) = crossbeam_channel::unbounded();
+
+    let manager_backend = backend.clone();
+    let manager = std::thread::spawn(move || {
+        Manager::with_backend(Box::new(manager_backend), tx, manager_rx)
+            .unwrap()
+            .subscribe(states_tx)
+            .start_sync();
+    });
+    let forward_tx = manager_tx.clone();
+    std::thread::spawn(move || {
+        for msg in rx {
+            forward_tx.send(msg).ok();
+        }
+    });
+
+    let next_state = || loop {
+        if let ManagerMessage::StateChanged(state) =
+            states_rx.recv_timeout(Duration::from_secs(5)).unwrap()
+        {
+            return state;
+        }
+    };
+    assert_eq!(next_state().timeline.pos, Duration::from_secs(200));
+    backend.apply(&FakeAction::Seek {
+        position_ms: 50_000,
+    });
+    // Still running after the seek
+    backend.apply(&FakeAction::Pause);
+    let state = next_state();
+    assert_eq!(state.playback.playback_status, PlaybackStatus::Paused);
+    assert_eq!(state.timeline.pos, Duration::from_secs(200));
+
+    manager_tx.send(ThreadMessage::Stop).unwrap();
+    manager.join().unwrap();
+}
+
+#[test]
+fn waits_for_a_session() {
+    let backend = FakeBackend::from_file(LATE_SCRIPT).unwrap();
+    let current = SessionSelector::Current;
+
+    assert!(matches!(
+        wait_for_session(&backend, &current, Some(Duration::from_millis(100))),
+        Err(MediaError::NoSession)
+    ));
+
+    backend.start();
+    let session = wait_for_session(&backend, &current, Some(Duration::from_secs(5))).unwrap();
+    assert_eq!(session.source_app_id(), "late-player");
+}
+
+#[test]
+fn cli_waits_for_a_session() {
+    let wait = |timeout: &str| {
+        Command::new(env!("CARGO_BIN_EXE_window"))
+            .args([
+                "--fake-session",
+                LATE_SCRIPT,
+                "wait-session",
+                "--timeout",
+                timeout,
+            ])
+            .output()
+            .unwrap()
+    };
+
+    let output = wait("5s");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "late-player\n");
+
+    assert_eq!(wait("0.1s").status.code(), Some(3));
+}
+
+#[test]
+fn async_commands_report_whether_they_were_accepted() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    session.pause().unwrap();
+
+    let (played, sought) = block_on(async {
+        futures::join!(
+            play_async(&*session),
+            seek_async(&*session, SeekTarget::Absolute(Duration::from_secs(30)))
+        )
+    });
+    assert_eq!(played, Ok(true));
+    assert_eq!(sought, Ok(Some(Duration::from_secs(30))));
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Playing
+    );
+
+    // The session itself refuses what it can't do, the checked functions
+    // don't even ask
+    assert_eq!(block_on(session.fast_forward_async()), Ok(false));
+    assert_eq!(
+        block_on(fast_forward_async(&*session)),
+        Err(MediaError::Unsupported(Control::FastForward))
+    );
+}
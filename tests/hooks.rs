@@ -0,0 +1,185 @@
+#![cfg(unix)]
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use window::{
+    controller::{Thread, ThreadMessage},
+    hooks::{state_env, HooksConfig},
+    media::{
+        diff_states, FakeBackend, ManagerMessage, MediaBackend, MediaError, MediaState,
+        PlaybackStatus, SystemClock,
+    },
+};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+/// Empty directory for the files of one test
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("window-hooks-{}-{}", std::process::id(), name));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn config(json: serde_json::Value, dir: &Path) -> HooksConfig {
+    let path = dir.join("hooks.json");
+    std::fs::write(&path, json.to_string()).unwrap();
+    HooksConfig::from_file(path).unwrap()
+}
+
+/// Publishes what a manager would for the session starting to play and then
+/// pausing
+fn publish(thread: &Thread) {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let playing = MediaState::read(&*session, &SystemClock).unwrap();
+    session.pause().unwrap();
+    let paused = MediaState::read(&*session, &SystemClock).unwrap();
+
+    let send = |msg| thread.send_message(ThreadMessage::Media(msg));
+    send(ManagerMessage::SessionChanged(Some(
+        "demo-player".to_string(),
+    )));
+    send(ManagerMessage::StateChanged(Box::new(playing.clone())));
+    for event in diff_states(None, &playing) {
+        send(ManagerMessage::Event(Box::new(event)));
+    }
+    send(ManagerMessage::StateChanged(Box::new(paused.clone())));
+    for event in diff_states(Some(&playing), &paused) {
+        send(ManagerMessage::Event(Box::new(event)));
+    }
+}
+
+#[test]
+fn runs_hooks_with_the_state() {
+    let dir = scratch_dir("state");
+    let log = dir.join("log");
+    let config = config(
+        serde_json::json!({
+            "hooks": [{
+                "on": ["session_changed", "track_changed", "playback_started", "playback_stopped"],
+                "command": [
+                    "sh", "-c",
+                    "echo \"$WINDOW_EVENT $WINDOW_STATUS $WINDOW_TITLE $WINDOW_DURATION_MS\" >> \"$0\"; \
+                    cat > \"$0.$WINDOW_EVENT.json\"",
+                    log,
+                ],
+            }],
+        }),
+        &dir,
+    );
+
+    let thread = Thread::new(move |rx| config.run(rx));
+    publish(&thread);
+    thread.stop();
+
+    assert_eq!(
+        std::fs::read_to_string(&log).unwrap(),
+        "session_changed PLAYING First Song 200000\n\
+        track_changed PLAYING First Song 200000\n\
+        playback_started PLAYING First Song 200000\n\
+        playback_stopped PAUSED First Song 200000\n"
+    );
+
+    let stdin = |event: &str| -> serde_json::Value {
+        let path = format!("{}.{}.json", log.display(), event);
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    };
+    let record = stdin("session_changed");
+    assert_eq!(record["type"], "session_changed");
+    assert_eq!(record["state"]["media"]["title"], "First Song");
+    let record = stdin("playback_stopped");
+    assert_eq!(record["type"], "status_changed");
+    assert_eq!(record["event"]["from"], "PLAYING");
+    assert_eq!(record["state"]["playback"]["playback_status"], "PAUSED");
+}
+
+#[test]
+fn kills_hooks_after_their_timeout() {
+    let dir = scratch_dir("timeout");
+    let log = dir.join("log");
+    let config = config(
+        serde_json::json!({
+            "timeout_ms": 200,
+            "hooks": [
+                { "on": ["track_changed"], "command": ["sleep", "10"] },
+                {
+                    "on": ["track_changed"],
+                    "command": ["sh", "-c", "echo \"$WINDOW_TITLE\" >> \"$0\"", log],
+                    "timeout_ms": 5000,
+                },
+            ],
+        }),
+        &dir,
+    );
+
+    let start = Instant::now();
+    let thread = Thread::new(move |rx| config.run(rx));
+    publish(&thread);
+    thread.stop();
+
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(std::fs::read_to_string(&log).unwrap(), "First Song\n");
+}
+
+#[test]
+fn rejects_invalid_configs() {
+    let dir = scratch_dir("invalid");
+    let path = dir.join("hooks.json");
+
+    for json in [
+        r#"{"hooks": [{"on": ["track_changed"], "command": []}]}"#,
+        r#"{"hooks": [{"on": ["lunch_time"], "command": ["true"]}]}"#,
+        r#"{"timeout_ms": 1000}"#,
+        "hooks: []",
+    ] {
+        std::fs::write(&path, json).unwrap();
+        assert!(matches!(
+            HooksConfig::from_file(&path),
+            Err(MediaError::Backend(_))
+        ));
+    }
+    assert!(HooksConfig::from_file(dir.join("missing.json")).is_err());
+}
+
+#[test]
+fn defaults_to_a_ten_second_timeout() {
+    let dir = scratch_dir("defaults");
+    let path = dir.join("hooks.json");
+    std::fs::write(&path, r#"{"hooks": []}"#).unwrap();
+
+    assert_eq!(HooksConfig::default().timeout_ms, 10_000);
+    assert_eq!(HooksConfig::from_file(&path).unwrap().timeout_ms, 10_000);
+    assert!(HooksConfig::default().hooks.is_empty());
+}
+
+#[test]
+fn exposes_the_state_as_variables() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let mut state = MediaState::read(&*session, &SystemClock).unwrap();
+    state.playback.playback_status = PlaybackStatus::Stopped;
+    state.duration_ms = None;
+
+    let env = state_env(&state);
+    let var = |name: &str| {
+        env.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    assert_eq!(var("WINDOW_SESSION"), Some("demo-player"));
+    assert_eq!(var("WINDOW_ARTIST"), Some("Some Artist"));
+    assert_eq!(var("WINDOW_ALBUM"), Some("Some Album"));
+    assert_eq!(var("WINDOW_TRACK_NUMBER"), Some("1"));
+    assert_eq!(var("WINDOW_STATUS"), Some("STOPPED"));
+    assert_eq!(var("WINDOW_DURATION_MS"), Some(""));
+    assert_eq!(var("WINDOW_REPEAT"), Some("NONE"));
+    assert_eq!(var("WINDOW_SHUFFLE"), Some("false"));
+    assert!(var("WINDOW_ART_HASH").is_some_and(|hash| hash.len() == 40));
+}
@@ -0,0 +1,272 @@
+#![cfg(target_os = "linux")]
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use window::{
+    controller::ThreadMessage,
+    media::{
+        list_sessions, seek, select_session, set_repeat, toggle_shuffle, Control, ManagerMessage,
+        MediaBackend, MediaError, MprisBackend, PlaybackStatus, RepeatMode, SeekTarget,
+        SessionSelector,
+    },
+};
+use zbus::{
+    dbus_interface,
+    zvariant::{ObjectPath, Value},
+    SignalContext,
+};
+
+/// Private `dbus-daemon` which is killed once the test is over
+struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    fn start() -> Option<Self> {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .ok()?;
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take()?)
+            .read_line(&mut address)
+            .ok()?;
+
+        Some(Self {
+            daemon,
+            address: address.trim().to_string(),
+        })
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        self.daemon.kill().ok();
+        self.daemon.wait().ok();
+    }
+}
+
+struct FakePlayer {
+    status: String,
+    position: i64,
+    shuffle: bool,
+}
+
+#[dbus_interface(name = "org.mpris.MediaPlayer2.Player")]
+impl FakePlayer {
+    async fn play(&mut self, #[zbus(signal_context)] ctxt: SignalContext<'_>) {
+        self.status = "Playing".to_string();
+        self.playback_status_changed(&ctxt).await.ok();
+    }
+
+    async fn pause(&mut self, #[zbus(signal_context)] ctxt: SignalContext<'_>) {
+        self.status = "Paused".to_string();
+        self.playback_status_changed(&ctxt).await.ok();
+    }
+
+    fn set_position(&mut self, track: ObjectPath<'_>, position: i64) {
+        if track.as_str() == "/track/1" {
+            self.position = position;
+        }
+    }
+
+    #[dbus_interface(property)]
+    fn playback_status(&self) -> String {
+        self.status.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn metadata(&self) -> HashMap<String, Value<'_>> {
+        HashMap::from([
+            ("xesam:title".to_string(), Value::from("Song")),
+            ("xesam:artist".to_string(), Value::from(vec!["Artist"])),
+            ("xesam:album".to_string(), Value::from("Album")),
+            ("mpris:length".to_string(), Value::from(180_000_000i64)),
+            (
+                "mpris:artUrl".to_string(),
+                Value::from(concat!(
+                    "file://",
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/fixtures/cover%2Epng"
+                )),
+            ),
+            (
+                "mpris:trackid".to_string(),
+                Value::from(ObjectPath::from_static_str_unchecked("/track/1")),
+            ),
+        ])
+    }
+
+    #[dbus_interface(property)]
+    fn position(&self) -> i64 {
+        self.position
+    }
+
+    #[dbus_interface(property)]
+    fn can_seek(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_control(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    #[dbus_interface(property)]
+    fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+    }
+
+    #[dbus_interface(property)]
+    fn can_play(&self) -> bool {
+        true
+    }
+
+    #[dbus_interface(property)]
+    fn can_pause(&self) -> bool {
+        true
+    }
+}
+
+fn start_player(bus: &Bus, name: &str) -> zbus::blocking::Connection {
+    zbus::blocking::ConnectionBuilder::address(bus.address.as_str())
+        .unwrap()
+        .name(format!("org.mpris.MediaPlayer2.{}", name))
+        .unwrap()
+        .serve_at(
+            "/org/mpris/MediaPlayer2",
+            FakePlayer {
+                status: "Paused".to_string(),
+                position: 90_000_000,
+                shuffle: false,
+            },
+        )
+        .unwrap()
+        .build()
+        .unwrap()
+}
+
+#[test]
+fn controls_and_reads_player() {
+    let bus = match Bus::start() {
+        Some(bus) => bus,
+        None => return eprintln!("dbus-daemon is not available, skipping"),
+    };
+    let _player = start_player(&bus, "fake");
+
+    let backend = MprisBackend::with_address(&bus.address).unwrap();
+    let session = backend.current_session().unwrap();
+    assert_eq!(session.source_app_id(), "fake");
+
+    let media = session.media_properties().unwrap();
+    assert_eq!(media.title, "Song");
+    assert_eq!(media.artist, "Artist");
+    assert_eq!(media.album_title, "Album");
+    let art = session.thumbnail(None).unwrap();
+    assert_eq!(art.content_type, "image/png");
+
+    let timeline = session.timeline_properties().unwrap();
+    assert_eq!(timeline.pos, Duration::from_secs(90));
+    assert_eq!(timeline.max_seek_time, Duration::from_secs(180));
+    assert!(!timeline.is_live());
+
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Paused
+    );
+
+    // The fake player's position doesn't move, so seek relative to it while
+    // paused, when the estimate holds still too
+    let forward = SeekTarget::Forward(Duration::from_secs(30));
+    assert_eq!(seek(&*session, forward), Ok(Duration::from_secs(120)));
+    assert_eq!(
+        session.timeline_properties().unwrap().pos,
+        Duration::from_secs(120)
+    );
+
+    session.play().unwrap();
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Playing
+    );
+
+    assert_eq!(toggle_shuffle(&*session), Ok(true));
+    assert!(session.playback_info().unwrap().shuffle_active);
+    // The fake player has no `LoopStatus`, so repeat is unsupported
+    assert_eq!(
+        set_repeat(&*session, RepeatMode::Track),
+        Err(MediaError::Unsupported(Control::Repeat))
+    );
+}
+
+#[test]
+fn forwards_player_events() {
+    let bus = match Bus::start() {
+        Some(bus) => bus,
+        None => return eprintln!("dbus-daemon is not available, skipping"),
+    };
+    let backend = MprisBackend::with_address(&bus.address).unwrap();
+    let (tx, rx) = crossbeam_channel::unbounded();
+
+    let _sessions = backend.listen(tx.clone()).unwrap();
+    let _player = start_player(&bus, "fake");
+    assert!(matches!(
+        rx.recv_timeout(Duration::from_secs(5)),
+        Ok(ThreadMessage::Media(ManagerMessage::SessionsChanged))
+    ));
+
+    let session = backend.current_session().unwrap();
+    let _listener = session.listen(tx).unwrap();
+    session.play().unwrap();
+    assert!(matches!(
+        rx.recv_timeout(Duration::from_secs(5)),
+        Ok(ThreadMessage::Media(ManagerMessage::PlaybackInfoChanged(id))) if id == "fake"
+    ));
+}
+
+#[test]
+fn lists_and_selects_players() {
+    let bus = match Bus::start() {
+        Some(bus) => bus,
+        None => return eprintln!("dbus-daemon is not available, skipping"),
+    };
+    let _fake = start_player(&bus, "fake");
+    let _other = start_player(&bus, "other");
+    let backend = MprisBackend::with_address(&bus.address).unwrap();
+
+    let sessions = list_sessions(&backend).unwrap();
+    let ids: Vec<_> = sessions.iter().map(|s| s.source_app_id.as_str()).collect();
+    assert_eq!(ids, ["fake", "other"]);
+
+    let other = select_session(&backend, &SessionSelector::AppId("other".to_string())).unwrap();
+    other.play().unwrap();
+    assert_eq!(
+        select_session(&backend, &SessionSelector::Index(1))
+            .unwrap()
+            .playback_info()
+            .unwrap()
+            .playback_status,
+        PlaybackStatus::Playing
+    );
+    // The playing player is preferred as the current one
+    assert_eq!(
+        select_session(&backend, &SessionSelector::Current)
+            .unwrap()
+            .source_app_id(),
+        "other"
+    );
+}
@@ -0,0 +1,192 @@
+use std::time::{Duration, SystemTime};
+
+use window::media::{
+    current_timeline, estimate_position, ActiveControls, Clock, FakeBackend, FakeScript, FakeTrack,
+    ManualClock, MediaBackend, PlaybackInfoProps, PlaybackStatus, PlaybackType, RepeatMode,
+    TimelineProps,
+};
+
+fn timeline(updated: SystemTime, pos_secs: u64, end_secs: u64) -> TimelineProps {
+    TimelineProps {
+        last_updated_time: updated,
+        pos: Duration::from_secs(pos_secs),
+        max_seek_time: Duration::from_secs(end_secs),
+        min_seek_time: Duration::ZERO,
+        endtime: Duration::from_secs(end_secs),
+        start_time: Duration::ZERO,
+    }
+}
+
+fn playback(status: PlaybackStatus, rate: f64) -> PlaybackInfoProps {
+    PlaybackInfoProps {
+        auto_repeat_mode: RepeatMode::None,
+        active_controls: ActiveControls::default(),
+        shuffle_active: false,
+        playback_status: status,
+        playback_type: PlaybackType::Unknown,
+        playback_rate: rate,
+    }
+}
+
+#[test]
+fn advances_while_playing() {
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000));
+    let timeline = timeline(clock.now(), 30, 180);
+    let playing = playback(PlaybackStatus::Playing, 1.0);
+
+    assert_eq!(
+        estimate_position(&timeline, &playing, clock.now()),
+        Duration::from_secs(30)
+    );
+
+    clock.advance(Duration::from_millis(12_500));
+    assert_eq!(
+        estimate_position(&timeline, &playing, clock.now()),
+        Duration::from_millis(42_500)
+    );
+}
+
+#[test]
+fn scales_with_the_playback_rate() {
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000));
+    let timeline = timeline(clock.now(), 30, 180);
+
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(
+        estimate_position(
+            &timeline,
+            &playback(PlaybackStatus::Playing, 1.5),
+            clock.now()
+        ),
+        Duration::from_secs(45)
+    );
+    assert_eq!(
+        estimate_position(
+            &timeline,
+            &playback(PlaybackStatus::Playing, 0.5),
+            clock.now()
+        ),
+        Duration::from_secs(35)
+    );
+}
+
+#[test]
+fn holds_still_unless_playing() {
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000));
+    let timeline = timeline(clock.now(), 30, 180);
+    clock.advance(Duration::from_secs(60));
+
+    for status in [
+        PlaybackStatus::Paused,
+        PlaybackStatus::Stopped,
+        PlaybackStatus::Changing,
+        PlaybackStatus::Opened,
+        PlaybackStatus::Closed,
+    ] {
+        assert_eq!(
+            estimate_position(&timeline, &playback(status, 1.0), clock.now()),
+            Duration::from_secs(30)
+        );
+    }
+}
+
+#[test]
+fn stops_at_the_end_unless_live() {
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000));
+    let playing = playback(PlaybackStatus::Playing, 1.0);
+    let track = timeline(clock.now(), 170, 180);
+    let live = timeline(clock.now(), 170, 0);
+
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(
+        estimate_position(&track, &playing, clock.now()),
+        Duration::from_secs(180)
+    );
+    assert_eq!(
+        estimate_position(&live, &playing, clock.now()),
+        Duration::from_secs(230)
+    );
+}
+
+#[test]
+fn saturates_on_absurd_rates() {
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000));
+    let track = timeline(clock.now(), 30, 180);
+    let live = timeline(clock.now(), 30, 0);
+
+    clock.advance(Duration::from_secs(10));
+    for rate in [1e300, f64::MAX] {
+        let playing = playback(PlaybackStatus::Playing, rate);
+        assert_eq!(
+            estimate_position(&track, &playing, clock.now()),
+            Duration::from_secs(180)
+        );
+        assert_eq!(
+            estimate_position(&live, &playing, clock.now()),
+            Duration::MAX
+        );
+    }
+}
+
+#[test]
+fn ignores_updates_from_the_future() {
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000));
+    let timeline = timeline(clock.now() + Duration::from_secs(5), 30, 180);
+
+    assert_eq!(
+        estimate_position(
+            &timeline,
+            &playback(PlaybackStatus::Playing, 1.0),
+            clock.now()
+        ),
+        Duration::from_secs(30)
+    );
+}
+
+#[test]
+fn estimates_the_timeline_of_a_session() {
+    let backend = FakeBackend::new(FakeScript {
+        tracks: vec![FakeTrack {
+            duration_ms: 180_000,
+            ..Default::default()
+        }],
+        playing: true,
+        ..Default::default()
+    });
+    let session = backend.current_session().unwrap();
+
+    let reported = session.timeline_properties().unwrap();
+    let clock = ManualClock::new(reported.last_updated_time);
+    clock.advance(Duration::from_secs(20));
+
+    let timeline = current_timeline(&*session, &clock).unwrap();
+    assert_eq!(timeline.pos, Duration::from_secs(20));
+    assert_eq!(timeline.last_updated_time, clock.now());
+    assert_eq!(timeline.finished_percentage().map(f64::round), Some(11.0));
+}
+
+#[test]
+fn fake_session_keeps_its_position_when_paused() {
+    let backend = FakeBackend::new(FakeScript {
+        tracks: vec![FakeTrack {
+            duration_ms: 180_000,
+            ..Default::default()
+        }],
+        playing: true,
+        ..Default::default()
+    });
+    let session = backend.current_session().unwrap();
+
+    std::thread::sleep(Duration::from_millis(50));
+    session.pause().unwrap();
+
+    let reported = session.timeline_properties().unwrap();
+    assert!(reported.pos >= Duration::from_millis(50));
+
+    // Nothing moves while paused, whatever the time
+    let clock = ManualClock::new(reported.last_updated_time + Duration::from_secs(60));
+    assert_eq!(
+        current_timeline(&*session, &clock).unwrap().pos,
+        reported.pos
+    );
+}
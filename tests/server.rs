@@ -0,0 +1,772 @@
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tungstenite::{Message, WebSocket};
+use window::{
+    controller::{Thread, ThreadMessage},
+    media::{
+        CommandFuture, FakeAction, FakeBackend, FakeScript, ManagerMessage, MediaBackend,
+        MediaError, MediaProps, MediaSession, MediaState, PlaybackInfoProps, PlaybackStatus,
+        RepeatMode, Subscription, SystemClock, Thumbnail, TimelineProps,
+    },
+    server::{Devices, Pairing, Server},
+};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+/// Sends a request without a body and returns the status and JSON body of the
+/// answer
+fn request(addr: SocketAddr, method: &str, path: &str) -> (u16, serde_json::Value) {
+    send(addr, method, path, None, "")
+}
+
+/// Sends a request with `body`, as the device with `token` if any
+fn send(
+    addr: SocketAddr,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> (u16, serde_json::Value) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let authorization = token
+        .map(|token| format!("Authorization: Bearer {}\r\n", token))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        authorization,
+        body.len(),
+        body
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    assert!(head.contains("Content-Type: application/json"), "{}", head);
+
+    (status, serde_json::from_str(body).unwrap())
+}
+
+/// Serves `backend` on a free port until the returned thread is stopped
+fn serve(backend: FakeBackend) -> (SocketAddr, Thread) {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+
+    (addr, thread)
+}
+
+/// Opens a WebSocket to the events of the server
+fn connect(addr: SocketAddr) -> WebSocket<TcpStream> {
+    let stream = TcpStream::connect(addr).unwrap();
+    let (socket, _) = tungstenite::client(format!("ws://{}/events", addr), stream).unwrap();
+    socket
+}
+
+/// Pairs as `name` with `pin`
+fn pair(addr: SocketAddr, pin: &str, name: &str) -> (u16, serde_json::Value) {
+    let body = serde_json::json!({ "pin": pin, "name": name }).to_string();
+    send(addr, "POST", "/pair", None, &body)
+}
+
+/// An empty directory for the test called `name`
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("window-server-{}-{}", std::process::id(), name));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Next text message of the socket as JSON
+fn next_json(socket: &mut WebSocket<TcpStream>) -> serde_json::Value {
+    loop {
+        match socket.read().unwrap() {
+            Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+            Message::Close(_) => panic!("the server closed the socket"),
+            _ => {}
+        }
+    }
+}
+
+#[test]
+fn controls_the_session() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let (addr, thread) = serve(backend.clone());
+
+    let (status, current) = request(addr, "GET", "/current");
+    assert_eq!(status, 200);
+    assert_eq!(current["session_id"], "demo-player");
+    assert_eq!(current["media"]["title"], "First Song");
+
+    assert_eq!(
+        request(addr, "POST", "/pause"),
+        (200, serde_json::json!({ "ok": true }))
+    );
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Paused
+    );
+
+    assert_eq!(request(addr, "POST", "/next").0, 200);
+    assert_eq!(request(addr, "POST", "/play").0, 200);
+    let (_, current) = request(addr, "GET", "/current");
+    assert_eq!(current["media"]["title"], "Second Song");
+    assert_eq!(current["playback"]["playback_status"], "PLAYING");
+
+    assert_eq!(request(addr, "POST", "/previous").0, 200);
+    assert_eq!(session.media_properties().unwrap().title, "First Song");
+
+    thread.stop();
+}
+
+#[test]
+fn answers_errors_as_json() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let (addr, thread) = serve(backend);
+
+    // Already on the first track
+    let (status, error) = request(addr, "POST", "/previous");
+    assert_eq!(status, 409);
+    assert_eq!(error["error"], "unsupported");
+    assert_eq!(error["control"], "previous");
+
+    assert_eq!(request(addr, "GET", "/pause").0, 405);
+    assert_eq!(request(addr, "POST", "/current").0, 405);
+    assert_eq!(request(addr, "GET", "/events").0, 426);
+    let (status, error) = request(addr, "GET", "/lunch");
+    assert_eq!(status, 404);
+    assert_eq!(error["error"], "not_found");
+
+    thread.stop();
+}
+
+#[test]
+fn reports_missing_sessions() {
+    let backend = FakeBackend::new(FakeScript {
+        tracks: vec![],
+        ..Default::default()
+    });
+    backend.apply(&FakeAction::Close);
+    let (addr, thread) = serve(backend);
+
+    let (status, error) = request(addr, "GET", "/current");
+    assert_eq!(status, 404);
+    assert_eq!(error["error"], "no_session");
+
+    thread.stop();
+}
+
+#[test]
+fn pushes_snapshots_and_changes_over_websockets() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let (addr, thread) = serve(backend);
+
+    let mut sockets = [connect(addr), connect(addr)];
+    for socket in &mut sockets {
+        let snapshot = next_json(socket);
+        assert_eq!(snapshot["type"], "snapshot");
+        assert_eq!(snapshot["session_id"], "demo-player");
+        assert_eq!(snapshot["state"]["media"]["title"], "First Song");
+    }
+
+    session.pause().unwrap();
+    let state = MediaState::read(&*session, &SystemClock).unwrap();
+    thread.send_message(ThreadMessage::Media(ManagerMessage::StateChanged(
+        Box::new(state),
+    )));
+    for socket in &mut sockets {
+        let record = next_json(socket);
+        assert_eq!(record["type"], "state");
+        assert_eq!(record["state"]["playback"]["playback_status"], "PAUSED");
+    }
+
+    // Commands are answered on the socket which sent them
+    let [first, second] = &mut sockets;
+    first
+        .send(Message::Text(r#"{"command": "play", "id": 7}"#.to_string()))
+        .unwrap();
+    assert_eq!(
+        next_json(first),
+        serde_json::json!({ "type": "reply", "id": 7, "status": 200, "body": { "ok": true } })
+    );
+    first
+        .send(Message::Text(r#"{"command": "current"}"#.to_string()))
+        .unwrap();
+    let reply = next_json(first);
+    assert_eq!(reply["status"], 200);
+    assert_eq!(reply["body"]["playback"]["playback_status"], "PLAYING");
+    first
+        .send(Message::Text(r#"{"command": "rewind"}"#.to_string()))
+        .unwrap();
+    assert_eq!(next_json(first)["status"], 404);
+    first.send(Message::Text("pause".to_string())).unwrap();
+    assert_eq!(next_json(first)["body"]["error"], "invalid_command");
+
+    // The server closes the sockets when it stops
+    thread.stop();
+    assert!(matches!(second.read(), Ok(Message::Close(_))));
+}
+
+#[test]
+fn drops_websocket_clients_which_fall_behind() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let mut state = MediaState::read(&*session, &SystemClock).unwrap();
+    // More than fits into the buffers of the socket and the client together
+    state.media.title = "x".repeat(128 * 1024);
+    let (addr, thread) = serve(backend);
+
+    // Never reads what is pushed to it until the fast one saw it all
+    let mut slow = connect(addr);
+    let mut fast = connect(addr);
+    assert_eq!(next_json(&mut slow)["type"], "snapshot");
+    assert_eq!(next_json(&mut fast)["type"], "snapshot");
+    let records = 1000;
+    for record in 0..records {
+        let mut state = state.clone();
+        if record == records - 1 {
+            state.media.title = "last".to_string();
+        }
+        thread.send_message(ThreadMessage::Media(ManagerMessage::StateChanged(
+            Box::new(state),
+        )));
+    }
+
+    let start = std::time::Instant::now();
+    assert_eq!(request(addr, "GET", "/current").0, 200);
+    assert!(start.elapsed() < std::time::Duration::from_secs(2));
+    while next_json(&mut fast)["state"]["media"]["title"] != "last" {}
+
+    slow.get_ref()
+        .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+        .unwrap();
+    // Gets what was buffered, without the records after it fell behind
+    let mut received = 0;
+    while let Ok(Message::Text(_)) = slow.read() {
+        received += 1;
+    }
+    assert!(received < records, "{}", received);
+
+    thread.stop();
+}
+
+#[test]
+fn pairs_devices_with_a_one_time_pin() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let path = temp_dir("pairs").join("devices.json");
+    let pairing = Pairing::open().unwrap();
+    let pin = pairing.pin().to_string();
+    let wrong = if pin == "000000" { "000001" } else { "000000" };
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .devices(Devices::open(&path).unwrap())
+        .pairing(pairing);
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+
+    let (status, body) = request(addr, "GET", "/current");
+    assert_eq!(status, 401);
+    assert_eq!(body["error"], "unauthorized");
+    let stream = TcpStream::connect(addr).unwrap();
+    assert!(tungstenite::client(format!("ws://{}/events", addr), stream).is_err());
+
+    assert_eq!(pair(addr, wrong, "Phone").1["error"], "wrong_pin");
+    assert_eq!(pair(addr, &pin, "").1["error"], "invalid_request");
+    let (status, body) = send(addr, "POST", "/pair", None, "pin");
+    assert_eq!((status, &body["error"]), (400, &"invalid_request".into()));
+
+    let (status, paired) = pair(addr, &pin, "Phone");
+    assert_eq!(status, 200);
+    assert_eq!(paired["device"]["name"], "Phone");
+    assert!(paired["device"].get("token_sha1").is_none());
+    let token = paired["token"].as_str().unwrap();
+    let id = paired["device"]["id"].as_str().unwrap();
+
+    // The PIN only pairs once
+    let (status, body) = pair(addr, &pin, "Laptop");
+    assert_eq!((status, &body["error"]), (403, &"pairing_closed".into()));
+
+    let (status, current) = send(addr, "GET", "/current", Some(token), "");
+    assert_eq!(status, 200);
+    assert_eq!(current["media"]["title"], "First Song");
+    assert_eq!(send(addr, "GET", "/current", Some("nope"), "").0, 401);
+    let stream = TcpStream::connect(addr).unwrap();
+    let (mut socket, _) =
+        tungstenite::client(format!("ws://{}/events?token={}", addr, token), stream).unwrap();
+    assert_eq!(next_json(&mut socket)["type"], "snapshot");
+
+    // Revoking in another process locks the device out
+    let mut devices = Devices::open(&path).unwrap();
+    assert_eq!(devices.list()[0].name, "Phone");
+    assert_eq!(devices.revoke(id).unwrap().name, "Phone");
+    assert_eq!(send(addr, "GET", "/current", Some(token), "").0, 401);
+
+    thread.stop();
+}
+
+#[test]
+fn notices_changes_to_the_devices_within_the_same_timestamp() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let path = temp_dir("timestamp").join("devices.json");
+    let mut devices = Devices::open(&path).unwrap();
+    let (phone, token) = devices.pair("Phone").unwrap();
+    devices.pair("Laptop").unwrap();
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .devices(Devices::open(&path).unwrap());
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+    assert_eq!(send(addr, "GET", "/current", Some(&token), "").0, 200);
+
+    // As if revoked right after the last write, on a file system with coarse
+    // timestamps
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    devices.revoke(&phone.id).unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+    assert_eq!(send(addr, "GET", "/current", Some(&token), "").0, 401);
+
+    thread.stop();
+}
+
+#[test]
+fn closes_the_sockets_of_revoked_devices() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let path = temp_dir("revoked").join("devices.json");
+    let mut devices = Devices::open(&path).unwrap();
+    let (phone, phone_token) = devices.pair("Phone").unwrap();
+    let (_, laptop_token) = devices.pair("Laptop").unwrap();
+    let (tablet, tablet_token) = devices.pair("Tablet").unwrap();
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .devices(Devices::open(&path).unwrap());
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+    let open = |token: &str| {
+        let stream = TcpStream::connect(addr).unwrap();
+        let url = format!("ws://{}/events?token={}", addr, token);
+        let (mut socket, _) = tungstenite::client(url, stream).unwrap();
+        assert_eq!(next_json(&mut socket)["type"], "snapshot");
+        socket
+    };
+    let mut phone_socket = open(&phone_token);
+    let mut laptop_socket = open(&laptop_token);
+    let mut tablet_socket = open(&tablet_token);
+    let closed = |socket: &mut WebSocket<TcpStream>| loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => panic!("a revoked device got {}", text),
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(_) => {}
+        }
+    };
+
+    // Revoked in another process, like `window devices revoke`
+    devices.revoke(&phone.id).unwrap();
+    devices.revoke(&tablet.id).unwrap();
+    phone_socket
+        .send(Message::Text(
+            r#"{"command": "pause", "id": 1}"#.to_string(),
+        ))
+        .unwrap();
+    let reply = next_json(&mut phone_socket);
+    assert_eq!(reply["status"], 401);
+    assert_eq!(reply["body"]["error"], "unauthorized");
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Playing
+    );
+    closed(&mut phone_socket);
+
+    // Only the devices still paired get the changes
+    let state = MediaState::read(&*session, &SystemClock).unwrap();
+    thread.send_message(ThreadMessage::Media(ManagerMessage::StateChanged(
+        Box::new(state),
+    )));
+    assert_eq!(next_json(&mut laptop_socket)["type"], "state");
+    closed(&mut tablet_socket);
+
+    thread.stop();
+}
+
+#[test]
+fn closes_pairing_after_too_many_wrong_pins() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let path = temp_dir("guesses").join("devices.json");
+    let pairing = Pairing::open().unwrap();
+    let pin = pairing.pin().to_string();
+    let wrong = if pin == "000000" { "000001" } else { "000000" };
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .devices(Devices::open(&path).unwrap())
+        .pairing(pairing);
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+
+    for _ in 0..5 {
+        assert_eq!(pair(addr, wrong, "Phone").1["error"], "wrong_pin");
+    }
+    assert_eq!(pair(addr, &pin, "Phone").1["error"], "pairing_closed");
+    assert!(Devices::open(&path).unwrap().list().is_empty());
+
+    thread.stop();
+}
+
+#[test]
+fn fails_to_bind_a_taken_address() {
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap().to_string();
+
+    assert!(Server::bind(&addr).is_err());
+}
+
+#[test]
+#[cfg(unix)]
+fn cli_serves_until_interrupted() {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Command, Stdio},
+    };
+
+    let dir = temp_dir("cli");
+    let mut serve = Command::new(env!("CARGO_BIN_EXE_window"))
+        .arg("--config-dir")
+        .arg(&dir)
+        .args(["--fake-session", SCRIPT, "serve", "--bind", "127.0.0.1:0"])
+        .arg("--no-tls")
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Kept open until the end, writing to a closed stderr would panic
+    let mut stderr = BufReader::new(serve.stderr.take().unwrap()).lines();
+    let addr: SocketAddr = stderr
+        .find_map(|line| {
+            line.unwrap()
+                .strip_prefix("[Server] Listening on http://")
+                .map(|addr| addr.parse().unwrap())
+        })
+        .unwrap();
+    // Nothing is paired yet, so pairing opens by itself
+    let pin = stderr
+        .find_map(|line| {
+            line.unwrap()
+                .strip_prefix("[Server] Pair a device with PIN ")
+                .map(|rest| rest[..6].to_string())
+        })
+        .unwrap();
+    let (status, paired) = pair(addr, &pin, "Phone");
+    assert_eq!(status, 200);
+    let token = paired["token"].as_str().unwrap();
+    assert!(dir.join("devices.json").exists());
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let (mut socket, _) =
+        tungstenite::client(format!("ws://{}/events?token={}", addr, token), stream).unwrap();
+    assert_eq!(next_json(&mut socket)["type"], "snapshot");
+
+    assert_eq!(send(addr, "POST", "/pause", Some(token), "").0, 200);
+    let (_, current) = send(addr, "GET", "/current", Some(token), "");
+    assert_eq!(current["playback"]["playback_status"], "PAUSED");
+    // The manager publishes the change to the socket
+    while next_json(&mut socket)["type"] != "status_changed" {}
+
+    Command::new("kill")
+        .args(["-INT", &serve.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(serve.wait().unwrap().success());
+}
+
+#[test]
+fn cli_manages_paired_devices() {
+    use std::process::Command;
+
+    let dir = temp_dir("devices");
+    let (device, _) = Devices::open(dir.join("devices.json"))
+        .unwrap()
+        .pair("Phone")
+        .unwrap();
+    let window = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_window"))
+            .arg("--config-dir")
+            .arg(&dir)
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    let list = window(&["devices", "list"]);
+    assert!(list.status.success());
+    let list = String::from_utf8(list.stdout).unwrap();
+    assert!(
+        list.starts_with(&format!("{}: Phone (paired ", device.id)),
+        "{}",
+        list
+    );
+
+    assert!(window(&["devices", "rename", &device.id, "Tablet"])
+        .status
+        .success());
+    // Names are checked as when pairing
+    for name in ["  ", &"x".repeat(65)] {
+        let rename = window(&["devices", "rename", &device.id, name]);
+        assert_eq!(rename.status.code(), Some(6));
+        assert!(String::from_utf8(rename.stderr)
+            .unwrap()
+            .contains("The name needs 1 to 64 characters"));
+    }
+    let list = window(&["--json", "devices", "list"]);
+    let json: serde_json::Value = serde_json::from_slice(&list.stdout).unwrap();
+    assert_eq!(json["id"], device.id.as_str());
+    assert_eq!(json["name"], "Tablet");
+    assert!(json.get("token_sha1").is_none());
+
+    let revoke = window(&["devices", "revoke", &device.id]);
+    assert_eq!(
+        String::from_utf8(revoke.stdout).unwrap(),
+        "Revoked Tablet\n"
+    );
+    assert!(window(&["devices", "list"]).stdout.is_empty());
+    assert!(!window(&["devices", "revoke", &device.id]).status.success());
+}
+
+/// Backend whose sessions only start playing once `play` lets them, and
+/// which hands out no session while `held` is locked, like a player slow to
+/// answer
+#[derive(Debug)]
+struct SlowBackend {
+    fake: FakeBackend,
+    play: crossbeam_channel::Receiver<()>,
+    held: Arc<Mutex<()>>,
+}
+
+impl MediaBackend for SlowBackend {
+    fn current_session(&self) -> Result<Box<dyn MediaSession>, MediaError> {
+        drop(self.held.lock().unwrap());
+        Ok(Box::new(SlowSession {
+            fake: self.fake.current_session()?,
+            play: self.play.clone(),
+        }))
+    }
+
+    fn sessions(&self) -> Result<Vec<Box<dyn MediaSession>>, MediaError> {
+        Ok(vec![self.current_session()?])
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        self.fake.listen(tx)
+    }
+}
+
+#[derive(Debug)]
+struct SlowSession {
+    fake: Box<dyn MediaSession>,
+    play: crossbeam_channel::Receiver<()>,
+}
+
+impl MediaSession for SlowSession {
+    fn source_app_id(&self) -> String {
+        self.fake.source_app_id()
+    }
+
+    fn play_async(&self) -> CommandFuture<'_> {
+        Box::pin(async move {
+            self.play.recv().ok();
+            self.fake.play_async().await
+        })
+    }
+
+    fn pause_async(&self) -> CommandFuture<'_> {
+        self.fake.pause_async()
+    }
+
+    fn next_track_async(&self) -> CommandFuture<'_> {
+        self.fake.next_track_async()
+    }
+
+    fn previous_track_async(&self) -> CommandFuture<'_> {
+        self.fake.previous_track_async()
+    }
+
+    fn stop_async(&self) -> CommandFuture<'_> {
+        self.fake.stop_async()
+    }
+
+    fn toggle_play_pause_async(&self) -> CommandFuture<'_> {
+        self.fake.toggle_play_pause_async()
+    }
+
+    fn fast_forward_async(&self) -> CommandFuture<'_> {
+        self.fake.fast_forward_async()
+    }
+
+    fn rewind_async(&self) -> CommandFuture<'_> {
+        self.fake.rewind_async()
+    }
+
+    fn channel_up_async(&self) -> CommandFuture<'_> {
+        self.fake.channel_up_async()
+    }
+
+    fn channel_down_async(&self) -> CommandFuture<'_> {
+        self.fake.channel_down_async()
+    }
+
+    fn record_async(&self) -> CommandFuture<'_> {
+        self.fake.record_async()
+    }
+
+    fn set_position_async(&self, position: Duration) -> CommandFuture<'_> {
+        self.fake.set_position_async(position)
+    }
+
+    fn set_shuffle_async(&self, shuffle: bool) -> CommandFuture<'_> {
+        self.fake.set_shuffle_async(shuffle)
+    }
+
+    fn set_repeat_async(&self, mode: RepeatMode) -> CommandFuture<'_> {
+        self.fake.set_repeat_async(mode)
+    }
+
+    fn set_playback_rate_async(&self, rate: f64) -> CommandFuture<'_> {
+        self.fake.set_playback_rate_async(rate)
+    }
+
+    fn media_properties(&self) -> Result<MediaProps, MediaError> {
+        self.fake.media_properties()
+    }
+
+    fn thumbnail(&self, max_size: Option<u32>) -> Option<Thumbnail> {
+        self.fake.thumbnail(max_size)
+    }
+
+    fn timeline_properties(&self) -> Result<TimelineProps, MediaError> {
+        self.fake.timeline_properties()
+    }
+
+    fn playback_info(&self) -> Result<PlaybackInfoProps, MediaError> {
+        self.fake.playback_info()
+    }
+
+    fn listen(
+        &self,
+        tx: crossbeam_channel::Sender<ThreadMessage>,
+    ) -> Result<Subscription, MediaError> {
+        self.fake.listen(tx)
+    }
+}
+
+#[test]
+fn keeps_serving_while_a_command_runs() {
+    let fake = FakeBackend::new(FakeScript::default());
+    let (play_tx, play_rx) = crossbeam_channel::unbounded();
+    let backend = SlowBackend {
+        fake: fake.clone(),
+        play: play_rx,
+        held: Arc::default(),
+    };
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+    let mut socket = connect(addr);
+    assert_eq!(next_json(&mut socket)["type"], "snapshot");
+
+    let playing = std::thread::spawn(move || request(addr, "POST", "/play"));
+
+    // The player takes its time, meanwhile requests are answered and changes
+    // pushed
+    let (status, current) = request(addr, "GET", "/current");
+    assert_eq!(status, 200);
+    assert_eq!(current["playback"]["playback_status"], "PAUSED");
+    let session = fake.current_session().unwrap();
+    let state = MediaState::read(&*session, &SystemClock).unwrap();
+    thread.send_message(ThreadMessage::Media(ManagerMessage::StateChanged(
+        Box::new(state),
+    )));
+    assert_eq!(next_json(&mut socket)["type"], "state");
+    assert!(!playing.is_finished());
+
+    play_tx.send(()).unwrap();
+    assert_eq!(
+        playing.join().unwrap(),
+        (200, serde_json::json!({ "ok": true }))
+    );
+    assert_eq!(
+        session.playback_info().unwrap().playback_status,
+        PlaybackStatus::Playing
+    );
+
+    thread.stop();
+}
+
+#[test]
+fn keeps_serving_while_a_player_is_looked_up() {
+    let fake = FakeBackend::new(FakeScript::default());
+    let (play_tx, play_rx) = crossbeam_channel::unbounded();
+    play_tx.send(()).unwrap();
+    let held = Arc::new(Mutex::new(()));
+    let backend = SlowBackend {
+        fake: fake.clone(),
+        play: play_rx,
+        held: held.clone(),
+    };
+    let server = Server::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+    let mut socket = connect(addr);
+    assert_eq!(next_json(&mut socket)["type"], "snapshot");
+
+    let lock = held.lock().unwrap();
+    let playing = std::thread::spawn(move || request(addr, "POST", "/play"));
+    let joining = std::thread::spawn(move || {
+        let mut socket = connect(addr);
+        (next_json(&mut socket), next_json(&mut socket))
+    });
+    std::thread::sleep(Duration::from_millis(100));
+
+    // Neither the command nor the snapshot of the new socket has a session
+    // yet, meanwhile requests are answered and changes pushed
+    assert_eq!(request(addr, "GET", "/nothing").0, 404);
+    let session = fake.current_session().unwrap();
+    let state = MediaState::read(&*session, &SystemClock).unwrap();
+    thread.send_message(ThreadMessage::Media(ManagerMessage::StateChanged(
+        Box::new(state),
+    )));
+    assert_eq!(next_json(&mut socket)["type"], "state");
+    assert!(!playing.is_finished());
+    assert!(!joining.is_finished());
+
+    drop(lock);
+    assert_eq!(
+        playing.join().unwrap(),
+        (200, serde_json::json!({ "ok": true }))
+    );
+    let (snapshot, state) = joining.join().unwrap();
+    assert_eq!(snapshot["type"], "snapshot");
+    assert_eq!(state["type"], "state");
+
+    thread.stop();
+}
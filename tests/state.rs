@@ -0,0 +1,74 @@
+use std::time::{Duration, SystemTime};
+
+use window::media::{
+    media_state_schema, Clock, FakeBackend, ManualClock, MediaBackend, MediaState, PlaybackStatus,
+    PlaybackType, RepeatMode, SystemClock,
+};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+const SCHEMA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/schema/media_state.json");
+
+#[test]
+fn schema_file_is_up_to_date() {
+    let generated = serde_json::to_string_pretty(&media_state_schema()).unwrap();
+    let committed = std::fs::read_to_string(SCHEMA).unwrap();
+
+    assert_eq!(
+        committed.trim_end(),
+        generated,
+        "schema/media_state.json is outdated, regenerate it with `window schema`"
+    );
+}
+
+#[test]
+fn state_round_trips_through_json() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    session.pause().unwrap();
+
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_millis(1_653_746_587_250));
+    let state = MediaState::read(&*session, &clock).unwrap();
+
+    let json = serde_json::to_value(&state).unwrap();
+    assert_eq!(json["session_id"], "demo-player");
+    assert_eq!(json["media"]["playback_type"], "MUSIC");
+    assert_eq!(json["timeline"]["last_updated"], "2022-05-28T14:03:07.250Z");
+    assert_eq!(json["playback"]["playback_status"], "PAUSED");
+    assert_eq!(json["playback"]["auto_repeat_mode"], "NONE");
+    assert_eq!(json["playback"]["active_controls"]["is_play_enabled"], true);
+    assert_eq!(json["duration_ms"], 200_000);
+    assert!(json.get("art").is_none());
+
+    let parsed: MediaState = serde_json::from_value(json.clone()).unwrap();
+    assert_eq!(parsed.timeline.last_updated_time, clock.now());
+    assert_eq!(parsed.timeline.endtime, Duration::from_secs(200));
+    assert_eq!(parsed.media.playback_type, PlaybackType::Music);
+    assert_eq!(parsed.playback.playback_status, PlaybackStatus::Paused);
+    assert_eq!(parsed.playback.auto_repeat_mode, RepeatMode::None);
+    assert_eq!(serde_json::to_value(&parsed).unwrap(), json);
+}
+
+#[test]
+fn rejects_malformed_timestamps() {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    let state = MediaState::read(&*session, &SystemClock).unwrap();
+
+    let mut json = serde_json::to_value(&state).unwrap();
+    for timestamp in ["2022-05-28 14:03:07Z", "2022-13-28T14:03:07Z", "yesterday"] {
+        json["timeline"]["last_updated"] = timestamp.into();
+        assert!(serde_json::from_value::<MediaState>(json.clone()).is_err());
+    }
+
+    // The fraction of a second is optional
+    json["timeline"]["last_updated"] = "2022-05-28T14:03:07Z".into();
+    let parsed: MediaState = serde_json::from_value(json).unwrap();
+    assert_eq!(
+        parsed.timeline.last_updated_time,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1_653_746_587)
+    );
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, SystemTime};
+
+use window::media::{FakeBackend, ManualClock, MediaBackend, MediaState, Template};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+/// State of the first track, 83s in
+fn state() -> MediaState {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let session = backend.current_session().unwrap();
+    session.pause().unwrap();
+    session.set_position(Duration::from_secs(83)).unwrap();
+
+    let clock = ManualClock::new(SystemTime::UNIX_EPOCH + Duration::from_secs(1_653_746_587));
+    MediaState::read(&*session, &clock).unwrap()
+}
+
+fn render(template: &str, state: &MediaState) -> String {
+    template.parse::<Template>().unwrap().render(state)
+}
+
+#[test]
+fn fills_in_fields() {
+    let state = state();
+
+    assert_eq!(
+        render("{{artist}} - {{title}} [{{position}}/{{duration}}]", &state),
+        "Some Artist - First Song [1:23/3:20]"
+    );
+    assert_eq!(
+        render(
+            "{{media.title}} on {{album}} by {{session}}: {{status}}",
+            &state
+        ),
+        "First Song on Some Album by demo-player: PAUSED"
+    );
+    assert_eq!(
+        render(
+            "{{position_ms}} {{duration_ms}} {{finished_percentage}} {{shuffle_active}} {{playback_rate}}",
+            &state
+        ),
+        "83000 200000 42 false 1"
+    );
+    assert_eq!(render("{{remaining}} left", &state), "1:57 left");
+    assert_eq!(render("[{{no_such_field}}]", &state), "[]");
+}
+
+#[test]
+fn applies_filters() {
+    let mut state = state();
+    state.media.title = "Don't <Stop> & \"Go\"".to_string();
+
+    assert_eq!(render("{{position | secs}}", &state), "83");
+    assert_eq!(render("{{position | ms}}", &state), "83000");
+    assert_eq!(render("{{duration_ms | clock}}", &state), "3:20");
+    assert_eq!(render("{{status | lower}}", &state), "paused");
+    assert_eq!(render("{{artist | upper}}", &state), "SOME ARTIST");
+    assert_eq!(render("{{artist | trunc 6}}", &state), "Some …");
+    assert_eq!(render("{{artist | trunc 20}}", &state), "Some Artist");
+    assert_eq!(
+        render("{{title | shell}}", &state),
+        r#"'Don'\''t <Stop> & "Go"'"#
+    );
+    assert_eq!(
+        render("{{title | json}}", &state),
+        r#""Don't <Stop> & \"Go\"""#
+    );
+    assert_eq!(
+        render("{{title | markup}}", &state),
+        "Don&apos;t &lt;Stop&gt; &amp; &quot;Go&quot;"
+    );
+    assert_eq!(
+        render(r#"{{subtitle | default "none" | upper}}"#, &state),
+        "NONE"
+    );
+    assert_eq!(render(r#"{{"{{" | default "x"}}"#, &state), "{{");
+
+    state.timeline.pos = Duration::from_secs(3723);
+    assert_eq!(render("{{position}}", &state), "1:02:03");
+}
+
+#[test]
+fn conditionals_check_for_missing_fields() {
+    let mut state = state();
+    let template = "{{#if subtitle}}{{subtitle}}{{else}}{{title}}{{/if}}\
+        {{#unless live}} ({{duration}}){{/unless}}";
+
+    assert_eq!(render(template, &state), "First Song (3:20)");
+
+    state.media.subtitle = "Live at Home".to_string();
+    state.timeline.endtime = Duration::ZERO;
+    state.timeline.max_seek_time = Duration::ZERO;
+    state.live = true;
+    assert_eq!(render(template, &state), "Live at Home");
+    assert_eq!(render("[{{duration}}]", &state), "[]");
+}
+
+#[test]
+fn rejects_malformed_templates() {
+    for template in [
+        "{{title",
+        "{{#if title}}",
+        "{{/if}}",
+        "{{else}}",
+        "{{#if title}}{{/unless}}",
+        "{{title | reverse}}",
+        "{{title | trunc}}",
+        "{{title | trunc many}}",
+        "{{title | upper 3}}",
+        "{{title | default none}}",
+        "{{some title}}",
+    ] {
+        assert!(
+            template.parse::<Template>().is_err(),
+            "{} should be rejected",
+            template
+        );
+    }
+}
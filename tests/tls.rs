@@ -0,0 +1,338 @@
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{ring, CryptoProvider},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned,
+};
+use tungstenite::Message;
+use window::{
+    controller::Thread,
+    media::FakeBackend,
+    server::{fingerprint, Certificates, Server},
+};
+
+const SCRIPT: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/fake_session.json"
+);
+
+/// Trusts the one certificate with the fingerprint, as paired devices do
+#[derive(Debug)]
+struct Pinned(String);
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _: &[CertificateDer<'_>],
+        _: &ServerName<'_>,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity) == self.0 {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("not the pinned certificate".into()))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &provider().signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &provider().signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+fn provider() -> CryptoProvider {
+    ring::default_provider()
+}
+
+type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Connects to the server, trusting only the certificate with `fingerprint`
+fn connect(addr: SocketAddr, fingerprint: &str) -> TlsStream {
+    let config = ClientConfig::builder_with_provider(Arc::new(provider()))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(Pinned(fingerprint.to_string())))
+        .with_no_client_auth();
+    let connection =
+        ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+
+    StreamOwned::new(connection, TcpStream::connect(addr).unwrap())
+}
+
+/// Sends a request with `body` over TLS, as the device with `token` if any,
+/// and returns the status and JSON body of the answer
+fn send(
+    addr: SocketAddr,
+    fingerprint: &str,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: &str,
+) -> io::Result<(u16, serde_json::Value)> {
+    let mut stream = connect(addr, fingerprint);
+    let authorization = token
+        .map(|token| format!("Authorization: Bearer {}\r\n", token))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        addr,
+        authorization,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+    Ok((status, serde_json::from_str(body).unwrap()))
+}
+
+fn request(addr: SocketAddr, fingerprint: &str, path: &str) -> io::Result<serde_json::Value> {
+    let (status, body) = send(addr, fingerprint, "GET", path, None, "")?;
+    assert_eq!(status, 200, "{}", body);
+
+    Ok(body)
+}
+
+/// Serves a fake session with the certificates in `path` until the returned
+/// thread is stopped
+fn serve(path: &PathBuf) -> (SocketAddr, Thread) {
+    let backend = FakeBackend::from_file(SCRIPT).unwrap();
+    let server = Server::bind("127.0.0.1:0")
+        .unwrap()
+        .tls(Certificates::open(path).unwrap());
+    let addr = server.local_addr().unwrap();
+    let thread = Thread::new(move |rx| server.run(&backend, rx));
+
+    (addr, thread)
+}
+
+/// An empty directory for the test called `name`
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("window-tls-{}-{}", std::process::id(), name));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn serves_over_tls_with_the_pinned_certificate() {
+    let path = temp_dir("serves").join("certificates.json");
+    let generated = Certificates::open(&path).unwrap().status().clone();
+    assert_eq!(generated.fingerprint.len(), 64);
+    assert!(generated.next.is_none());
+    // Kept rather than generated again
+    assert_eq!(
+        Certificates::open(&path).unwrap().status().fingerprint,
+        generated.fingerprint
+    );
+    let (addr, thread) = serve(&path);
+    let pinned = generated.fingerprint.as_str();
+
+    assert_eq!(
+        request(addr, pinned, "/current").unwrap()["media"]["title"],
+        "First Song"
+    );
+    let status = request(addr, pinned, "/certificate").unwrap();
+    assert_eq!(status["fingerprint"], pinned);
+    assert_eq!(status["next"], serde_json::Value::Null);
+
+    // Clients pinning another certificate don't get through
+    assert!(request(addr, &"00".repeat(32), "/current").is_err());
+
+    // Nor do clients without TLS
+    let mut plain = TcpStream::connect(addr).unwrap();
+    write!(plain, "GET /current HTTP/1.1\r\nHost: {}\r\n\r\n", addr).unwrap();
+    let mut response = vec![];
+    plain.read_to_end(&mut response).ok();
+    assert!(!response.starts_with(b"HTTP/1.1 200"));
+
+    let (mut socket, _) =
+        tungstenite::client(format!("wss://{}/events", addr), connect(addr, pinned)).unwrap();
+    match socket.read().unwrap() {
+        Message::Text(text) => {
+            let snapshot: serde_json::Value = serde_json::from_str(&text).unwrap();
+            assert_eq!(snapshot["type"], "snapshot");
+        }
+        message => panic!("expected a snapshot, got {:?}", message),
+    }
+
+    thread.stop();
+}
+
+#[test]
+fn rotates_certificates_after_the_grace_period() {
+    let path = temp_dir("rotates").join("certificates.json");
+    let old = Certificates::open(&path)
+        .unwrap()
+        .status()
+        .fingerprint
+        .clone();
+    let (addr, thread) = serve(&path);
+
+    // The next certificate is announced but not used yet
+    let mut certificates = Certificates::open(&path).unwrap();
+    let rotating = certificates
+        .rotate(Duration::from_secs(3600))
+        .unwrap()
+        .clone();
+    assert_eq!(rotating.fingerprint, old);
+    let next = rotating.next.unwrap().fingerprint;
+    assert_ne!(next, old);
+    let status = request(addr, &old, "/certificate").unwrap();
+    assert_eq!(status["next"]["fingerprint"], next.as_str());
+    assert!(request(addr, &next, "/current").is_err());
+
+    // Without a grace period the new certificate is used right away
+    let rotated = certificates.rotate(Duration::ZERO).unwrap().clone();
+    assert!(rotated.next.is_none());
+    assert_ne!(rotated.fingerprint, old);
+    assert!(request(addr, &rotated.fingerprint, "/current").is_ok());
+    assert!(request(addr, &old, "/current").is_err());
+
+    // Once the grace period is over, opening switches
+    let pending = certificates
+        .rotate(Duration::from_millis(10))
+        .unwrap()
+        .clone();
+    std::thread::sleep(Duration::from_millis(50));
+    let switched = Certificates::open(&path).unwrap().status().clone();
+    assert_eq!(switched.fingerprint, pending.next.unwrap().fingerprint);
+    assert!(switched.next.is_none());
+
+    thread.stop();
+}
+
+#[test]
+fn cli_shows_and_rotates_certificates() {
+    use std::process::Command;
+
+    let dir = temp_dir("cli");
+    let window = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_window"))
+            .arg("--config-dir")
+            .arg(&dir)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let json = |args: &[&str]| -> serde_json::Value {
+        serde_json::from_str(&window(&[&["--json"], args].concat())).unwrap()
+    };
+
+    let shown = json(&["cert", "show"]);
+    let fingerprint = shown["fingerprint"].as_str().unwrap().to_string();
+    assert!(dir.join("certificates.json").exists());
+    assert!(
+        window(&["cert", "show"]).starts_with(&format!("Fingerprint: {} (created ", fingerprint))
+    );
+
+    let rotating = json(&["cert", "rotate", "--grace", "1h"]);
+    assert_eq!(rotating["fingerprint"], fingerprint.as_str());
+    let next = rotating["next"]["fingerprint"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(window(&["cert", "show"]).contains(&format!("\nNext: {} (from ", next)));
+
+    let rotated = json(&["cert", "rotate", "--grace", "0s"]);
+    assert_ne!(rotated["fingerprint"], fingerprint.as_str());
+    assert_ne!(rotated["fingerprint"], next.as_str());
+    assert_eq!(rotated["next"], serde_json::Value::Null);
+}
+
+#[test]
+#[cfg(unix)]
+fn cli_serves_over_tls() {
+    use std::{
+        io::BufRead,
+        process::{Command, Stdio},
+    };
+
+    let mut serve = Command::new(env!("CARGO_BIN_EXE_window"))
+        .arg("--config-dir")
+        .arg(temp_dir("serve"))
+        .args(["--fake-session", SCRIPT, "serve", "--bind", "127.0.0.1:0"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    // Kept open until the end, writing to a closed stderr would panic
+    let mut stderr = io::BufReader::new(serve.stderr.take().unwrap()).lines();
+    let mut next_line = |prefix: &str| {
+        stderr
+            .find_map(|line| line.unwrap().strip_prefix(prefix).map(str::to_string))
+            .unwrap()
+    };
+    let addr: SocketAddr = next_line("[Server] Listening on https://").parse().unwrap();
+    let fingerprint = next_line("[Server] Certificate fingerprint ");
+    let pin = next_line("[Server] Pair a device with PIN ")[..6].to_string();
+    let uri = next_line("window://pair?");
+    assert!(
+        uri.ends_with(&format!("&pin={}&fp={}", pin, fingerprint)),
+        "{}",
+        uri
+    );
+
+    let body = serde_json::json!({ "pin": pin, "name": "Phone" }).to_string();
+    let (status, paired) = send(addr, &fingerprint, "POST", "/pair", None, &body).unwrap();
+    assert_eq!(status, 200);
+    let token = paired["token"].as_str();
+    let (status, current) = send(addr, &fingerprint, "GET", "/current", token, "").unwrap();
+    assert_eq!(status, 200);
+    assert_eq!(current["media"]["title"], "First Song");
+
+    Command::new("kill")
+        .args(["-INT", &serve.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(serve.wait().unwrap().success());
+}